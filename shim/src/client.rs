@@ -27,6 +27,8 @@ use containerd_shim::{
 use tokio::{net::UnixStream, time::timeout};
 
 pub const HVSOCK_PREFIX: &str = "hvsock://";
+// The same as TASK_EVENT_PORT of vmm-common, the port guest agent serves the task events on
+pub const TASK_EVENT_PORT: u32 = 1027;
 
 pub(crate) fn uds_task_client(address: &str) -> Result<TaskClient> {
     let client = Client::connect(address)?;
@@ -35,18 +37,7 @@ pub(crate) fn uds_task_client(address: &str) -> Result<TaskClient> {
 
 // Client to connect vm hvsock, used to call task service
 pub(crate) async fn vsock_task_client(address: &str) -> Result<TaskClient> {
-    let (addr, port) = match address.strip_prefix(HVSOCK_PREFIX) {
-        None => {
-            return Err(other!("task address {} should have prefix hvsock", address));
-        }
-        Some(address) => {
-            let v: Vec<&str> = address.split(':').collect();
-            if v.len() < 2 {
-                return Err(other!("hvsock address {} should not less than 2", address));
-            }
-            (v[0], v[1])
-        }
-    };
+    let (addr, port) = parse_hvsock_address(address)?;
 
     let ctx_timeout = 2;
     let mut last_err = other!("");
@@ -69,6 +60,25 @@ pub(crate) async fn vsock_task_client(address: &str) -> Result<TaskClient> {
     Ok(TaskClient::new(client))
 }
 
+// Connection to the vm hvsock on the event port, used to receive task events
+pub(crate) async fn vsock_event_stream(address: &str) -> Result<RawFd> {
+    let (addr, _) = parse_hvsock_address(address)?;
+    connect_to_hvsocket(addr, &TASK_EVENT_PORT.to_string()).await
+}
+
+fn parse_hvsock_address(address: &str) -> Result<(&str, &str)> {
+    match address.strip_prefix(HVSOCK_PREFIX) {
+        None => Err(other!("task address {} should have prefix hvsock", address)),
+        Some(address) => {
+            let v: Vec<&str> = address.split(':').collect();
+            if v.len() < 2 {
+                return Err(other!("hvsock address {} should not less than 2", address));
+            }
+            Ok((v[0], v[1]))
+        }
+    }
+}
+
 async fn connect_to_hvsocket(addr: &str, port: &str) -> Result<RawFd> {
     loop {
         let stream = UnixStream::connect(addr)
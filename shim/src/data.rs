@@ -44,6 +44,8 @@ impl<T> ProcessData<T> {
 pub struct ContainerData<T> {
     pub id: String,
     pub io: T,
+    // host path of the bundle prepared by the sandbox, which is in the shared dir of the sandbox
+    pub bundle: String,
     pub processes: Vec<ProcessData<T>>,
 }
 
@@ -52,6 +54,7 @@ impl<T: ContainerIoTransport> ContainerData<T> {
         Self {
             id: id.to_string(),
             io,
+            bundle: String::new(),
             processes: vec![],
         }
     }
@@ -0,0 +1,101 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::UnixStream,
+    },
+    sync::Arc,
+};
+
+use containerd_shim::{
+    protos::{
+        protobuf::{CodedInputStream, MessageField},
+        shim::events::{Envelope, ForwardRequest},
+        shim_async::Events,
+    },
+    publisher::RemotePublisher,
+    TtrpcContext,
+};
+use log::{debug, warn};
+use tokio::sync::mpsc::{channel, Sender};
+
+use crate::io::ContainerIoTransport;
+
+/// Receive the task events published in the vm and forward them to containerd,
+/// the namespace of the events is replaced with the one of the shim.
+pub(crate) async fn forward_events<T: ContainerIoTransport>(
+    task_addr: String,
+    namespace: String,
+    publisher: Arc<RemotePublisher>,
+) {
+    let fd = match T::new_event_stream(&task_addr).await {
+        Ok(Some(fd)) => fd,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("failed to connect to event stream of {}: {}", task_addr, e);
+            return;
+        }
+    };
+    let (tx, mut rx) = channel(128);
+    tokio::task::spawn_blocking(move || read_events(fd, tx));
+
+    let ctx = TtrpcContext {
+        fd: 0,
+        mh: Default::default(),
+        metadata: Default::default(),
+        timeout_nano: 0,
+    };
+    while let Some(mut envelope) = rx.recv().await {
+        envelope.namespace = namespace.clone();
+        let topic = envelope.topic.clone();
+        debug!("forward event {} of vm", topic);
+        let req = ForwardRequest {
+            envelope: MessageField::some(envelope),
+            ..Default::default()
+        };
+        if let Err(e) = publisher.forward(&ctx, req).await {
+            warn!("failed to publish event {}: {}", topic, e);
+        }
+    }
+}
+
+// the envelopes are length delimited on the stream, which ends when the vm exits
+fn read_events(fd: RawFd, tx: Sender<Envelope>) {
+    let mut stream = unsafe { UnixStream::from_raw_fd(fd) };
+    if let Err(e) = stream.set_nonblocking(false) {
+        warn!("failed to set event stream blocking: {}", e);
+        return;
+    }
+    let mut input = CodedInputStream::new(&mut stream);
+    loop {
+        match input.eof() {
+            Ok(false) => {}
+            _ => return,
+        }
+        let envelope: Envelope = match input.read_message() {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("failed to read event from vm: {}", e);
+                return;
+            }
+        };
+        if tx.blocking_send(envelope).is_err() {
+            return;
+        }
+    }
+}
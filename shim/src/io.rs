@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashSet, str::FromStr};
+use std::{collections::HashSet, os::unix::io::RawFd, str::FromStr};
 
 use async_trait::async_trait;
 use containerd_shim::{
@@ -33,7 +33,7 @@ use tokio::{
 };
 
 use crate::{
-    client::{uds_task_client, vsock_task_client, HVSOCK_PREFIX},
+    client::{uds_task_client, vsock_event_stream, vsock_task_client, HVSOCK_PREFIX},
     sandbox::{VMM_SANDBOXER_SOCKET_PATH, WASM_SANDBOXER_SOCKET_PATH},
 };
 
@@ -163,6 +163,8 @@ pub trait ContainerIoTransport: Sync + Send + Default + Clone {
 
     async fn new_task_client(address: &str) -> Result<TaskClient>;
 
+    async fn new_event_stream(address: &str) -> Result<Option<RawFd>>;
+
     async fn cleanup_connection(self);
 }
 
@@ -226,6 +228,11 @@ impl ContainerIoTransport for UdsTransport {
         uds_task_client(address)
     }
 
+    // task events are not forwarded for the sandboxes served over uds
+    async fn new_event_stream(_address: &str) -> Result<Option<RawFd>> {
+        Ok(None)
+    }
+
     async fn cleanup_connection(self) {}
 }
 #[async_trait]
@@ -351,6 +358,10 @@ impl ContainerIoTransport for VSockTransport {
         vsock_task_client(address).await
     }
 
+    async fn new_event_stream(address: &str) -> Result<Option<RawFd>> {
+        vsock_event_stream(address).await.map(Some)
+    }
+
     async fn cleanup_connection(self) {
         Self::remove_port(self).await;
     }
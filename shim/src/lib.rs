@@ -13,6 +13,7 @@
 
 mod client;
 mod data;
+mod events;
 pub mod io;
 mod sandbox;
 pub mod service;
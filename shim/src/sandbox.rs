@@ -201,7 +201,8 @@ where
 
         // Append container data to sandbox structure.
         let mut sandbox_guard = self.data.lock().await;
-        let container_data = ContainerData::new(&id, shim_io.clone());
+        let mut container_data = ContainerData::new(&id, shim_io.clone());
+        container_data.bundle = resp_v2.get_ref().bundle.clone();
         sandbox_guard.add_container_data(container_data);
         Ok(resp_v2)
     }
@@ -41,7 +41,8 @@ use tonic::transport::{Endpoint, Uri};
 use tower::service_fn;
 
 use crate::{
-    data::SandboxData, io::ContainerIoTransport, sandbox::SandboxHandler, task::TaskHandler,
+    data::SandboxData, events::forward_events, io::ContainerIoTransport, sandbox::SandboxHandler,
+    task::TaskHandler,
 };
 
 pub struct Service<T> {
@@ -55,10 +56,10 @@ where
 {
     type T = KuasarServer<Transport>;
 
-    async fn new(_runtime_id: &str, id: &str, _namespace: &str, _config: &mut Config) -> Self {
+    async fn new(_runtime_id: &str, id: &str, namespace: &str, _config: &mut Config) -> Self {
         let exit = Arc::new(ExitSignal::default());
         Self {
-            kuasar_server: Box::new(KuasarServer::new(id, exit).await),
+            kuasar_server: Box::new(KuasarServer::new(id, namespace, exit).await),
         }
     }
 
@@ -117,7 +118,8 @@ where
         self.kuasar_server.exit.wait().await;
     }
 
-    async fn create_task_service(&self, _publisher: RemotePublisher) -> Self::T {
+    async fn create_task_service(&self, publisher: RemotePublisher) -> Self::T {
+        *self.kuasar_server.task.publisher.write().await = Some(Arc::new(publisher));
         *self.kuasar_server.clone()
     }
 
@@ -136,7 +138,7 @@ pub struct KuasarServer<T> {
 }
 
 impl<T: ContainerIoTransport> KuasarServer<T> {
-    pub async fn new(id: &str, exit: Arc<ExitSignal>) -> Self {
+    pub async fn new(id: &str, namespace: &str, exit: Arc<ExitSignal>) -> Self {
         let channel = Endpoint::from_static("https://www.kuasar.io")
             .connect_with_connector(service_fn(
                 |_: Uri| UnixStream::connect(T::sandboxer_addr()),
@@ -153,6 +155,8 @@ impl<T: ContainerIoTransport> KuasarServer<T> {
             task: TaskHandler {
                 task_cli: Arc::new(Mutex::new(None)),
                 task_addr: Arc::new(RwLock::new("".to_string())),
+                namespace: namespace.to_string(),
+                publisher: Arc::new(RwLock::new(None)),
             },
             exit,
         }
@@ -167,6 +171,14 @@ impl<T: ContainerIoTransport> KuasarServer<T> {
         let client = T::new_task_client(task_addr).await?;
         *task_guard = Some(client);
 
+        if let Some(publisher) = self.task.publisher.read().await.clone() {
+            tokio::spawn(forward_events::<T>(
+                task_addr.to_string(),
+                self.task.namespace.clone(),
+                publisher,
+            ));
+        }
+
         Ok(())
     }
 }
@@ -14,25 +14,34 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 use containerd_shim::{
     api::*,
-    error::Error,
-    other,
+    error::{Error, Result},
+    other, other_error,
     protos::{shim_async::TaskClient, ttrpc::context::with_timeout},
+    publisher::RemotePublisher,
     DeleteResponse, Task, TtrpcContext, TtrpcResult,
 };
 use tokio::sync::{Mutex, RwLock};
 
 use crate::{io::ContainerIoTransport, service::KuasarServer};
 
+const CHECKPOINT_DIR: &str = "checkpoints";
+
 // cheap to clone
 #[derive(Clone)]
 pub struct TaskHandler {
     pub task_cli: Arc<Mutex<Option<TaskClient>>>,
     pub task_addr: Arc<RwLock<String>>,
+    pub namespace: String,
+    // set when the task service is created, used to forward the task events of the vm
+    pub publisher: Arc<RwLock<Option<Arc<RemotePublisher>>>>,
 }
 
 impl TaskHandler {
@@ -256,6 +265,12 @@ where
 
         if !prepare_res.bundle.is_empty() {
             req_new.bundle = prepare_res.bundle.clone();
+            // The guest restores the container from the checkpoint dir in the shared dir,
+            // so copy the checkpoint image from containerd into it.
+            if !req.checkpoint.is_empty() {
+                let checkpoint_path = checkpoint_path(&prepare_res.bundle, &req.id)?;
+                copy_dir(PathBuf::from(&req.checkpoint), checkpoint_path).await?;
+            }
         }
 
         let res = self.task.create(ctx, req_new).await?;
@@ -315,7 +330,25 @@ where
         ctx: &TtrpcContext,
         req: CheckpointTaskRequest,
     ) -> TtrpcResult<Empty> {
-        self.task.checkpoint(ctx, req).await
+        let bundle = self
+            .sandbox
+            .data
+            .lock()
+            .await
+            .get_container_data(&req.id)?
+            .bundle
+            .clone();
+        let res = self.task.checkpoint(ctx, req.clone()).await?;
+        // The guest writes the checkpoint image to the checkpoint dir in the shared dir,
+        // move it to the path from containerd.
+        if !req.path.is_empty() && !bundle.is_empty() {
+            let checkpoint_path = checkpoint_path(&bundle, &req.id)?;
+            copy_dir(checkpoint_path.clone(), PathBuf::from(&req.path)).await?;
+            tokio::fs::remove_dir_all(&checkpoint_path)
+                .await
+                .map_err(other_error!(e, "remove checkpoint dir"))?;
+        }
+        Ok(res)
     }
 
     async fn kill(&self, ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
@@ -380,3 +413,33 @@ where
         self.task.shutdown(ctx, req).await
     }
 }
+
+// The same path as `KuasarSandbox::get_checkpoint_path` of the sandboxer,
+// the bundle of the container is in the shared dir of the sandbox.
+fn checkpoint_path(bundle: &str, id: &str) -> Result<PathBuf> {
+    let shared_dir = Path::new(bundle)
+        .parent()
+        .ok_or_else(|| other!("no shared dir of bundle {}", bundle))?;
+    Ok(shared_dir.join(CHECKPOINT_DIR).join(id))
+}
+
+async fn copy_dir(src: PathBuf, dst: PathBuf) -> Result<()> {
+    tokio::task::spawn_blocking(move || copy_dir_all(&src, &dst))
+        .await
+        .map_err(other_error!(e, "join copy dir task"))?
+        .map_err(other_error!(e, "copy checkpoint dir"))
+}
+
+fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
@@ -19,6 +19,7 @@ use ttrpc_codegen::{Codegen, Customize, ProtobufCustomize};
 fn main() {
     let protos = [
         "src/protos/sandbox.proto",
+        "src/protos/sandboxer.proto",
         "src/protos/google/protobuf/empty.proto",
     ];
 
@@ -17,3 +17,5 @@ limitations under the License.
 pub mod empty;
 pub mod sandbox;
 pub mod sandbox_ttrpc;
+pub mod sandboxer;
+pub mod sandboxer_ttrpc;
@@ -25,6 +25,7 @@ pub const KUASAR_STATE_DIR: &str = "/run/kuasar/state";
 pub const IO_FILE_PREFIX: &str = "io";
 pub const STORAGE_FILE_PREFIX: &str = "storage";
 pub const SHARED_DIR_SUFFIX: &str = "shared";
+pub const CHECKPOINT_DIR: &str = "checkpoints";
 
 pub const ETC_HOSTS: &str = "/etc/hosts";
 pub const ETC_HOSTNAME: &str = "/etc/hostname";
@@ -38,4 +39,24 @@ pub const SANDBOX_NS_PATH: &str = "/run/sandbox-ns";
 pub const NET_NAMESPACE: &str = "net";
 pub const IPC_NAMESPACE: &str = "ipc";
 pub const UTS_NAMESPACE: &str = "uts";
+pub const PID_NAMESPACE: &str = "pid";
 pub const CGROUP_NAMESPACE: &str = "cgroup";
+
+/// Vsock port that the guest agent serves its structured logs on
+pub const TASK_LOG_PORT: u32 = 1026;
+/// Vsock port that the guest agent serves the task events on
+pub const TASK_EVENT_PORT: u32 = 1027;
+
+/// A parameter of a kernel module should be in the form of `key=value`, and the value should not
+/// start with `-`, so that it can not be taken as an option of modprobe.
+pub fn is_valid_module_parameter(param: &str) -> bool {
+    let (key, value) = match param.split_once('=') {
+        Some(kv) => kv,
+        None => return false,
+    };
+    !key.is_empty()
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !value.is_empty()
+        && !value.starts_with('-')
+        && !value.chars().any(char::is_whitespace)
+}
@@ -29,6 +29,15 @@ pub const DRIVERSCSITYPE: &str = "scsi";
 pub const DRIVERNVDIMMTYPE: &str = "nvdimm";
 pub const DRIVEREPHEMERALTYPE: &str = "ephemeral";
 pub const DRIVERLOCALTYPE: &str = "local";
+pub const DRIVEROVERLAYFSTYPE: &str = "overlayfs";
+
+/// The driver option of a storage assembled in the guest, refers to the storage it is on,
+/// such as the layers of an overlay, or the scratch disk of a local storage.
+pub const LAYER_OPTION_PREFIX: &str = "layer=";
+
+/// Dir in the guest that the dirs of local storages are created in, the rootfs of the guest is
+/// readonly, so it is in the writable /run, the scratch disk of a local storage is mounted in it.
+pub const LOCAL_STORAGE_DIR: &str = "/run/kuasar/local";
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Storage {
@@ -44,11 +53,14 @@ pub struct Storage {
     pub fstype: String,
     pub options: Vec<String>,
     pub mount_point: String,
+    /// options of the mount in the shared dir on host, to mount it again if it is gone
+    #[serde(default)]
+    pub host_options: Vec<String>,
 }
 
 impl Storage {
     pub fn is_for_mount(&self, m: &Mount) -> bool {
-        self.host_source == m.source && self.r#type == m.r#type
+        self.host_source == mount_source(m) && self.r#type == m.r#type
     }
 
     pub fn ref_count(&self) -> u32 {
@@ -68,3 +80,19 @@ impl Storage {
         self.ref_container.remove(container_id);
     }
 }
+
+/// The source of overlay mounts is always "overlay", so the upperdir, or the lowerdir if it is
+/// a readonly overlay, is taken as the source to tell the overlay mounts apart.
+pub fn mount_source(m: &Mount) -> String {
+    if m.r#type != "overlay" {
+        return m.source.to_string();
+    }
+    let option = |key: &str| {
+        m.options
+            .iter()
+            .find_map(|o| o.strip_prefix(key).map(|x| x.to_string()))
+    };
+    option("upperdir=")
+        .or_else(|| option("lowerdir="))
+        .unwrap_or_else(|| m.source.to_string())
+}
@@ -0,0 +1,464 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use containerd_sandbox::{
+    data::SandboxData,
+    error::{Error, Result},
+};
+use vmm_common::{api::sandbox::KernelModule, is_valid_module_parameter};
+
+use crate::{
+    sandbox::SandboxConfig,
+    utils::{get_host_memory_in_mb, get_resources},
+    vm::{BlockDriver, IoLimits},
+};
+
+pub(crate) const ANNOTATION_PREFIX: &str = "io.kuasar.hypervisor.";
+pub(crate) const ANNOTATION_KEY_KERNEL_PARAMS: &str = "io.kuasar.hypervisor.kernel_params";
+pub(crate) const ANNOTATION_KEY_IMAGE: &str = "io.kuasar.hypervisor.image";
+pub(crate) const ANNOTATION_KEY_VCPUS: &str = "io.kuasar.hypervisor.default_vcpus";
+pub(crate) const ANNOTATION_KEY_MEMORY: &str = "io.kuasar.hypervisor.default_memory";
+pub(crate) const ANNOTATION_KEY_HUGEPAGES: &str = "io.kuasar.hypervisor.enable_hugepages";
+pub(crate) const ANNOTATION_KEY_BLOCK_DRIVER: &str = "io.kuasar.hypervisor.block_device_driver";
+pub(crate) const ANNOTATION_KEY_DEBUG: &str = "io.kuasar.hypervisor.enable_debug";
+pub(crate) const ANNOTATION_KEY_DISK_BW: &str =
+    "io.kuasar.hypervisor.disk_rate_limiter_bw_max_rate";
+pub(crate) const ANNOTATION_KEY_DISK_OPS: &str =
+    "io.kuasar.hypervisor.disk_rate_limiter_ops_max_rate";
+pub(crate) const ANNOTATION_KEY_NET_BW: &str = "io.kuasar.hypervisor.net_rate_limiter_bw_max_rate";
+pub(crate) const ANNOTATION_KEY_NET_OPS: &str =
+    "io.kuasar.hypervisor.net_rate_limiter_ops_max_rate";
+
+/// Kernel modules to load in the guest, a ";" separated list of the module name followed by
+/// its parameters, like "nf_conntrack hashsize=4096;tun"
+pub(crate) const ANNOTATION_KEY_KERNEL_MODULES: &str = "io.kuasar.kernel_modules";
+
+// the cgroup v2 io limits of the pod, like "8:16 rbps=2097152 wbps=max riops=1000"
+const UNIFIED_KEY_IO_MAX: &str = "io.max";
+
+pub(crate) const DEBUG_KERNEL_PARAMS: &str = "debug task.debug task.log_level=debug";
+
+/// Hypervisor overrides of a pod, set by the "io.kuasar.hypervisor.*" annotations.
+#[derive(Debug, Default)]
+pub struct HypervisorAnnotations {
+    pub kernel_params: Option<String>,
+    pub image: Option<String>,
+    pub vcpus: Option<u32>,
+    pub memory_in_mb: Option<u32>,
+    pub hugepages: Option<bool>,
+    pub block_driver: Option<BlockDriver>,
+    pub debug: Option<bool>,
+    pub disk_bandwidth: Option<u64>,
+    pub disk_ops: Option<u64>,
+    pub net_bandwidth: Option<u64>,
+    pub net_ops: Option<u64>,
+}
+
+impl HypervisorAnnotations {
+    pub fn parse(data: &SandboxData) -> Result<Self> {
+        let mut res = Self::default();
+        let annotations = match data.config.as_ref() {
+            None => return Ok(res),
+            Some(c) => &c.annotations,
+        };
+        for (k, v) in annotations
+            .iter()
+            .filter(|(k, _)| k.starts_with(ANNOTATION_PREFIX))
+        {
+            match k.as_str() {
+                ANNOTATION_KEY_KERNEL_PARAMS => {
+                    res.kernel_params = Some(parse_kernel_params(k, v)?)
+                }
+                ANNOTATION_KEY_IMAGE => res.image = Some(v.to_string()),
+                ANNOTATION_KEY_VCPUS => res.vcpus = Some(parse_positive(k, v)?),
+                ANNOTATION_KEY_MEMORY => res.memory_in_mb = Some(parse_positive(k, v)?),
+                ANNOTATION_KEY_HUGEPAGES => res.hugepages = Some(parse_bool(k, v)?),
+                ANNOTATION_KEY_BLOCK_DRIVER => res.block_driver = Some(parse_block_driver(k, v)?),
+                ANNOTATION_KEY_DEBUG => res.debug = Some(parse_bool(k, v)?),
+                ANNOTATION_KEY_DISK_BW => res.disk_bandwidth = Some(parse_u64(k, v)?),
+                ANNOTATION_KEY_DISK_OPS => res.disk_ops = Some(parse_u64(k, v)?),
+                ANNOTATION_KEY_NET_BW => res.net_bandwidth = Some(parse_u64(k, v)?),
+                ANNOTATION_KEY_NET_OPS => res.net_ops = Some(parse_u64(k, v)?),
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "annotation {} is not supported",
+                        k
+                    )))
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// The image can only be chosen from the image paths allowed by the sandboxer config,
+    /// the kernel params and the debug mode can only be set if they are allowed by it,
+    /// and the memory can not be larger than the memory of the host.
+    pub async fn validate(&self, config: &SandboxConfig) -> Result<()> {
+        let allowed = |key: &str| config.allowed_annotations.iter().any(|a| a == key);
+        if self.kernel_params.is_some() && !allowed(ANNOTATION_KEY_KERNEL_PARAMS) {
+            return Err(Error::InvalidArgument(format!(
+                "annotation {} is not allowed",
+                ANNOTATION_KEY_KERNEL_PARAMS
+            )));
+        }
+        if self.debug == Some(true) && !allowed(ANNOTATION_KEY_DEBUG) {
+            return Err(Error::InvalidArgument(format!(
+                "annotation {} is not allowed",
+                ANNOTATION_KEY_DEBUG
+            )));
+        }
+        if let Some(memory_in_mb) = self.memory_in_mb {
+            let host_memory_in_mb = get_host_memory_in_mb().await?;
+            if memory_in_mb as u64 > host_memory_in_mb {
+                return Err(Error::InvalidArgument(format!(
+                    "memory {}M of annotation {} is larger than the host memory {}M",
+                    memory_in_mb, ANNOTATION_KEY_MEMORY, host_memory_in_mb
+                )));
+            }
+        }
+        if let Some(image) = &self.image {
+            if !config.valid_image_paths.contains(image) {
+                return Err(Error::InvalidArgument(format!(
+                    "image {} of annotation {} is not allowed",
+                    image, ANNOTATION_KEY_IMAGE
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn append_kernel_params(&self, params: &mut String) {
+        let mut extra = vec![];
+        if let Some(p) = &self.kernel_params {
+            extra.push(p.as_str());
+        }
+        if let Some(true) = self.debug {
+            extra.push(DEBUG_KERNEL_PARAMS);
+        }
+        for p in extra.into_iter().filter(|p| !p.is_empty()) {
+            if !params.is_empty() {
+                params.push(' ');
+            }
+            params.push_str(p);
+        }
+    }
+}
+
+// the guest agent takes its settings, like the policy, from the "task." params, and the init
+// of the guest can be replaced by "init=" and "rdinit=", none of them can be set by a pod.
+fn parse_kernel_params(k: &str, v: &str) -> Result<String> {
+    for param in v.split_whitespace() {
+        let key = param.split_once('=').map(|(k, _)| k).unwrap_or(param);
+        if key.starts_with("task.") || key == "init" || key == "rdinit" || key == "--" {
+            return Err(Error::InvalidArgument(format!(
+                "kernel param {} of annotation {} is not allowed",
+                param, k
+            )));
+        }
+    }
+    Ok(v.to_string())
+}
+
+/// Get the io limits of the sandbox devices, the disk limits are taken from the "io.max" of the
+/// pod resources, and the annotations take precedence over it.
+pub fn get_io_limits(data: &SandboxData) -> Result<IoLimits> {
+    let mut limits = IoLimits::default();
+    if let Some(io_max) = get_resources(data).and_then(|r| r.unified.get(UNIFIED_KEY_IO_MAX)) {
+        for line in io_max.lines() {
+            // the limits are for the devices on the host, devices in the guest are different,
+            // so the lowest limit of all the host devices is taken.
+            for (k, v) in line
+                .split_whitespace()
+                .skip(1)
+                .filter_map(|x| x.split_once('='))
+            {
+                let v = match v.parse::<u64>() {
+                    Ok(v) if v > 0 => v,
+                    _ => continue,
+                };
+                let limit = match k {
+                    "rbps" | "wbps" => &mut limits.disk.bandwidth,
+                    "riops" | "wiops" => &mut limits.disk.ops,
+                    _ => continue,
+                };
+                if *limit == 0 || v < *limit {
+                    *limit = v;
+                }
+            }
+        }
+    }
+
+    let annotations = HypervisorAnnotations::parse(data)?;
+    if let Some(v) = annotations.disk_bandwidth {
+        limits.disk.bandwidth = v;
+    }
+    if let Some(v) = annotations.disk_ops {
+        limits.disk.ops = v;
+    }
+    if let Some(v) = annotations.net_bandwidth {
+        limits.net.bandwidth = v;
+    }
+    if let Some(v) = annotations.net_ops {
+        limits.net.ops = v;
+    }
+    Ok(limits)
+}
+
+/// Get the kernel modules of the "io.kuasar.kernel_modules" annotation,
+/// only the modules allowed by the sandboxer config can be loaded.
+pub fn get_kernel_modules(data: &SandboxData, allowed: &[String]) -> Result<Vec<KernelModule>> {
+    let value = match data
+        .config
+        .as_ref()
+        .and_then(|c| c.annotations.get(ANNOTATION_KEY_KERNEL_MODULES))
+    {
+        None => return Ok(vec![]),
+        Some(v) => v,
+    };
+    let mut modules = vec![];
+    for m in value.split(';') {
+        let mut fields = m.split_whitespace();
+        let name = match fields.next() {
+            None => continue,
+            Some(n) => n,
+        };
+        if !allowed.iter().any(|a| a == name) {
+            return Err(Error::InvalidArgument(format!(
+                "kernel module {} of annotation {} is not allowed",
+                name, ANNOTATION_KEY_KERNEL_MODULES
+            )));
+        }
+        let mut module = KernelModule::new();
+        module.name = name.to_string();
+        module.parameters = fields.map(|p| p.to_string()).collect();
+        if let Some(p) = module
+            .parameters
+            .iter()
+            .find(|p| !is_valid_module_parameter(p))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "invalid parameter {} of kernel module {} in annotation {}",
+                p, name, ANNOTATION_KEY_KERNEL_MODULES
+            )));
+        }
+        modules.push(module);
+    }
+    Ok(modules)
+}
+
+fn parse_u64(key: &str, value: &str) -> Result<u64> {
+    value.parse::<u64>().map_err(|_| {
+        Error::InvalidArgument(format!("invalid value {} of annotation {}", value, key))
+    })
+}
+
+fn parse_positive(key: &str, value: &str) -> Result<u32> {
+    match value.parse::<u32>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(Error::InvalidArgument(format!(
+            "invalid value {} of annotation {}",
+            value, key
+        ))),
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    value.parse::<bool>().map_err(|_| {
+        Error::InvalidArgument(format!("invalid value {} of annotation {}", value, key))
+    })
+}
+
+fn parse_block_driver(key: &str, value: &str) -> Result<BlockDriver> {
+    match value {
+        "virtio-blk" | "virtio-scsi" | "virtio-mmio" => Ok(BlockDriver::from(value)),
+        _ => Err(Error::InvalidArgument(format!(
+            "invalid value {} of annotation {}",
+            value, key
+        ))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use containerd_sandbox::{
+        cri::api::v1::{LinuxContainerResources, LinuxPodSandboxConfig},
+        data::SandboxData,
+        PodSandboxConfig,
+    };
+
+    use super::*;
+
+    fn resources(cpu_quota: i64, memory_limit_in_bytes: i64) -> LinuxContainerResources {
+        LinuxContainerResources {
+            cpu_period: 100000,
+            cpu_quota,
+            memory_limit_in_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Sandbox data of a pod with 1.5 cpus and 1G memory, and an overhead of 0.5 cpu and 128M.
+    pub(crate) fn sandbox_data(annotations: &[(&str, &str)]) -> SandboxData {
+        let mut pod_sandbox_config = PodSandboxConfig::default();
+        pod_sandbox_config.annotations = annotations
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        pod_sandbox_config.linux = Some(LinuxPodSandboxConfig {
+            resources: Some(resources(150000, 1024 * 1024 * 1024)),
+            overhead: Some(resources(50000, 128 * 1024 * 1024)),
+            ..Default::default()
+        });
+        let mut data = SandboxData::default();
+        data.config = Some(pod_sandbox_config);
+        data
+    }
+
+    #[tokio::test]
+    async fn test_parse_annotations() {
+        let data = sandbox_data(&[
+            (ANNOTATION_KEY_KERNEL_PARAMS, "quiet"),
+            (ANNOTATION_KEY_IMAGE, "/var/lib/kuasar/kuasar-debug.img"),
+            (ANNOTATION_KEY_VCPUS, "2"),
+            (ANNOTATION_KEY_MEMORY, "2048"),
+            (ANNOTATION_KEY_HUGEPAGES, "true"),
+            (ANNOTATION_KEY_BLOCK_DRIVER, "virtio-scsi"),
+            (ANNOTATION_KEY_DEBUG, "true"),
+            ("io.kubernetes.cri.sandbox-id", "sandbox1"),
+        ]);
+        let a = HypervisorAnnotations::parse(&data).unwrap();
+        assert_eq!(a.vcpus, Some(2));
+        assert_eq!(a.memory_in_mb, Some(2048));
+        assert_eq!(a.hugepages, Some(true));
+        assert!(matches!(a.block_driver, Some(BlockDriver::VirtioScsi)));
+
+        let mut params = "console=hvc0".to_string();
+        a.append_kernel_params(&mut params);
+        assert_eq!(
+            params,
+            format!("console=hvc0 quiet {}", DEBUG_KERNEL_PARAMS)
+        );
+
+        let mut config = SandboxConfig {
+            allowed_annotations: vec![
+                ANNOTATION_KEY_KERNEL_PARAMS.to_string(),
+                ANNOTATION_KEY_DEBUG.to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(a.validate(&config).await.is_err());
+        config.valid_image_paths = vec!["/var/lib/kuasar/kuasar-debug.img".to_string()];
+        assert!(a.validate(&config).await.is_ok());
+        // the kernel params and the debug mode are not allowed by default
+        config.allowed_annotations = vec![ANNOTATION_KEY_DEBUG.to_string()];
+        assert!(a.validate(&config).await.is_err());
+        config.allowed_annotations = vec![ANNOTATION_KEY_KERNEL_PARAMS.to_string()];
+        assert!(a.validate(&config).await.is_err());
+        // the memory can not be larger than the host memory
+        let a = HypervisorAnnotations {
+            memory_in_mb: Some(u32::MAX),
+            ..Default::default()
+        };
+        assert!(a.validate(&config).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_annotations() {
+        for (k, v) in [
+            ("io.kuasar.hypervisor.path", "/usr/bin/qemu"),
+            (ANNOTATION_KEY_VCPUS, "0"),
+            (ANNOTATION_KEY_MEMORY, "2G"),
+            (ANNOTATION_KEY_HUGEPAGES, "yes"),
+            (ANNOTATION_KEY_BLOCK_DRIVER, "nvme"),
+            (ANNOTATION_KEY_DISK_BW, "10M"),
+            (ANNOTATION_KEY_KERNEL_PARAMS, "quiet task.policy="),
+            (ANNOTATION_KEY_KERNEL_PARAMS, "task.debug"),
+            (ANNOTATION_KEY_KERNEL_PARAMS, "init=/bin/sh"),
+            (ANNOTATION_KEY_KERNEL_PARAMS, "rdinit=/bin/sh"),
+        ] {
+            let data = sandbox_data(&[(k, v)]);
+            assert!(HypervisorAnnotations::parse(&data).is_err());
+        }
+        assert!(HypervisorAnnotations::parse(&SandboxData::default()).is_ok());
+    }
+
+    #[test]
+    fn test_get_io_limits() {
+        assert_eq!(
+            get_io_limits(&SandboxData::default()).unwrap(),
+            IoLimits::default()
+        );
+
+        let mut data = sandbox_data(&[
+            (ANNOTATION_KEY_DISK_OPS, "2000"),
+            (ANNOTATION_KEY_NET_BW, "1048576"),
+        ]);
+        let resources = LinuxContainerResources {
+            unified: HashMap::from([(
+                "io.max".to_string(),
+                "8:0 rbps=4194304 wbps=max riops=1000\n8:16 rbps=2097152".to_string(),
+            )]),
+            ..Default::default()
+        };
+        data.config.as_mut().unwrap().linux = Some(LinuxPodSandboxConfig {
+            resources: Some(resources),
+            ..Default::default()
+        });
+        let limits = get_io_limits(&data).unwrap();
+        assert_eq!(limits.disk.bandwidth, 2097152);
+        // annotations take precedence over the pod resources
+        assert_eq!(limits.disk.ops, 2000);
+        assert_eq!(limits.net.bandwidth, 1048576);
+        assert_eq!(limits.net.ops, 0);
+    }
+
+    #[test]
+    fn test_get_kernel_modules() {
+        let allowed = vec!["nf_conntrack".to_string(), "tun".to_string()];
+        assert!(get_kernel_modules(&SandboxData::default(), &allowed)
+            .unwrap()
+            .is_empty());
+
+        let data = sandbox_data(&[(
+            ANNOTATION_KEY_KERNEL_MODULES,
+            "nf_conntrack hashsize=4096 expect_hashsize=512; tun;",
+        )]);
+        let modules = get_kernel_modules(&data, &allowed).unwrap();
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0].name, "nf_conntrack");
+        assert_eq!(
+            modules[0].parameters,
+            vec!["hashsize=4096", "expect_hashsize=512"]
+        );
+        assert_eq!(modules[1].name, "tun");
+        assert!(modules[1].parameters.is_empty());
+
+        let data = sandbox_data(&[(ANNOTATION_KEY_KERNEL_MODULES, "tun;kvm")]);
+        assert!(get_kernel_modules(&data, &allowed).is_err());
+
+        for invalid in [
+            "tun -C=/tmp/conf",
+            "tun --first-time",
+            "tun hashsize",
+            "tun a-b=1",
+        ] {
+            let data = sandbox_data(&[(ANNOTATION_KEY_KERNEL_MODULES, invalid)]);
+            assert!(get_kernel_modules(&data, &allowed).is_err(), "{}", invalid);
+        }
+    }
+}
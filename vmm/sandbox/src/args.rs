@@ -26,6 +26,11 @@ pub struct Args {
     #[arg(short, long, value_name = "FILE")]
     pub config: Option<String>,
 
+    /// Kata configuration.toml path, the hypervisor config is converted from it
+    /// and "--config" is ignored
+    #[arg(long, value_name = "FILE")]
+    pub kata_config: Option<String>,
+
     /// Sandboxer working directory
     #[arg(short, long, value_name = "DIR")]
     pub dir: Option<String>,
@@ -31,6 +31,9 @@ async fn main() -> anyhow::Result<()> {
     // Initialize log
     init_logger(sandboxer.log_level());
 
+    // Serve the operations on sandboxes that are not in the sandbox api of containerd
+    let _api_server = sandboxer.start_api_server().await?;
+
     // Run the sandboxer
     containerd_sandbox::run("kuasar-sandboxer", sandboxer)
         .await
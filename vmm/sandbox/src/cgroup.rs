@@ -16,8 +16,8 @@ limitations under the License.
 
 use anyhow::{anyhow, Ok, Result};
 use cgroups_rs::{
-    cgroup_builder::*, cpu::CpuController, cpuset::CpuSetController, hugetlb::HugeTlbController,
-    memory::MemController, Cgroup,
+    blkio::BlkIoController, cgroup_builder::*, cpu::CpuController, cpuset::CpuSetController,
+    hugetlb::HugeTlbController, memory::MemController, Cgroup,
 };
 use containerd_sandbox::{cri::api::v1::LinuxContainerResources, data::SandboxData};
 use serde::{Deserialize, Serialize};
@@ -29,6 +29,7 @@ use crate::{
 
 pub const VCPU_CGROUP_NAME: &str = "vcpu";
 pub const POD_OVERHEAD_CGROUP_NAME: &str = "pod_overhead";
+const UNIFIED_KEY_IO_WEIGHT: &str = "io.weight";
 
 /// `SandboxCgroup` represents a set of cgroups for a sandbox.
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -80,6 +81,7 @@ impl SandboxCgroup {
             apply_memory_resource(&self.sandbox_cgroup, &total_resources)?;
             apply_cpuset_resources(&self.sandbox_cgroup, &total_resources)?;
             apply_hugetlb_resources(&self.sandbox_cgroup, &total_resources)?;
+            apply_blkio_resources(&self.sandbox_cgroup, &total_resources)?;
         }
 
         // apply the cpu resource of containers in the vcpu cpu subsystem cgroup
@@ -182,6 +184,47 @@ fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Re
     Ok(())
 }
 
+// the io of the vmm process is weighted by the "io.weight" of the unified resources,
+// so that the disks of a sandbox get their share of the host disks.
+fn apply_blkio_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    let weight = match res.unified.get(UNIFIED_KEY_IO_WEIGHT) {
+        Some(w) => get_blkio_weight(w)?,
+        None => return Ok(()),
+    };
+    let blkio_controller: &BlkIoController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No blkio controller attached!"))?;
+    blkio_controller.set_weight(weight)?;
+    Ok(())
+}
+
+// convert the default weight of "io.weight" in [1, 10000] of cgroup v2
+// to the "blkio.weight" in [10, 1000] of cgroup v1.
+fn get_blkio_weight(io_weight: &str) -> Result<u64> {
+    let weight = io_weight
+        .lines()
+        .find_map(|l| match l.split_whitespace().collect::<Vec<_>>()[..] {
+            [w] | ["default", w] => Some(w),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "no default weight in {} {}",
+                UNIFIED_KEY_IO_WEIGHT,
+                io_weight
+            )
+        })?
+        .parse::<u64>()?;
+    if !(1..=10000).contains(&weight) {
+        return Err(anyhow!(
+            "{} {} is out of range [1, 10000]",
+            UNIFIED_KEY_IO_WEIGHT,
+            weight
+        ));
+    }
+    Ok(10 + (weight - 1) * 990 / 9999)
+}
+
 fn remove_sandbox_cgroup(cgroup: &Cgroup) -> Result<()> {
     // get the tids in the current cgroup and then move the tids to parent cgroup
     let tids = cgroup.tasks();
@@ -402,4 +445,14 @@ mod tests {
 
         assert_eq!(sandbox_cgroups.remove_sandbox_cgroups().is_ok(), true);
     }
+
+    #[test]
+    fn test_get_blkio_weight() {
+        assert_eq!(get_blkio_weight("100").unwrap(), 19);
+        assert_eq!(get_blkio_weight("default 1\n8:16 200").unwrap(), 10);
+        assert_eq!(get_blkio_weight("8:16 200\ndefault 10000").unwrap(), 1000);
+        assert!(get_blkio_weight("8:16 200").is_err());
+        assert!(get_blkio_weight("default 0").is_err());
+        assert!(get_blkio_weight("default max").is_err());
+    }
 }
@@ -15,6 +15,7 @@ limitations under the License.
 */
 
 use std::{
+    collections::HashMap,
     io::{BufRead, BufReader, Write},
     os::unix::{
         io::{IntoRawFd, RawFd},
@@ -253,6 +254,108 @@ pub(crate) async fn client_update_routes(
     Ok(())
 }
 
+pub(crate) async fn client_resize_volume(
+    client: &SandboxServiceClient,
+    mount_point: &str,
+    size: u64,
+) -> Result<()> {
+    let mut req = ResizeVolumeRequest::new();
+    req.mount_point = mount_point.to_string();
+    req.size = size;
+
+    client
+        .resize_volume(
+            with_timeout(Duration::from_secs(30).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to resize volume {}: {}", mount_point, e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_get_volume_stats(
+    client: &SandboxServiceClient,
+    mount_points: Vec<String>,
+) -> Result<Vec<VolumeStats>> {
+    let mut req = GetVolumeStatsRequest::new();
+    req.mount_points = mount_points;
+
+    let resp = client
+        .get_volume_stats(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to get volume stats: {}", e))?;
+    Ok(resp.stats)
+}
+
+pub(crate) async fn client_get_sandbox_stats(
+    client: &SandboxServiceClient,
+) -> Result<SandboxStats> {
+    let req = GetSandboxStatsRequest::new();
+    let resp = client
+        .get_sandbox_stats(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to get sandbox stats: {}", e))?;
+    Ok(resp)
+}
+
+pub(crate) async fn client_set_log_level(client: &SandboxServiceClient, level: &str) -> Result<()> {
+    let mut req = SetLogLevelRequest::new();
+    req.level = level.to_string();
+    client
+        .set_log_level(with_timeout(Duration::from_secs(5).as_nanos() as i64), &req)
+        .await
+        .map_err(|e| anyhow!("failed to set log level: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_setup_kernel(
+    client: &SandboxServiceClient,
+    sysctls: HashMap<String, String>,
+    kernel_modules: Vec<KernelModule>,
+) -> Result<()> {
+    let mut req = SetupKernelRequest::new();
+    req.sysctls = sysctls;
+    req.kernel_modules = kernel_modules;
+    client
+        .setup_kernel(
+            with_timeout(Duration::from_secs(30).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to setup guest kernel: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_pause_containers(client: &SandboxServiceClient) -> Result<()> {
+    let req = PauseContainersRequest::new();
+    client
+        .pause_containers(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to pause containers: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_resume_containers(client: &SandboxServiceClient) -> Result<()> {
+    let req = ResumeContainersRequest::new();
+    client
+        .resume_containers(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to resume containers: {}", e))?;
+    Ok(())
+}
+
 pub(crate) async fn client_sync_clock(client: &SandboxServiceClient, id: &str) {
     let id = id.to_string();
     let client = client.clone();
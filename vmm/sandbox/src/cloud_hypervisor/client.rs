@@ -21,13 +21,20 @@ use std::{
 };
 
 use anyhow::anyhow;
-use api_client::{simple_api_command, simple_api_full_command_with_fds_and_response};
+use api_client::{
+    simple_api_command, simple_api_full_command_and_response,
+    simple_api_full_command_with_fds_and_response,
+};
 use containerd_sandbox::error::Result;
 use log::error;
 
 use crate::{
-    cloud_hypervisor::devices::{block::DiskConfig, AddDeviceResponse, RemoveDeviceRequest},
+    cloud_hypervisor::devices::{
+        block::DiskConfig, AddDeviceResponse, RateLimiterConfig, RemoveDeviceRequest,
+        ResizeDiskRequest, VmInfoResponse,
+    },
     device::DeviceInfo,
+    vm::IoLimits,
 };
 
 pub(crate) const CLOUD_HYPERVISOR_START_TIMEOUT_IN_SEC: u64 = 10;
@@ -59,7 +66,7 @@ impl ChClient {
         .map_err(|e| anyhow!("failed to spawn a task {}", e))?
     }
 
-    pub fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<String> {
+    pub fn hot_attach(&mut self, device_info: DeviceInfo, io_limits: &IoLimits) -> Result<String> {
         match device_info {
             DeviceInfo::Block(blk) => {
                 let disk_config = DiskConfig {
@@ -69,6 +76,7 @@ impl ChClient {
                     vhost_user: false,
                     vhost_socket: None,
                     id: blk.id,
+                    rate_limiter_config: RateLimiterConfig::new(&io_limits.disk),
                 };
                 let request_body = serde_json::to_string(&disk_config)
                     .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", disk_config, e))?;
@@ -120,4 +128,45 @@ impl ChClient {
         .map_err(|e| anyhow!("failed to remove device {}, {}", request_body, e))?;
         Ok(())
     }
+
+    pub fn resize_disk(&mut self, device_id: &str, size: u64) -> Result<()> {
+        let request = ResizeDiskRequest {
+            id: device_id.to_string(),
+            desired_size: size,
+        };
+        let request_body = serde_json::to_string(&request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_command(&mut self.socket, "PUT", "resize-disk", Some(&request_body))
+            .map_err(|e| anyhow!("failed to resize disk {}, {}", request_body, e))?;
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> Result<()> {
+        simple_api_command(&mut self.socket, "PUT", "pause", None)
+            .map_err(|e| anyhow!("failed to pause vm, {}", e))?;
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        simple_api_command(&mut self.socket, "PUT", "resume", None)
+            .map_err(|e| anyhow!("failed to resume vm, {}", e))?;
+        Ok(())
+    }
+
+    // the disks hot plugged are in the config of the vm info
+    pub fn disk_ids(&mut self) -> Result<Vec<String>> {
+        let response_opt =
+            simple_api_full_command_and_response(&mut self.socket, "GET", "vm.info", None)
+                .map_err(|e| anyhow!("failed to get vm info, {}", e))?;
+        let response_body = response_opt.ok_or_else(|| anyhow!("no response body from server"))?;
+        let info = serde_json::from_str::<VmInfoResponse>(&response_body)
+            .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+        Ok(info
+            .config
+            .disks
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|d| d.id)
+            .collect())
+    }
 }
@@ -17,6 +17,8 @@ limitations under the License.
 use sandbox_derive::CmdLineParams;
 use serde_derive::Serialize;
 
+use crate::cloud_hypervisor::devices::RateLimiterConfig;
+
 #[derive(CmdLineParams, Debug, Clone)]
 pub struct Disk {
     path: String,
@@ -25,14 +27,19 @@ pub struct Disk {
     direct: Option<bool>,
     iommu: Option<bool>,
     num_queues: Option<u32>,
-    bw_refill_time: Option<u32>,
-    ops_size: Option<u32>,
-    ope_one_time_burst: Option<u32>,
-    ops_refill_time: Option<u32>,
+    #[property(key = "bw_size")]
+    bw_size: Option<u64>,
+    #[property(key = "bw_refill_time")]
+    bw_refill_time: Option<u64>,
+    #[property(key = "ops_size")]
+    ops_size: Option<u64>,
+    #[property(key = "ops_refill_time")]
+    ops_refill_time: Option<u64>,
     pci_segment: Option<String>,
 }
 
 impl_device_no_bus!(Disk);
+impl_rate_limit!(Disk);
 
 impl Disk {
     pub fn new(id: &str, path: &str, readonly: bool, direct: bool) -> Self {
@@ -43,9 +50,9 @@ impl Disk {
             direct: Some(direct),
             iommu: None,
             num_queues: None,
+            bw_size: None,
             bw_refill_time: None,
             ops_size: None,
-            ope_one_time_burst: None,
             ops_refill_time: None,
             pci_segment: None,
         }
@@ -60,4 +67,41 @@ pub struct DiskConfig {
     pub vhost_user: bool,
     pub vhost_socket: Option<String>,
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Disk, DiskConfig};
+    use crate::{
+        cloud_hypervisor::devices::RateLimiterConfig, param::ToCmdLineParams, vm::RateLimit,
+    };
+
+    #[test]
+    fn test_disk_rate_limit() {
+        let mut disk = Disk::new("blk0", "/dev/dm-1", false, true);
+        let limit = RateLimit {
+            bandwidth: 1048576,
+            ops: 0,
+        };
+        disk.set_rate_limit(&limit);
+        let params = disk.to_cmdline_params("--");
+        assert!(params[1].contains("bw_size=1048576,bw_refill_time=1000"));
+        assert!(!params[1].contains("ops_size"));
+
+        let disk_config = DiskConfig {
+            path: "/dev/dm-1".to_string(),
+            readonly: false,
+            direct: true,
+            vhost_user: false,
+            vhost_socket: None,
+            id: "blk0".to_string(),
+            rate_limiter_config: RateLimiterConfig::new(&limit),
+        };
+        assert_eq!(
+            serde_json::to_value(&disk_config).unwrap()["rate_limiter_config"],
+            serde_json::json!({"bandwidth": {"size": 1048576, "refill_time": 1000}})
+        );
+    }
 }
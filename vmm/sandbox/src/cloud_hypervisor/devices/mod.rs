@@ -16,7 +16,27 @@ limitations under the License.
 
 use serde_derive::{Deserialize, Serialize};
 
-use crate::{device::Device, param::ToCmdLineParams};
+use crate::{device::Device, param::ToCmdLineParams, vm::RateLimit};
+
+// the devices limited by the token buckets of cloud hypervisor have the fields
+// "bw_size", "bw_refill_time", "ops_size" and "ops_refill_time".
+macro_rules! impl_rate_limit {
+    ($ty:ty) => {
+        impl $ty {
+            pub fn set_rate_limit(&mut self, limit: &crate::vm::RateLimit) {
+                use crate::cloud_hypervisor::devices::RATE_LIMITER_REFILL_TIME_IN_MS;
+                if limit.bandwidth > 0 {
+                    self.bw_size = Some(limit.bandwidth);
+                    self.bw_refill_time = Some(RATE_LIMITER_REFILL_TIME_IN_MS);
+                }
+                if limit.ops > 0 {
+                    self.ops_size = Some(limit.ops);
+                    self.ops_refill_time = Some(RATE_LIMITER_REFILL_TIME_IN_MS);
+                }
+            }
+        }
+    };
+}
 
 pub mod block;
 pub mod console;
@@ -38,7 +58,73 @@ pub struct AddDeviceResponse {
     pub bdf: String,
 }
 
+#[derive(Serialize, Debug)]
+pub struct ResizeDiskRequest {
+    pub id: String,
+    pub desired_size: u64,
+}
+
+// only the part of the response of "vm.info" we care about
+#[derive(Deserialize, Debug, Default)]
+pub struct VmInfoResponse {
+    #[serde(default)]
+    pub config: VmInfoConfig,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct VmInfoConfig {
+    #[serde(default)]
+    pub disks: Option<Vec<VmInfoDevice>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VmInfoDevice {
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
 #[derive(Serialize, Debug)]
 pub struct RemoveDeviceRequest {
     pub id: String,
 }
+
+// the token buckets are refilled every second, so the size of bucket is the rate per second
+pub(crate) const RATE_LIMITER_REFILL_TIME_IN_MS: u64 = 1000;
+
+#[derive(Serialize, Debug)]
+pub struct TokenBucketConfig {
+    pub size: u64,
+    pub refill_time: u64,
+}
+
+impl TokenBucketConfig {
+    fn new(rate: u64) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        Some(Self {
+            size: rate,
+            refill_time: RATE_LIMITER_REFILL_TIME_IN_MS,
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RateLimiterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<TokenBucketConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops: Option<TokenBucketConfig>,
+}
+
+impl RateLimiterConfig {
+    pub fn new(limit: &RateLimit) -> Option<Self> {
+        if limit.is_unlimited() {
+            return None;
+        }
+        Some(Self {
+            bandwidth: TokenBucketConfig::new(limit.bandwidth),
+            ops: TokenBucketConfig::new(limit.ops),
+        })
+    }
+}
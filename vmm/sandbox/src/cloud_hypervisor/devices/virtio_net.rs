@@ -37,9 +37,19 @@ pub struct VirtioNetDevice {
 
     #[property(key = "num_queues", predicate = "self.fds.len()>0")]
     pub(crate) num_queues: u32,
+
+    #[property(key = "bw_size")]
+    pub(crate) bw_size: Option<u64>,
+    #[property(key = "bw_refill_time")]
+    pub(crate) bw_refill_time: Option<u64>,
+    #[property(key = "ops_size")]
+    pub(crate) ops_size: Option<u64>,
+    #[property(key = "ops_refill_time")]
+    pub(crate) ops_refill_time: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioNetDevice);
+impl_rate_limit!(VirtioNetDevice);
 
 impl VirtioNetDevice {
     pub fn new(id: &str, name: Option<String>, mac: &str, fds: Vec<RawFd>) -> Self {
@@ -49,6 +59,10 @@ impl VirtioNetDevice {
             num_queues: (fds.len() * 2) as u32,
             mac: mac.to_string(),
             fds,
+            bw_size: None,
+            bw_refill_time: None,
+            ops_size: None,
+            ops_refill_time: None,
         }
     }
 }
@@ -17,9 +17,11 @@ limitations under the License.
 use containerd_sandbox::SandboxOption;
 
 use crate::{
+    annotation::{get_io_limits, HypervisorAnnotations},
     cloud_hypervisor::{
         config::CloudHypervisorVMConfig,
         devices::{console::Console, fs::Fs, pmem::Pmem, rng::Rng, vsock::Vsock},
+        hooks::check_annotations,
         CloudHypervisorVM,
     },
     utils::get_netns,
@@ -46,6 +48,7 @@ impl VMFactory for CloudHypervisorVMFactory {
     ) -> containerd_sandbox::error::Result<Self::VM> {
         let netns = get_netns(&s.sandbox);
         let mut vm = CloudHypervisorVM::new(id, &netns, &s.base_dir, &self.vm_config);
+        vm.io_limits = get_io_limits(&s.sandbox)?;
 
         // add image as a disk
         if !self.vm_config.common.image_path.is_empty() {
@@ -79,6 +82,7 @@ impl VMFactory for CloudHypervisorVMFactory {
             vm.add_device(fs);
         }
 
+        check_annotations(&HypervisorAnnotations::parse(&s.sandbox)?, &vm)?;
         Ok(vm)
     }
 }
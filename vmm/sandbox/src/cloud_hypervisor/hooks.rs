@@ -14,10 +14,15 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use containerd_sandbox::error::Result;
+use containerd_sandbox::error::{Error, Result};
 
 use crate::{
-    cloud_hypervisor::CloudHypervisorVM, sandbox::KuasarSandbox, utils::get_resources, vm::Hooks,
+    annotation::HypervisorAnnotations,
+    cloud_hypervisor::{devices::pmem::Pmem, CloudHypervisorVM},
+    device::Device,
+    sandbox::KuasarSandbox,
+    utils::get_resources,
+    vm::{BlockDriver, Hooks},
 };
 
 pub struct CloudHypervisorHooks {}
@@ -25,8 +30,9 @@ pub struct CloudHypervisorHooks {}
 #[async_trait::async_trait]
 impl Hooks<CloudHypervisorVM> for CloudHypervisorHooks {
     async fn pre_start(&self, sandbox: &mut KuasarSandbox<CloudHypervisorVM>) -> Result<()> {
-        process_annotation(sandbox).await?;
         process_config(sandbox).await?;
+        // annotations are applied after the resources so that they can override the sizing
+        process_annotation(sandbox).await?;
         Ok(())
     }
 
@@ -38,7 +44,62 @@ impl Hooks<CloudHypervisorVM> for CloudHypervisorHooks {
     }
 }
 
-async fn process_annotation(_sandbox: &mut KuasarSandbox<CloudHypervisorVM>) -> Result<()> {
+async fn process_annotation(sandbox: &mut KuasarSandbox<CloudHypervisorVM>) -> Result<()> {
+    let annotations = HypervisorAnnotations::parse(&sandbox.data)?;
+    apply_annotations(&annotations, &mut sandbox.vm)
+}
+
+/// Check the annotations that cloud hypervisor can not apply, before the vm is created.
+pub(crate) fn check_annotations(
+    annotations: &HypervisorAnnotations,
+    vm: &CloudHypervisorVM,
+) -> Result<()> {
+    if let Some(driver) = &annotations.block_driver {
+        // cloud hypervisor only has virtio-blk devices
+        if !matches!(driver, BlockDriver::VirtioBlk) {
+            return Err(Error::InvalidArgument(format!(
+                "block driver {} is not supported by cloud hypervisor",
+                driver.to_driver_string()
+            )));
+        }
+    }
+    if annotations.image.is_some() && !vm.devices.iter().any(|d| d.id() == "rootfs") {
+        return Err(Error::InvalidArgument(
+            "can not change image of vm without rootfs".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn apply_annotations(
+    annotations: &HypervisorAnnotations,
+    vm: &mut CloudHypervisorVM,
+) -> Result<()> {
+    check_annotations(annotations, vm)?;
+    if let Some(vcpus) = annotations.vcpus {
+        vm.config.cpus.boot = vcpus;
+        vm.config.cpus.max = Some(vcpus);
+    }
+    if let Some(memory_in_mb) = annotations.memory_in_mb {
+        vm.config.memory.size = memory_in_mb as u64 * bytefmt::MIB;
+    }
+    if let Some(hugepages) = annotations.hugepages {
+        vm.config.memory.hugepages = hugepages;
+    }
+    if let Some(debug) = annotations.debug {
+        vm.config.debug = debug;
+    }
+    annotations.append_kernel_params(&mut vm.config.cmdline);
+    if let Some(image) = &annotations.image {
+        let index = vm
+            .devices
+            .iter()
+            .position(|d| d.id() == "rootfs")
+            .ok_or_else(|| {
+                Error::InvalidArgument("can not change image of vm without rootfs".to_string())
+            })?;
+        vm.devices[index] = Box::new(Pmem::new("rootfs", image, true));
+    }
     Ok(())
 }
 
@@ -57,3 +118,53 @@ async fn process_config(sandbox: &mut KuasarSandbox<CloudHypervisorVM>) -> Resul
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::apply_annotations;
+    use crate::{
+        annotation::HypervisorAnnotations,
+        cloud_hypervisor::{
+            config::CloudHypervisorVMConfig, devices::pmem::Pmem, CloudHypervisorVM,
+        },
+        param::ToCmdLineParams,
+        vm::BlockDriver,
+    };
+
+    #[test]
+    fn test_apply_annotations() {
+        let vm_config = CloudHypervisorVMConfig::default();
+        let mut vm = CloudHypervisorVM::new("sandbox1", "", "/run/kuasar/sandbox1", &vm_config);
+        let annotations = HypervisorAnnotations {
+            vcpus: Some(2),
+            memory_in_mb: Some(2048),
+            hugepages: Some(true),
+            kernel_params: Some("quiet".to_string()),
+            ..Default::default()
+        };
+        apply_annotations(&annotations, &mut vm).unwrap();
+        assert_eq!(vm.config.cpus.boot, 2);
+        assert_eq!(vm.config.memory.size, 2048 * bytefmt::MIB);
+        assert!(vm.config.memory.hugepages);
+        assert!(vm.config.cmdline.ends_with(" quiet"));
+
+        // no rootfs device to replace when booting from initrd
+        let annotations = HypervisorAnnotations {
+            image: Some("/var/lib/kuasar/kuasar-debug.img".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_annotations(&annotations, &mut vm).is_err());
+        vm.add_device(Pmem::new("rootfs", "/var/lib/kuasar/kuasar.img", true));
+        apply_annotations(&annotations, &mut vm).unwrap();
+        let params = vm.devices[0].to_cmdline_params("--");
+        assert!(params
+            .iter()
+            .any(|p| p.contains("file=/var/lib/kuasar/kuasar-debug.img")));
+
+        let annotations = HypervisorAnnotations {
+            block_driver: Some(BlockDriver::VirtioScsi),
+            ..Default::default()
+        };
+        assert!(apply_annotations(&annotations, &mut vm).is_err());
+    }
+}
@@ -41,11 +41,13 @@ use crate::{
         },
     },
     device::{BusType, DeviceInfo},
-    impl_recoverable, load_config,
+    impl_recoverable,
+    kata_config::Hypervisor,
+    load_config, load_kata_config,
     param::ToCmdLineParams,
     sandbox::KuasarSandboxer,
     utils::{read_std, set_cmd_fd, set_cmd_netns, wait_pid, write_file_atomic},
-    vm::{Pids, VcpuThreads, VM},
+    vm::{IoLimits, Pids, VcpuThreads, VM},
 };
 
 mod client;
@@ -73,6 +75,8 @@ pub struct CloudHypervisorVM {
     client: Option<ChClient>,
     fds: Vec<RawFd>,
     pids: Pids,
+    #[serde(default)]
+    io_limits: IoLimits,
 }
 
 impl CloudHypervisorVM {
@@ -98,6 +102,7 @@ impl CloudHypervisorVM {
             client: None,
             fds: vec![],
             pids: Pids::default(),
+            io_limits: IoLimits::default(),
         }
     }
 
@@ -206,7 +211,8 @@ impl VM for CloudHypervisorVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                let mut device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                device.set_rate_limit(&self.io_limits.disk);
                 self.add_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
@@ -215,12 +221,13 @@ impl VM for CloudHypervisorVM {
                     let index = self.append_fd(fd);
                     fd_ints.push(index as i32);
                 }
-                let device = VirtioNetDevice::new(
+                let mut device = VirtioNetDevice::new(
                     &tap_info.id,
                     Some(tap_info.name),
                     &tap_info.mac_address,
                     fd_ints,
                 );
+                device.set_rate_limit(&self.io_limits.net);
                 self.add_device(device);
             }
             DeviceInfo::Physical(vfio_info) => {
@@ -238,8 +245,9 @@ impl VM for CloudHypervisorVM {
     }
 
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
+        let io_limits = self.io_limits.clone();
         let client = self.get_client()?;
-        let addr = client.hot_attach(device_info)?;
+        let addr = client.hot_attach(device_info, &io_limits)?;
         Ok((BusType::PCI, addr))
     }
 
@@ -249,6 +257,26 @@ impl VM for CloudHypervisorVM {
         Ok(())
     }
 
+    async fn resize_block_device(&mut self, id: &str, size: u64) -> Result<()> {
+        let client = self.get_client()?;
+        client.resize_disk(id, size)
+    }
+
+    async fn pause(&mut self) -> Result<()> {
+        let client = self.get_client()?;
+        client.pause()
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        let client = self.get_client()?;
+        client.resume()
+    }
+
+    async fn hot_attached_devices(&mut self) -> Result<Vec<String>> {
+        let client = self.get_client()?;
+        client.disk_ids()
+    }
+
     async fn ping(&self) -> Result<()> {
         // TODO
         Ok(())
@@ -348,8 +376,10 @@ fn spawn_wait(
 pub async fn init_cloud_hypervisor_sandboxer(
     args: &Args,
 ) -> Result<KuasarSandboxer<CloudHypervisorVMFactory, CloudHypervisorHooks>> {
-    let (config, persist_dir_path) =
-        load_config::<CloudHypervisorVMConfig>(args, CONFIG_CLH_PATH).await?;
+    let (config, persist_dir_path) = match &args.kata_config {
+        Some(k) => load_kata_config(args, k, "clh", Hypervisor::to_cloud_hypervisor_config).await?,
+        None => load_config::<CloudHypervisorVMConfig>(args, CONFIG_CLH_PATH).await?,
+    };
     let hooks = CloudHypervisorHooks {};
     let mut s = KuasarSandboxer::new(config.sandbox, config.hypervisor, hooks);
     if !persist_dir_path.is_empty() {
@@ -24,8 +24,11 @@ use log::debug;
 use vmm_common::{storage::ANNOTATION_KEY_STORAGE, DEV_SHM, STORAGE_FILE_PREFIX};
 
 use crate::{
-    container::handler::Handler, sandbox::KuasarSandbox, storage::mount::is_bind_shm,
-    utils::write_file_atomic, vm::VM,
+    container::handler::Handler,
+    sandbox::KuasarSandbox,
+    storage::{layer_storage_ids, mount::is_bind_shm},
+    utils::write_file_atomic,
+    vm::VM,
 };
 
 pub struct StorageHandler {
@@ -63,6 +66,12 @@ where
                 m.source = storage.mount_point.clone();
                 m.options = vec!["bind".to_string()];
                 if storage.need_guest_handle {
+                    // the scratch disk of a local storage is mounted in guest before it
+                    for id in layer_storage_ids(storage) {
+                        if let Some(layer) = sandbox.storages.iter().find(|x| x.id == id) {
+                            storages.push(layer);
+                        }
+                    }
                     storages.push(storage);
                 }
             }
@@ -88,6 +97,12 @@ where
                 })?;
             root_source = storage.mount_point.to_string();
             if storage.need_guest_handle {
+                // the layers should be mounted in guest before the overlay assembled from them
+                for id in layer_storage_ids(storage) {
+                    if let Some(layer) = sandbox.storages.iter().find(|x| x.id == id) {
+                        storages.push(layer);
+                    }
+                }
                 storages.push(storage);
             }
         }
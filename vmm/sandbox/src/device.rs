@@ -60,9 +60,9 @@ pub enum BusType {
     #[allow(dead_code)]
     CCW,
     SCSI,
-    #[allow(dead_code)]
     MMIO,
     SERIAL,
+    #[allow(dead_code)]
     NULL,
 }
 
@@ -150,7 +150,6 @@ pub enum SlotStatus {
 pub enum Transport {
     Pci,
     Ccw,
-    #[allow(dead_code)]
     Mmio,
 }
 
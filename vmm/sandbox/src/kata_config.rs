@@ -19,11 +19,17 @@ use std::{collections::HashMap, path::Path, str::FromStr};
 use anyhow::anyhow;
 use containerd_sandbox::error::{Error, Result};
 use lazy_static::lazy_static;
+use log::warn;
 use serde_derive::Deserialize;
 use tokio::sync::{RwLock, RwLockReadGuard};
 
 use crate::{
-    qemu::config::QemuVMConfig, sandbox::SandboxConfig, utils::read_file, vm::ShareFsType,
+    cloud_hypervisor::config::CloudHypervisorVMConfig,
+    qemu::config::QemuVMConfig,
+    sandbox::SandboxConfig,
+    stratovirt::config::StratoVirtVMConfig,
+    utils::read_file,
+    vm::{BlockDriver, HypervisorCommonConfig, ShareFsType},
 };
 
 lazy_static! {
@@ -53,6 +59,80 @@ lazy_static! {
     ];
 }
 
+// keys of kata hypervisor config that are converted into the config of each hypervisor,
+// others are ignored with a warning.
+const QEMU_SUPPORTED_KEYS: &[&str] = &[
+    "path",
+    "kernel",
+    "initrd",
+    "image",
+    "firmware",
+    "machine_accelerators",
+    "cpu_features",
+    "kernel_params",
+    "machine_type",
+    "block_device_driver",
+    "entropy_source",
+    "shared_fs",
+    "virtio_fs_daemon",
+    "virtio_fs_cache",
+    "virtio_fs_extra_args",
+    "virtio_fs_cache_size",
+    "virtio_9p_direct_io",
+    "virtio_9p_multidevs",
+    "enable_vhost_user_store",
+    "default_vcpus",
+    "default_maxvcpus",
+    "default_memory",
+    "memory_slots",
+    "memory_offset",
+    "file_mem_backend",
+    "default_bridges",
+    "msize_9p",
+    "enable_mem_prealloc",
+    "enable_hugepages",
+    "enable_swap",
+    "enable_debug",
+    "enable_iothreads",
+    "use_vsock",
+    "disable_image_nvdimm",
+];
+
+const CLH_SUPPORTED_KEYS: &[&str] = &[
+    "path",
+    "kernel",
+    "initrd",
+    "image",
+    "firmware",
+    "kernel_params",
+    "entropy_source",
+    "shared_fs",
+    "virtio_fs_daemon",
+    "virtio_fs_cache",
+    "default_vcpus",
+    "default_memory",
+    "enable_mem_prealloc",
+    "enable_hugepages",
+    "enable_debug",
+];
+
+const STRATOVIRT_SUPPORTED_KEYS: &[&str] = &[
+    "path",
+    "kernel",
+    "initrd",
+    "image",
+    "firmware",
+    "kernel_params",
+    "machine_type",
+    "block_device_driver",
+    "shared_fs",
+    "virtio_fs_daemon",
+    "default_vcpus",
+    "default_memory",
+    "enable_mem_prealloc",
+    "enable_debug",
+];
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct KataConfig {
     pub hypervisor: HashMap<String, Hypervisor>,
@@ -75,6 +155,9 @@ pub struct Runtime {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Hypervisor {
+    // keys set in the config file, used to find out the unsupported ones
+    #[serde(skip)]
+    pub keys: Vec<String>,
     pub path: String,
     pub kernel: String,
     #[serde(default)]
@@ -132,6 +215,8 @@ pub struct Hypervisor {
     #[serde(default)]
     pub memory_offset: u32,
     #[serde(default)]
+    pub file_mem_backend: String,
+    #[serde(default)]
     pub default_bridges: u32,
     #[serde(default)]
     pub default_root_ports: u32,
@@ -182,13 +267,29 @@ pub struct Hypervisor {
 impl KataConfig {
     pub async fn init(config_path: &Path) -> Result<()> {
         let toml_str = read_file(config_path).await?;
-        let conf: KataConfig =
-            toml::from_str(&toml_str).map_err(|e| anyhow!("failed to parse kata config {}", e))?;
+        let conf = KataConfig::parse(&toml_str)?;
         let mut static_conf = CONFIG.write().await;
         *static_conf = conf;
         Ok(())
     }
 
+    fn parse(toml_str: &str) -> Result<KataConfig> {
+        let mut conf: KataConfig =
+            toml::from_str(toml_str).map_err(|e| anyhow!("failed to parse kata config {}", e))?;
+        let value: toml::Value =
+            toml::from_str(toml_str).map_err(|e| anyhow!("failed to parse kata config {}", e))?;
+        for (name, h) in conf.hypervisor.iter_mut() {
+            if let Some(t) = value
+                .get("hypervisor")
+                .and_then(|x| x.get(name))
+                .and_then(|x| x.as_table())
+            {
+                h.keys = t.keys().cloned().collect();
+            }
+        }
+        Ok(conf)
+    }
+
     pub async fn get() -> Result<RwLockReadGuard<'static, KataConfig>> {
         let config = CONFIG.read().await;
         Ok(config)
@@ -214,8 +315,22 @@ impl KataConfig {
 }
 
 impl Hypervisor {
+    fn warn_unsupported_keys(&self, hypervisor: &str, supported: &[&str]) {
+        for k in self
+            .keys
+            .iter()
+            .filter(|k| !supported.contains(&k.as_str()))
+        {
+            warn!(
+                "kata config \"{}\" is not supported by {}, it will be ignored",
+                k, hypervisor
+            );
+        }
+    }
+
     #[allow(clippy::field_reassign_with_default)]
     pub fn to_qemu_config(&self) -> Result<QemuVMConfig> {
+        self.warn_unsupported_keys("qemu", QEMU_SUPPORTED_KEYS);
         let mut res = QemuVMConfig::default();
         res.qemu_path = self.path.to_string();
         res.machine_type = self.machine_type.to_string();
@@ -231,6 +346,8 @@ impl Hypervisor {
         res.enable_vhost_user_store = self.enable_vhost_user_store;
         res.mem_prealloc = self.enable_mem_prealloc;
         res.mem_slots = self.memory_slots as u8;
+        res.mem_offset = self.memory_offset;
+        res.file_backend_mem_path = self.file_mem_backend.to_string();
         res.common.memory_in_mb = self.default_memory;
         if !self.cpu_features.is_empty() {
             // kick out any space character
@@ -268,6 +385,9 @@ impl Hypervisor {
             res.msize_9p = self.msize_9p;
         }
         res.share_fs = ShareFsType::from_str(&self.shared_fs)?;
+        if !self.block_device_driver.is_empty() {
+            res.block_device_driver = BlockDriver::from(&self.block_device_driver);
+        }
 
         let kernel_params = DEFAULT_KERNEL_PARAMS
             .iter()
@@ -298,4 +418,145 @@ impl Hypervisor {
         res.use_vsock = self.use_vsock;
         Ok(res)
     }
+
+    #[allow(clippy::field_reassign_with_default)]
+    pub fn to_cloud_hypervisor_config(&self) -> Result<CloudHypervisorVMConfig> {
+        self.warn_unsupported_keys("cloud hypervisor", CLH_SUPPORTED_KEYS);
+        let mut res = CloudHypervisorVMConfig::default();
+        if !self.shared_fs.is_empty() && self.shared_fs != "virtio-fs" {
+            return Err(Error::InvalidArgument(format!(
+                "shared_fs {} is not supported by cloud hypervisor",
+                self.shared_fs
+            )));
+        }
+        if !self.path.is_empty() {
+            res.path = self.path.to_string();
+        }
+        self.fill_common_config(&mut res.common);
+        res.hugepages = self.enable_hugepages;
+        if !self.entropy_source.is_empty() {
+            res.entropy_source = self.entropy_source.to_string();
+        }
+        res.task.debug = self.enable_debug;
+        if !self.virtio_fs_daemon.is_empty() {
+            res.virtiofsd.path = self.virtio_fs_daemon.to_string();
+        }
+        if !self.virtio_fs_cache.is_empty() {
+            res.virtiofsd.cache = self.virtio_fs_cache.to_string();
+        }
+        Ok(res)
+    }
+
+    #[allow(clippy::field_reassign_with_default)]
+    pub fn to_stratovirt_config(&self) -> Result<StratoVirtVMConfig> {
+        self.warn_unsupported_keys("stratovirt", STRATOVIRT_SUPPORTED_KEYS);
+        let mut res = StratoVirtVMConfig::default();
+        if !self.shared_fs.is_empty() && self.shared_fs != "virtio-fs" {
+            return Err(Error::InvalidArgument(format!(
+                "shared_fs {} is not supported by stratovirt",
+                self.shared_fs
+            )));
+        }
+        if !self.path.is_empty() {
+            res.path = self.path.to_string();
+        }
+        if !self.machine_type.is_empty() {
+            res.machine_type = self.machine_type.to_string();
+        }
+        if !self.block_device_driver.is_empty() {
+            res.block_device_driver = self.block_device_driver.to_string();
+        }
+        self.fill_common_config(&mut res.common);
+        if !self.virtio_fs_daemon.is_empty() {
+            res.virtiofsd_conf.path = self.virtio_fs_daemon.to_string();
+        }
+        Ok(res)
+    }
+
+    fn fill_common_config(&self, common: &mut HypervisorCommonConfig) {
+        if !self.kernel.is_empty() {
+            common.kernel_path = self.kernel.to_string();
+        }
+        common.initrd_path = self.initrd.to_string();
+        common.image_path = self.image.to_string();
+        common.firmware = self.firmware.to_string();
+        common.kernel_params = self.kernel_params.to_string();
+        if self.default_vcpus > 0 {
+            common.vcpus = self.default_vcpus as u32;
+        }
+        if self.default_memory > 0 {
+            common.memory_in_mb = self.default_memory;
+        }
+        common.enable_mem_prealloc = self.enable_mem_prealloc;
+        common.debug = self.enable_debug;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kata_config::KataConfig;
+
+    const KATA_CONFIG: &str = r#"
+[hypervisor.clh]
+path = "/usr/bin/cloud-hypervisor"
+kernel = "/usr/share/kata-containers/vmlinux.container"
+image = "/usr/share/kata-containers/kata-containers.img"
+kernel_params = "console=hvc0"
+default_vcpus = 2
+default_memory = 2048
+shared_fs = "virtio-fs"
+virtio_fs_daemon = "/usr/libexec/virtiofsd"
+virtio_fs_cache = "never"
+enable_debug = true
+valid_hypervisor_paths = ["/usr/bin/cloud-hypervisor"]
+
+[hypervisor.stratovirt]
+path = "/usr/bin/stratovirt"
+kernel = "/usr/share/kata-containers/vmlinux.container"
+machine_type = "microvm"
+block_device_driver = "virtio-mmio"
+shared_fs = "virtio-9p"
+
+[runtime]
+"#;
+
+    #[test]
+    fn test_convert_cloud_hypervisor_config() {
+        let conf = KataConfig::parse(KATA_CONFIG).unwrap();
+        let h = conf.hypervisor.get("clh").unwrap();
+        assert!(h.keys.contains(&"valid_hypervisor_paths".to_string()));
+        let c = h.to_cloud_hypervisor_config().unwrap();
+        assert_eq!(c.path, "/usr/bin/cloud-hypervisor");
+        assert_eq!(
+            c.common.kernel_path,
+            "/usr/share/kata-containers/vmlinux.container"
+        );
+        assert_eq!(
+            c.common.image_path,
+            "/usr/share/kata-containers/kata-containers.img"
+        );
+        assert_eq!(c.common.kernel_params, "console=hvc0");
+        assert_eq!(c.common.vcpus, 2);
+        assert_eq!(c.common.memory_in_mb, 2048);
+        assert!(c.common.debug);
+        assert!(c.task.debug);
+        assert_eq!(c.virtiofsd.path, "/usr/libexec/virtiofsd");
+        assert_eq!(c.virtiofsd.cache, "never");
+    }
+
+    #[test]
+    fn test_convert_stratovirt_config() {
+        let conf = KataConfig::parse(KATA_CONFIG).unwrap();
+        let h = conf.hypervisor.get("stratovirt").unwrap();
+        assert!(h.to_stratovirt_config().is_err());
+
+        let mut h = h.clone();
+        h.shared_fs = "".to_string();
+        let c = h.to_stratovirt_config().unwrap();
+        assert_eq!(c.path, "/usr/bin/stratovirt");
+        assert_eq!(c.machine_type, "microvm");
+        assert_eq!(c.block_device_driver, "virtio-mmio");
+        assert_eq!(c.common.vcpus, 1);
+        assert_eq!(c.common.memory_in_mb, 1024);
+    }
 }
@@ -17,18 +17,26 @@ limitations under the License.
 use anyhow::Context;
 use serde::de::DeserializeOwned;
 
-use crate::{args::Args, config::Config, sandbox::KuasarSandbox};
+use crate::{
+    args::Args,
+    config::Config,
+    kata_config::{Hypervisor, KataConfig},
+    sandbox::KuasarSandbox,
+};
 
 #[macro_use]
 mod device;
 
+mod annotation;
 mod cgroup;
 mod client;
 mod container;
 mod io;
 mod network;
 mod param;
+mod sandboxer_service;
 mod storage;
+mod task_log;
 mod vm;
 
 pub mod args;
@@ -46,18 +54,10 @@ async fn load_config<T: DeserializeOwned>(
     default_config_path: &str,
 ) -> anyhow::Result<(Config<T>, String)> {
     let mut config_path = default_config_path.to_string();
-    let mut dir_path = String::new();
     if let Some(c) = &args.config {
         config_path = c.to_string();
     }
-    if let Some(d) = &args.dir {
-        dir_path = d.to_string();
-        if !std::path::Path::new(&dir_path).exists() {
-            tokio::fs::create_dir_all(&dir_path)
-                .await
-                .with_context(|| format!("Failed to mkdir for {}", dir_path))?;
-        }
-    }
+    let dir_path = prepare_dir(args).await?;
 
     let path = std::path::Path::new(&config_path);
     let config: Config<T> = if path.exists() {
@@ -67,3 +67,41 @@ async fn load_config<T: DeserializeOwned>(
     };
     Ok((config, dir_path))
 }
+
+// load the hypervisor config from the [hypervisor.<h>] section of a kata configuration.toml,
+// keys that can not be converted are ignored with a warning.
+async fn load_kata_config<T>(
+    args: &Args,
+    kata_config_path: &str,
+    h: &str,
+    convert: impl Fn(&Hypervisor) -> containerd_sandbox::error::Result<T>,
+) -> anyhow::Result<(Config<T>, String)> {
+    let dir_path = prepare_dir(args).await?;
+    let path = std::path::Path::new(kata_config_path);
+    if !path.exists() {
+        panic!("kata config file {} not exist", kata_config_path);
+    }
+    KataConfig::init(path).await?;
+    let hypervisor = KataConfig::hypervisor_config(h, convert).await??;
+    let sandbox = KataConfig::sandbox_config(h).await?;
+    Ok((
+        Config {
+            sandbox,
+            hypervisor,
+        },
+        dir_path,
+    ))
+}
+
+async fn prepare_dir(args: &Args) -> anyhow::Result<String> {
+    let mut dir_path = String::new();
+    if let Some(d) = &args.dir {
+        dir_path = d.to_string();
+        if !std::path::Path::new(&dir_path).exists() {
+            tokio::fs::create_dir_all(&dir_path)
+                .await
+                .with_context(|| format!("Failed to mkdir for {}", dir_path))?;
+        }
+    }
+    Ok(dir_path)
+}
@@ -48,7 +48,7 @@ use crate::{
     },
     sandbox::KuasarSandbox,
     utils::write_file_async,
-    vm::VM,
+    vm::{RateLimit, VM},
 };
 
 const DEVICE_DRIVER_VFIO: &str = "vfio-pci";
@@ -381,6 +381,16 @@ impl NetworkInterface {
         Ok(())
     }
 
+    // limit the traffic in both directions of a veth, the veth is policed for the traffic to
+    // the vm, and its tap twin is policed for the traffic from the vm.
+    pub async fn set_rate_limit(&self, netns: &str, limit: &RateLimit) -> Result<()> {
+        if let (LinkType::Veth, Some(tap_intf)) = (&self.r#type, &self.twin) {
+            self.add_police_tc_filters(netns, limit).await?;
+            tap_intf.add_police_tc_filters(netns, limit).await?;
+        }
+        Ok(())
+    }
+
     pub async fn after_detach(&mut self, _netns: &str) -> Result<()> {
         if let LinkType::Physical(bdf, driver) = &self.r#type {
             bind_device_to_driver(driver, bdf).await?
@@ -396,6 +406,57 @@ impl NetworkInterface {
         Ok(())
     }
 
+    // the police filters are matched before the redirect filter, the packets over the limit are
+    // dropped, others continue to the redirect filter. tc polices either bytes or packets in one
+    // action, so there is a filter for each of them.
+    async fn add_police_tc_filters(&self, netns: &str, limit: &RateLimit) -> Result<()> {
+        let mut polices = vec![];
+        if limit.bandwidth > 0 {
+            polices.push([
+                "rate".to_string(),
+                format!("{}bps", limit.bandwidth),
+                "burst".to_string(),
+                limit.bandwidth.to_string(),
+            ]);
+        }
+        if limit.ops > 0 {
+            polices.push([
+                "pkts_rate".to_string(),
+                limit.ops.to_string(),
+                "pkts_burst".to_string(),
+                limit.ops.to_string(),
+            ]);
+        }
+        for (i, police) in polices.iter().enumerate() {
+            // TODO do this with netlink library
+            let prio = (i + 1).to_string();
+            let mut cmd = std::process::Command::new("tc");
+            cmd.args([
+                "filter",
+                "add",
+                "dev",
+                &*self.name,
+                "parent",
+                "ffff:",
+                "protocol",
+                "all",
+                "prio",
+                &prio,
+                "u32",
+                "match",
+                "u8",
+                "0",
+                "0",
+                "action",
+                "police",
+            ]);
+            cmd.args(police);
+            cmd.args(["conform-exceed", "drop/continue"]);
+            execute_in_netns(netns, cmd).await?;
+        }
+        Ok(())
+    }
+
     async fn add_redirect_tc_filter(&self, netns: &str, dest: &str) -> Result<()> {
         // TODO do this with netlink library
         let mut cmd = std::process::Command::new("tc");
@@ -30,7 +30,12 @@ use serde_derive::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
 
 pub use crate::network::{address::IpNet, link::NetworkInterface, route::Route};
-use crate::{network::link::LinkType, sandbox::KuasarSandbox, utils::safe_open_file, vm::VM};
+use crate::{
+    network::link::LinkType,
+    sandbox::KuasarSandbox,
+    utils::safe_open_file,
+    vm::{RateLimit, VM},
+};
 
 pub mod address;
 mod convert;
@@ -121,6 +126,18 @@ impl Network {
         Ok(())
     }
 
+    // limit the traffic of the interfaces on the host, for the hypervisors that
+    // can not limit the traffic of their network devices.
+    pub async fn set_rate_limit(&self, limit: &RateLimit) -> Result<()> {
+        if limit.is_unlimited() {
+            return Ok(());
+        }
+        for intf in &self.intfs {
+            intf.set_rate_limit(&self.config.netns, limit).await?;
+        }
+        Ok(())
+    }
+
     pub async fn destroy(&mut self) {
         for intf in &mut self.intfs {
             if let Err(e) = intf.after_detach(&self.config.netns).await {
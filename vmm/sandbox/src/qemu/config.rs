@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, os::unix::io::RawFd};
+use std::{collections::HashMap, os::unix::io::RawFd, path::Path};
 
 use containerd_sandbox::error::{Error, Result};
 #[cfg(target_arch = "x86_64")]
@@ -23,6 +23,7 @@ use sandbox_derive::{CmdLineParamSet, CmdLineParams};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    device::Transport,
     param::ToCmdLineParams,
     utils::{bool_to_on_off, get_host_memory_in_mb},
     vm::{BlockDriver, HypervisorCommonConfig, ShareFsType},
@@ -33,6 +34,7 @@ pub(crate) const MACHINE_TYPE_Q35: &str = "q35";
 #[allow(dead_code)]
 pub(crate) const MACHINE_TYPE_PC: &str = "pc";
 pub(crate) const MACHINE_TYPE_MICROVM_PCI: &str = "microvm-pci";
+pub(crate) const MACHINE_TYPE_MICROVM: &str = "microvm";
 pub(crate) const MACHINE_TYPE_VIRT: &str = "virt";
 #[allow(dead_code)]
 pub(crate) const MACHINE_TYPE_PSERIES: &str = "pseries";
@@ -55,6 +57,15 @@ lazy_static! {
                 options: None,
             },
         );
+        sms.insert(
+            "microvm".to_string(),
+            Machine {
+                r#type: "microvm".to_string(),
+                options: Some(
+                    "accel=kvm,pit=off,pic=off,isa-serial=off,rtc=on,x-option-roms=off".to_string(),
+                ),
+            },
+        );
         sms.insert(
             "pc".to_string(),
             Machine {
@@ -186,20 +197,51 @@ impl QemuVMConfig {
                 "Vhost-user-blk/scsi is enabled without hugepages enabled".to_string(),
             ));
         }
+        let host_memory_in_mb = get_host_memory_in_mb().await?;
+        if self.common.memory_in_mb as u64 > host_memory_in_mb {
+            return Err(Error::InvalidArgument(format!(
+                "memory {}M is larger than host memory {}M",
+                self.common.memory_in_mb, host_memory_in_mb
+            )));
+        }
         result.memory = Memory {
             size: format!("{}M", self.common.memory_in_mb),
             slots: self.mem_slots,
-            max_mem: format!("{}M", get_host_memory_in_mb().await?),
+            // mem_offset is the extra address space reserved beyond the host memory,
+            // such as the space for nvdimm devices.
+            max_mem: format!("{}M", host_memory_in_mb + self.mem_offset as u64),
             backend_type: MemoryBackend::Ram,
             pre_alloc: self.mem_prealloc,
             shared: self.enable_vhost_user_store,
-            enable_numa: self.machine_type != MACHINE_TYPE_MICROVM_PCI,
+            enable_numa: self.machine_type != MACHINE_TYPE_MICROVM_PCI
+                && self.machine_type != MACHINE_TYPE_MICROVM,
         };
+        // microvm has neither acpi memory hotplug nor pc-dimm support,
+        // so the memory size is fixed when the vm starts.
+        if self.mem_slots == 0 || self.machine_type == MACHINE_TYPE_MICROVM {
+            result.memory.slots = 0;
+            result.memory.max_mem = "".to_string();
+        }
 
-        if !self.memory_path.is_empty() {
-            result.memory.backend_type = MemoryBackend::File(self.memory_path.to_string());
+        let mem_path = if !self.memory_path.is_empty() {
+            Some(self.memory_path.to_string())
+        } else if !self.file_backend_mem_path.is_empty() {
+            // file backed memory is shared so that vhost-user backends can map it
+            result.memory.shared = true;
+            Some(self.file_backend_mem_path.to_string())
         } else if self.hugepages {
-            result.memory.backend_type = MemoryBackend::File("/dev/hugepages".to_string());
+            Some("/dev/hugepages".to_string())
+        } else {
+            None
+        };
+        if let Some(p) = mem_path {
+            if !Path::new(&p).exists() {
+                return Err(Error::InvalidArgument(format!(
+                    "memory backend path {} not exist",
+                    p
+                )));
+            }
+            result.memory.backend_type = MemoryBackend::File(p);
         }
         result.kernel = Kernel {
             path: self.common.kernel_path.to_string(),
@@ -212,6 +254,14 @@ impl QemuVMConfig {
         if !self.common.kernel_params.is_empty() {
             result.kernel.params = Some(self.common.kernel_params.to_string());
         }
+        // online the hot added memory in guest automatically
+        if result.memory.slots > 0 {
+            let params = result.kernel.params.get_or_insert_with(String::new);
+            if !params.is_empty() {
+                params.push(' ');
+            }
+            params.push_str("memhp_default_state=online");
+        }
         result.rtc = RTC {
             base: "utc".to_string(),
             clock: "host".to_string(),
@@ -244,6 +294,15 @@ pub struct Machine {
     pub options: Option<String>,
 }
 
+impl Machine {
+    pub fn transport(&self) -> Transport {
+        match self.r#type.as_str() {
+            MACHINE_TYPE_MICROVM => Transport::Mmio,
+            _ => Transport::Pci,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct QmpSocket {
     pub param_key: String,
@@ -297,15 +356,35 @@ pub struct Memory {
     pub enable_numa: bool,
 }
 
+impl Memory {
+    pub fn size_in_mb(&self) -> Result<u64> {
+        parse_size_in_mb(&self.size)
+    }
+
+    pub fn max_mem_in_mb(&self) -> Result<u64> {
+        parse_size_in_mb(&self.max_mem)
+    }
+}
+
+fn parse_size_in_mb(size: &str) -> Result<u64> {
+    size.trim_end_matches('M')
+        .parse::<u64>()
+        .map_err(|e| Error::InvalidArgument(format!("invalid memory size {}, {}", size, e)))
+}
+
 impl ToCmdLineParams for Memory {
     fn to_cmdline_params(&self, hyphen: &str) -> Vec<String> {
         let mut params = vec![];
         if !self.size.is_empty() {
             params.push(format!("{}m", hyphen));
-            params.push(format!(
-                "{},slots={},maxmem={}",
-                self.size, self.slots, self.max_mem
-            ));
+            if self.max_mem.is_empty() {
+                params.push(self.size.to_string());
+            } else {
+                params.push(format!(
+                    "{},slots={},maxmem={}",
+                    self.size, self.slots, self.max_mem
+                ));
+            }
         }
         // -machine with memory-backend is only supported by qemu with version higher than 5.0,
         // so we return directly here if numa is not supported.
@@ -492,8 +571,10 @@ mod tests {
     use uuid::Uuid;
 
     use crate::{
+        device::Transport,
         param::ToCmdLineParams,
         qemu::config::{IOThread, Incoming, MigrationType, Object, QemuVMConfig, QmpSocket},
+        utils::get_host_memory_in_mb,
     };
 
     #[tokio::test]
@@ -525,4 +606,65 @@ mod tests {
         eprintln!("params: {:?}", params);
         // TODO asserts
     }
+
+    #[tokio::test]
+    async fn test_microvm_params() {
+        let vmconfig = QemuVMConfig {
+            machine_type: "microvm".to_string(),
+            ..Default::default()
+        };
+        let config = vmconfig.to_qemu_config().await.unwrap();
+        assert!(matches!(config.machine.transport(), Transport::Mmio));
+        let params = config.memory.to_cmdline_params("-");
+        assert_eq!(
+            params,
+            vec![
+                "-m".to_string(),
+                format!("{}M", vmconfig.common.memory_in_mb)
+            ]
+        );
+
+        let vmconfig = QemuVMConfig::default();
+        let config = vmconfig.to_qemu_config().await.unwrap();
+        assert!(matches!(config.machine.transport(), Transport::Pci));
+        let params = config.memory.to_cmdline_params("-");
+        assert!(params.contains(&"-numa".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_memory_params() {
+        let vmconfig = QemuVMConfig {
+            mem_slots: 0,
+            memory_path: "/tmp".to_string(),
+            mem_prealloc: true,
+            ..Default::default()
+        };
+        let config = vmconfig.to_qemu_config().await.unwrap();
+        let params = config.memory.to_cmdline_params("-");
+        assert_eq!(params[1], format!("{}M", vmconfig.common.memory_in_mb));
+        assert!(params[3].starts_with("memory-backend-file,"));
+        assert!(params[3].contains("mem-path=/tmp,prealloc=on"));
+
+        let vmconfig = QemuVMConfig {
+            mem_slots: 4,
+            mem_offset: 1024,
+            ..Default::default()
+        };
+        let config = vmconfig.to_qemu_config().await.unwrap();
+        assert_eq!(
+            config.memory.max_mem_in_mb().unwrap(),
+            get_host_memory_in_mb().await.unwrap() + 1024
+        );
+        assert!(config
+            .kernel
+            .params
+            .unwrap()
+            .contains("memhp_default_state=online"));
+
+        let vmconfig = QemuVMConfig {
+            memory_path: "/path/not/exist".to_string(),
+            ..Default::default()
+        };
+        assert!(vmconfig.to_qemu_config().await.is_err());
+    }
 }
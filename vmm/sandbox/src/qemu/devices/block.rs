@@ -14,13 +14,15 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::os::unix::fs::FileTypeExt;
+
 use async_trait::async_trait;
 use containerd_sandbox::error::Result;
 use log::{debug, error};
 use qapi::{
     qmp::{
-        blockdev_add, blockdev_del, device_add, device_del, BlockdevOptions, BlockdevOptionsBase,
-        BlockdevOptionsFile,
+        blockdev_add, blockdev_del, device_add, device_del, object_del, BlockdevOptions,
+        BlockdevOptionsBase, BlockdevOptionsFile,
     },
     Dictionary,
 };
@@ -29,7 +31,12 @@ use serde_json::Value;
 
 use crate::{
     device::{BusType, Device, Transport},
-    qemu::{devices::HotAttachable, qmp_client::QmpClient},
+    qemu::{
+        devices::HotAttachable,
+        qmp::{blockdev_add_throttle, object_add},
+        qmp_client::QmpClient,
+    },
+    vm::RateLimit,
 };
 
 pub const VIRTIO_BLK_DRIVER: &str = "virtio-blk";
@@ -62,6 +69,8 @@ pub struct VirtioBlockDevice {
     pub disable_modern: Option<bool>,
     #[property(param = "device")]
     pub romfile: Option<String>,
+    #[property(param = "device")]
+    pub serial: Option<String>,
     #[property(
         param = "device",
         predicate = "self.share_rw==true",
@@ -70,6 +79,12 @@ pub struct VirtioBlockDevice {
     pub share_rw: bool,
     #[property(param = "drive", generator = "crate::utils::bool_to_on_off")]
     pub readonly: bool,
+    #[property(param = "drive", key = "throttling.bps-total")]
+    pub bps_total: Option<u64>,
+    #[property(param = "drive", key = "throttling.iops-total")]
+    pub iops_total: Option<u64>,
+    #[property(param = "device")]
+    pub iothread: Option<String>,
 }
 
 impl_device_no_bus!(VirtioBlockDevice);
@@ -87,10 +102,41 @@ impl VirtioBlockDevice {
             wce: Some(false),
             disable_modern: None,
             romfile: None,
+            serial: None,
             share_rw: false,
             readonly: read_only,
+            bps_total: None,
+            iops_total: None,
+            iothread: None,
+        }
+    }
+
+    pub fn set_rate_limit(&mut self, limit: &RateLimit) {
+        if limit.bandwidth > 0 {
+            self.bps_total = Some(limit.bandwidth);
+        }
+        if limit.ops > 0 {
+            self.iops_total = Some(limit.ops);
+        }
+    }
+
+    fn throttled(&self) -> bool {
+        self.bps_total.is_some() || self.iops_total.is_some()
+    }
+
+    // a throttled device has a throttle node on top of the file node, and the device is attached
+    // to the throttle node, which is named by the device id.
+    pub(crate) fn file_node_name(&self) -> String {
+        if self.throttled() {
+            format!("file-{}", self.id)
+        } else {
+            self.id.to_string()
         }
     }
+
+    fn throttle_group_id(&self) -> String {
+        format!("throttle-{}", self.id)
+    }
 }
 
 #[async_trait]
@@ -103,20 +149,31 @@ impl HotAttachable for VirtioBlockDevice {
         slot_index: usize,
     ) -> Result<()> {
         debug!("hot attach block device {}", self.id);
-        client.execute(self.to_blockdev_add()).await?;
+        if self.throttled() {
+            client.execute(self.to_throttle_group_add()).await?;
+        }
+        if let Err(e) = client.execute(self.to_blockdev_add()).await {
+            self.delete_throttle_group(client).await;
+            return Err(e);
+        }
+        if self.throttled() {
+            if let Err(e) = client.execute(self.to_blockdev_add_throttle()).await {
+                self.delete_blockdev(client, self.file_node_name()).await;
+                self.delete_throttle_group(client).await;
+                return Err(e);
+            }
+        }
         match client
             .execute(self.to_device_add(bus_type, bus_id, slot_index))
             .await
         {
             Ok(_) => Ok(()),
             Err(e) => {
-                client
-                    .execute(self.to_blockdev_del())
-                    .await
-                    .unwrap_or_else(|e| {
-                        error!("failed to delete blockdev after device_add failed, {:?}", e);
-                        qapi::Empty {}
-                    });
+                if self.throttled() {
+                    self.delete_blockdev(client, self.id.to_string()).await;
+                }
+                self.delete_blockdev(client, self.file_node_name()).await;
+                self.delete_throttle_group(client).await;
                 Err(e)
             }
         }
@@ -127,31 +184,105 @@ impl HotAttachable for VirtioBlockDevice {
         let device_id = format!("virtio-{}", self.id());
         client.delete_device(&device_id).await?;
         client.execute(self.to_blockdev_del()).await?;
+        if self.throttled() {
+            client
+                .execute(blockdev_del {
+                    node_name: self.file_node_name(),
+                })
+                .await?;
+            client
+                .execute(object_del {
+                    id: self.throttle_group_id(),
+                })
+                .await?;
+        }
         Ok(())
     }
 }
 
 impl VirtioBlockDevice {
+    async fn delete_blockdev(&self, client: &QmpClient, node_name: String) {
+        client
+            .execute(blockdev_del { node_name })
+            .await
+            .unwrap_or_else(|e| {
+                error!("failed to delete blockdev when rollback, {:?}", e);
+                qapi::Empty {}
+            });
+    }
+
+    async fn delete_throttle_group(&self, client: &QmpClient) {
+        if !self.throttled() {
+            return;
+        }
+        client
+            .execute(object_del {
+                id: self.throttle_group_id(),
+            })
+            .await
+            .unwrap_or_else(|e| {
+                error!("failed to delete throttle group when rollback, {:?}", e);
+                qapi::Empty {}
+            });
+    }
+
+    fn to_throttle_group_add(&self) -> object_add {
+        let mut limits = Dictionary::new();
+        if let Some(x) = self.bps_total {
+            limits.insert("bps-total".to_string(), Value::from(x));
+        }
+        if let Some(x) = self.iops_total {
+            limits.insert("iops-total".to_string(), Value::from(x));
+        }
+        let mut props = Dictionary::new();
+        props.insert("limits".to_string(), Value::from(limits));
+        object_add {
+            qom_type: "throttle-group".to_string(),
+            id: self.throttle_group_id(),
+            props,
+        }
+    }
+
+    fn to_blockdev_add_throttle(&self) -> blockdev_add_throttle {
+        blockdev_add_throttle {
+            driver: "throttle".to_string(),
+            node_name: self.id.to_string(),
+            throttle_group: self.throttle_group_id(),
+            file: self.file_node_name(),
+        }
+    }
+
     fn to_blockdev_add(&self) -> blockdev_add {
-        blockdev_add(BlockdevOptions::host_device {
-            base: BlockdevOptionsBase {
-                node_name: Some(self.id.to_string()),
-                read_only: Some(self.readonly),
-                auto_read_only: None,
-                cache: None,
-                force_share: None,
-                discard: None,
-                detect_zeroes: None,
-            },
-            host_device: BlockdevOptionsFile {
-                drop_cache: None,
-                locking: None,
-                x_check_cache_dropped: None,
-                filename: self.file.as_ref().unwrap().to_string(),
-                aio: None,
-                pr_manager: None,
-            },
-        })
+        let filename = self.file.as_ref().unwrap().to_string();
+        // image files, like the erofs layers, are attached with the file driver
+        let is_block_device = std::fs::metadata(&filename)
+            .map(|m| m.file_type().is_block_device())
+            .unwrap_or(true);
+        let base = BlockdevOptionsBase {
+            node_name: Some(self.file_node_name()),
+            read_only: Some(self.readonly),
+            auto_read_only: None,
+            cache: None,
+            force_share: None,
+            discard: None,
+            detect_zeroes: None,
+        };
+        let file = BlockdevOptionsFile {
+            drop_cache: None,
+            locking: None,
+            x_check_cache_dropped: None,
+            filename,
+            aio: None,
+            pr_manager: None,
+        };
+        if is_block_device {
+            blockdev_add(BlockdevOptions::host_device {
+                base,
+                host_device: file,
+            })
+        } else {
+            blockdev_add(BlockdevOptions::file { base, file })
+        }
     }
 
     fn to_device_add(&self, bus_type: &BusType, bus_id: &str, index: usize) -> device_add {
@@ -163,12 +294,20 @@ impl VirtioBlockDevice {
             );
         }
         args.insert("drive".to_string(), Value::from(self.id()));
-        if let BusType::SCSI = bus_type {
-            args.insert("scsi-id".to_string(), Value::from(index / 256));
-            args.insert("lun".to_string(), Value::from(index % 256));
-        } else {
-            let addr = format!("{:02x}", index);
-            args.insert("addr".to_string(), Value::from(addr));
+        match bus_type {
+            BusType::SCSI => {
+                args.insert("scsi-id".to_string(), Value::from(index / 256));
+                args.insert("lun".to_string(), Value::from(index % 256));
+            }
+            BusType::MMIO => {
+                // virtio-mmio devices have no address, qemu picks a free transport for it,
+                // so the guest finds the device by the serial.
+                args.insert("serial".to_string(), Value::from(self.id()));
+            }
+            _ => {
+                let addr = format!("{:02x}", index);
+                args.insert("addr".to_string(), Value::from(addr));
+            }
         }
         if let Some(x) = self.disable_modern {
             args.insert("disable-modern".to_string(), Value::from(x));
@@ -176,19 +315,25 @@ impl VirtioBlockDevice {
         if let Some(x) = self.romfile.as_ref() {
             args.insert("romfile".to_string(), Value::from(x.to_string()));
         }
+        if let (BusType::PCI | BusType::PCIE | BusType::CCW | BusType::MMIO, Some(x)) =
+            (bus_type, self.iothread.as_ref())
+        {
+            args.insert("iothread".to_string(), Value::from(x.to_string()));
+        }
         let driver = match bus_type {
             BusType::PCI => Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
             BusType::PCIE => Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
             BusType::CCW => Transport::Ccw.to_driver(VIRTIO_BLK_DRIVER),
             BusType::SCSI => "scsi-hd".to_string(),
+            BusType::MMIO => Transport::Mmio.to_driver(VIRTIO_BLK_DRIVER),
             _ => Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
         };
 
-        let bus = if let BusType::SCSI = bus_type {
+        let bus = match bus_type {
             // virtio-scsi bus option is like this
-            Some(format!("{}.0", bus_id))
-        } else {
-            Some(bus_id.to_string())
+            BusType::SCSI => Some(format!("{}.0", bus_id)),
+            BusType::MMIO => None,
+            _ => Some(bus_id.to_string()),
         };
 
         device_add {
@@ -212,3 +357,57 @@ impl VirtioBlockDevice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{VirtioBlockDevice, VIRTIO_BLK_DRIVER};
+    use crate::{device::BusType, param::ToCmdLineParams, vm::RateLimit};
+
+    #[test]
+    fn test_throttled_block_device() {
+        let mut device = VirtioBlockDevice::new(
+            VIRTIO_BLK_DRIVER,
+            "blk0",
+            Some("/dev/dm-1".to_string()),
+            false,
+        );
+        device.set_rate_limit(&RateLimit {
+            bandwidth: 1048576,
+            ops: 500,
+        });
+        device.iothread = Some("iothread0".to_string());
+
+        let params = device.to_cmdline_params("-");
+        let drive = params.iter().position(|x| x == "-drive").unwrap();
+        assert!(
+            params[drive + 1].ends_with(",throttling.bps-total=1048576,throttling.iops-total=500")
+        );
+        let dev = params.iter().position(|x| x == "-device").unwrap();
+        assert!(params[dev + 1].ends_with(",iothread=iothread0"));
+
+        assert_eq!(
+            serde_json::to_value(device.to_throttle_group_add()).unwrap(),
+            json!({
+                "qom-type": "throttle-group",
+                "id": "throttle-blk0",
+                "limits": {"bps-total": 1048576, "iops-total": 500}
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(device.to_blockdev_add_throttle()).unwrap(),
+            json!({
+                "driver": "throttle",
+                "node-name": "blk0",
+                "throttle-group": "throttle-blk0",
+                "file": "file-blk0"
+            })
+        );
+        let blockdev_add = serde_json::to_value(device.to_blockdev_add().0).unwrap();
+        assert_eq!(blockdev_add["node-name"], "file-blk0");
+        let device_add = device.to_device_add(&BusType::PCI, "pci.1", 3);
+        assert_eq!(device_add.arguments["drive"], "blk0");
+        assert_eq!(device_add.arguments["iothread"], "iothread0");
+    }
+}
@@ -0,0 +1,53 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use sandbox_derive::CmdLineParams;
+
+pub(crate) const DEFAULT_IOTHREAD_ID: &str = "iothread0";
+
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("object")]
+pub struct IoThread {
+    #[property(ignore_key)]
+    pub(crate) object_type: String,
+    pub(crate) id: String,
+}
+
+impl_device_no_bus!(IoThread);
+
+impl IoThread {
+    pub fn new(id: &str) -> Self {
+        Self {
+            object_type: "iothread".to_string(),
+            id: id.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IoThread;
+    use crate::param::ToCmdLineParams;
+
+    #[test]
+    fn test_iothread_params() {
+        let iothread = IoThread::new("iothread0");
+        assert_eq!(
+            iothread.to_cmdline_params("-"),
+            vec!["-object".to_string(), "iothread,id=iothread0".to_string()]
+        );
+    }
+}
@@ -0,0 +1,54 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    device::{Bus, BusType, Slot},
+    param::ToCmdLineParams,
+};
+
+// x86 microvm machine creates 24 virtio-mmio transports when started.
+pub(crate) const MMIO_BUS_CAPACITY: usize = 24;
+
+/// VirtioMmioBus represents the virtio-mmio transports created by the microvm machine itself,
+/// it adds nothing to the command line, but keeps track of the transports used by hot attached
+/// devices, so that we know when they run out.
+#[derive(Debug, Clone)]
+pub struct VirtioMmioBus {
+    pub id: String,
+    pub(crate) bus: Bus,
+}
+
+impl VirtioMmioBus {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            bus: Bus {
+                r#type: BusType::MMIO,
+                id: id.to_string(),
+                bus_addr: "".to_string(),
+                slots: vec![Slot::default(); MMIO_BUS_CAPACITY],
+            },
+        }
+    }
+}
+
+impl ToCmdLineParams for VirtioMmioBus {
+    fn to_cmdline_params(&self, _hyphen: &str) -> Vec<String> {
+        vec![]
+    }
+}
+
+impl_device!(VirtioMmioBus);
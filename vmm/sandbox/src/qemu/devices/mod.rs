@@ -26,6 +26,8 @@ use crate::{
 pub mod block;
 pub mod bridge;
 pub mod char;
+pub mod iothread;
+pub mod mmio;
 pub mod scsi;
 pub mod serial;
 pub mod vfio;
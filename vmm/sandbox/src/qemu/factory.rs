@@ -21,6 +21,7 @@ use uuid::Uuid;
 use vmm_common::SHARED_DIR_SUFFIX;
 
 use crate::{
+    annotation::{get_io_limits, HypervisorAnnotations},
     device::Transport,
     qemu::{
         config::{QemuVMConfig, QmpSocket},
@@ -28,12 +29,15 @@ use crate::{
             block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
             char::{CharDevice, VIRT_CONSOLE_DRIVER, VIRT_SERIAL_PORT_DRIVER},
             create_bridges,
+            iothread::{IoThread, DEFAULT_IOTHREAD_ID},
+            mmio::VirtioMmioBus,
             scsi::ScsiController,
             serial::SerialBridge,
             virtio_9p::Virtio9PDevice,
             virtio_rng::VirtioRngDevice,
             vsock::{find_context_id, VSockDevice},
         },
+        hooks::{check_annotations, check_block_driver},
         QemuVM,
     },
     utils::get_netns,
@@ -67,6 +71,9 @@ impl VMFactory for QemuVMFactory {
         vm.config.name = format!("sandbox-{}", id);
         vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
         vm.block_driver = self.default_config.block_device_driver.clone();
+        let transport = vm.config.machine.transport();
+        check_block_driver(&vm.block_driver, &transport)?;
+        vm.io_limits = get_io_limits(&s.sandbox)?;
 
         // set qmp socket
         vm.config.qmp_socket = Some(QmpSocket {
@@ -81,8 +88,10 @@ impl VMFactory for QemuVMFactory {
             no_wait: true,
         });
 
-        // set pci/pcie bridge
-        if self.default_config.default_bridges > 0 {
+        // set pci/pcie bridge, or the virtio-mmio transports if there is no pci bus
+        if let Transport::Mmio = transport {
+            vm.attach_device(VirtioMmioBus::new("virtio-mmio-bus"));
+        } else if self.default_config.default_bridges > 0 {
             let bridges = create_bridges(
                 self.default_config.default_bridges,
                 &self.default_config.machine_type,
@@ -92,15 +101,21 @@ impl VMFactory for QemuVMFactory {
             }
         }
 
+        // set iothread for the block devices
+        if self.default_config.enable_iothreads {
+            vm.attach_device(IoThread::new(DEFAULT_IOTHREAD_ID));
+            vm.iothread = Some(DEFAULT_IOTHREAD_ID.to_string());
+        }
+
         // set scsi controller
         if let BlockDriver::VirtioScsi = self.default_config.block_device_driver {
-            // TODO: transport may be not PCI on other cpu arch
-            let scsi_controller = ScsiController::new("scsi0", Transport::Pci);
+            let mut scsi_controller = ScsiController::new("scsi0", transport.clone());
+            scsi_controller.iothread = vm.iothread.clone();
             vm.attach_device(scsi_controller);
         }
 
         // set console
-        let serial = SerialBridge::new("serial0", Transport::Pci);
+        let serial = SerialBridge::new("serial0", transport.clone());
         vm.attach_device(serial);
         let console = CharDevice::new_socket(
             "console0",
@@ -113,8 +128,11 @@ impl VMFactory for QemuVMFactory {
 
         // set virtio-rng device
         if !self.default_config.entropy_source.is_empty() {
-            let rng_device =
-                VirtioRngDevice::new("rng0", &self.default_config.entropy_source, Transport::Pci);
+            let rng_device = VirtioRngDevice::new(
+                "rng0",
+                &self.default_config.entropy_source,
+                transport.clone(),
+            );
             vm.attach_device(rng_device);
         }
 
@@ -122,7 +140,7 @@ impl VMFactory for QemuVMFactory {
         if self.default_config.use_vsock {
             let (fd, cid) = find_context_id().await?;
             let fd_index = vm.append_fd(fd);
-            let vsock_device = VSockDevice::new(cid, Some(fd_index as i32), Transport::Pci);
+            let vsock_device = VSockDevice::new(cid, Some(fd_index as i32), transport.clone());
             vm.attach_device(vsock_device);
             vm.agent_socket = format!("vsock://{}:1024", cid);
         } else {
@@ -157,7 +175,7 @@ impl VMFactory for QemuVMFactory {
                     "kuasar",
                     self.default_config.virtio_9p_direct_io,
                     multidevs,
-                    Transport::Pci,
+                    transport.clone(),
                 );
                 vm.attach_device(virtio_9p);
             }
@@ -168,21 +186,33 @@ impl VMFactory for QemuVMFactory {
         }
         if !self.default_config.common.image_path.is_empty() {
             if self.default_config.disable_nvdimm {
-                let mut image_device = VirtioBlockDevice::new(
-                    &Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
-                    "image1",
-                    Some(self.default_config.common.image_path.to_string()),
-                    true,
-                );
-                image_device.format = Some("raw".to_string());
-                image_device.aio = Some("threads".to_string());
-                image_device.r#if = Some("none".to_string());
+                let image_device =
+                    new_image_device(&self.default_config.common.image_path, &transport);
                 vm.attach_device(image_device);
             } else {
                 //TODO support nvdimm device
                 return Err(Error::Unimplemented("nvdimm not implemented".to_string()));
             }
         }
+        check_annotations(&HypervisorAnnotations::parse(&s.sandbox)?, &vm)?;
         Ok(vm)
     }
 }
+
+pub(crate) const IMAGE_DEVICE_ID: &str = "image1";
+
+pub(crate) fn new_image_device(image_path: &str, transport: &Transport) -> VirtioBlockDevice {
+    let mut image_device = VirtioBlockDevice::new(
+        &transport.to_driver(VIRTIO_BLK_DRIVER),
+        IMAGE_DEVICE_ID,
+        Some(image_path.to_string()),
+        true,
+    );
+    if let Transport::Mmio = transport {
+        image_device.serial = Some(IMAGE_DEVICE_ID.to_string());
+    }
+    image_device.format = Some("raw".to_string());
+    image_device.aio = Some("threads".to_string());
+    image_device.r#if = Some("none".to_string());
+    image_device
+}
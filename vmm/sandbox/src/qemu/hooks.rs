@@ -14,16 +14,27 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::path::Path;
+
 use async_trait::async_trait;
-use containerd_sandbox::error::Result;
+use containerd_sandbox::error::{Error, Result};
 
 use crate::{
-    qemu::{config::QemuVMConfig, QemuVM},
+    annotation::HypervisorAnnotations,
+    device::{Device, Transport},
+    qemu::{
+        config::{MemoryBackend, QemuVMConfig},
+        devices::scsi::ScsiController,
+        factory::{new_image_device, IMAGE_DEVICE_ID},
+        QemuVM,
+    },
     sandbox::KuasarSandbox,
     utils::get_resources,
-    vm::Hooks,
+    vm::{BlockDriver, Hooks},
 };
 
+const HUGEPAGES_PATH: &str = "/dev/hugepages";
+
 pub struct QemuHooks {
     #[allow(dead_code)]
     config: QemuVMConfig,
@@ -37,9 +48,18 @@ impl QemuHooks {
 
 #[async_trait]
 impl Hooks<QemuVM> for QemuHooks {
+    async fn post_create(&self, sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
+        // the network devices can not be limited by the hypervisor, limit the interfaces instead
+        if let Some(network) = &sandbox.network {
+            network.set_rate_limit(&sandbox.vm.io_limits.net).await?;
+        }
+        Ok(())
+    }
+
     async fn pre_start(&self, sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
-        process_annotation(sandbox).await?;
         process_config(sandbox).await?;
+        // annotations are applied after the resources so that they can override the sizing
+        process_annotation(sandbox).await?;
         Ok(())
     }
 
@@ -51,10 +71,107 @@ impl Hooks<QemuVM> for QemuHooks {
     }
 }
 
-async fn process_annotation(_sandbox: &KuasarSandbox<QemuVM>) -> Result<()> {
+async fn process_annotation(sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
+    let annotations = HypervisorAnnotations::parse(&sandbox.data)?;
+    apply_annotations(&annotations, &mut sandbox.vm)
+}
+
+/// Check the annotations that qemu can not apply, before the vm is created.
+pub(crate) fn check_annotations(annotations: &HypervisorAnnotations, vm: &QemuVM) -> Result<()> {
+    if let (Some(memory_in_mb), false) = (
+        annotations.memory_in_mb,
+        vm.config.memory.max_mem.is_empty(),
+    ) {
+        let max_mem_in_mb = vm.config.memory.max_mem_in_mb()?;
+        if memory_in_mb as u64 > max_mem_in_mb {
+            return Err(Error::InvalidArgument(format!(
+                "memory {}M is larger than the max memory {}M of qemu",
+                memory_in_mb, max_mem_in_mb
+            )));
+        }
+    }
+    if let Some(driver) = &annotations.block_driver {
+        check_block_driver(driver, &vm.config.machine.transport())?;
+    }
+    if annotations.image.is_some() && !vm.devices.iter().any(|d| d.id() == IMAGE_DEVICE_ID) {
+        return Err(Error::InvalidArgument(
+            "can not change image of vm without rootfs".to_string(),
+        ));
+    }
     Ok(())
 }
 
+fn apply_annotations(annotations: &HypervisorAnnotations, vm: &mut QemuVM) -> Result<()> {
+    check_annotations(annotations, vm)?;
+    if let Some(vcpus) = annotations.vcpus {
+        vm.config.smp.cpus = vcpus;
+        vm.config.smp.max_cpus = vcpus;
+    }
+    if let Some(memory_in_mb) = annotations.memory_in_mb {
+        vm.config.memory.size = format!("{}M", memory_in_mb);
+    }
+    match annotations.hugepages {
+        Some(true) => {
+            if !Path::new(HUGEPAGES_PATH).exists() {
+                return Err(Error::InvalidArgument(format!(
+                    "memory backend path {} not exist",
+                    HUGEPAGES_PATH
+                )));
+            }
+            vm.config.memory.backend_type = MemoryBackend::File(HUGEPAGES_PATH.to_string());
+        }
+        Some(false) => {
+            if let MemoryBackend::File(p) = &vm.config.memory.backend_type {
+                if p == HUGEPAGES_PATH {
+                    vm.config.memory.backend_type = MemoryBackend::Ram;
+                }
+            }
+        }
+        None => {}
+    }
+    let mut kernel_params = vm.config.kernel.params.take().unwrap_or_default();
+    annotations.append_kernel_params(&mut kernel_params);
+    if !kernel_params.is_empty() {
+        vm.config.kernel.params = Some(kernel_params);
+    }
+
+    let transport = vm.config.machine.transport();
+    if let Some(driver) = &annotations.block_driver {
+        if let BlockDriver::VirtioScsi = driver {
+            if !vm.devices.iter().any(|d| d.id() == "scsi0") {
+                let mut scsi_controller = ScsiController::new("scsi0", transport.clone());
+                scsi_controller.iothread = vm.iothread.clone();
+                vm.attach_device(scsi_controller);
+            }
+        }
+        vm.block_driver = driver.clone();
+    }
+    if let Some(image) = &annotations.image {
+        let index = vm
+            .devices
+            .iter()
+            .position(|d| d.id() == IMAGE_DEVICE_ID)
+            .ok_or_else(|| {
+                Error::InvalidArgument("can not change image of vm without rootfs".to_string())
+            })?;
+        vm.devices[index] = Box::new(new_image_device(image, &transport));
+    }
+    Ok(())
+}
+
+/// Check that the block devices of the driver can be attached by the transport of the machine.
+pub(crate) fn check_block_driver(driver: &BlockDriver, transport: &Transport) -> Result<()> {
+    match (driver, transport) {
+        (BlockDriver::VirtioMmio, Transport::Pci) => Err(Error::InvalidArgument(
+            "block driver virtio-mmio is only supported by microvm".to_string(),
+        )),
+        (BlockDriver::VirtioBlk, Transport::Mmio) => Err(Error::InvalidArgument(
+            "block driver virtio-blk needs a pci bus, which microvm does not have".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
 async fn process_config(sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
     if let Some(resources) = get_resources(&sandbox.data) {
         if resources.cpu_period > 0 && resources.cpu_quota > 0 {
@@ -73,3 +190,72 @@ async fn process_config(sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_annotations, check_annotations, check_block_driver};
+    use crate::{
+        annotation::HypervisorAnnotations,
+        device::{Device, Transport},
+        qemu::{
+            config::QemuVMConfig,
+            factory::{new_image_device, IMAGE_DEVICE_ID},
+            QemuVM,
+        },
+        vm::BlockDriver,
+    };
+
+    #[tokio::test]
+    async fn test_apply_annotations() {
+        let mut vm = QemuVM::new("sandbox1", "", "/run/kuasar/sandbox1");
+        let mut vm_config = QemuVMConfig::default();
+        vm_config.common.initrd_path = "/var/lib/kuasar/initrd".to_string();
+        vm.config = vm_config.to_qemu_config().await.unwrap();
+        let annotations = HypervisorAnnotations {
+            vcpus: Some(2),
+            memory_in_mb: Some(2048),
+            kernel_params: Some("quiet".to_string()),
+            block_driver: Some(BlockDriver::VirtioScsi),
+            ..Default::default()
+        };
+        apply_annotations(&annotations, &mut vm).unwrap();
+        assert_eq!(vm.config.smp.cpus, 2);
+        assert_eq!(vm.config.memory.size, "2048M");
+        assert!(vm.config.kernel.params.as_ref().unwrap().ends_with("quiet"));
+        assert!(vm.devices.iter().any(|d| d.id() == "scsi0"));
+
+        // virtio-mmio is only for microvm
+        let annotations = HypervisorAnnotations {
+            block_driver: Some(BlockDriver::VirtioMmio),
+            ..Default::default()
+        };
+        assert!(apply_annotations(&annotations, &mut vm).is_err());
+
+        let annotations = HypervisorAnnotations {
+            image: Some("/var/lib/kuasar/kuasar-debug.img".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_annotations(&annotations, &mut vm).is_err());
+        let transport = vm.config.machine.transport();
+        vm.attach_device(new_image_device("/var/lib/kuasar/kuasar.img", &transport));
+        apply_annotations(&annotations, &mut vm).unwrap();
+        assert!(vm.devices.iter().any(|d| d.id() == IMAGE_DEVICE_ID));
+
+        // memory can not be larger than the max memory of the vm
+        vm.config.memory.max_mem = "1024M".to_string();
+        let annotations = HypervisorAnnotations {
+            memory_in_mb: Some(2048),
+            ..Default::default()
+        };
+        assert!(check_annotations(&annotations, &vm).is_err());
+    }
+
+    #[test]
+    fn test_check_block_driver() {
+        assert!(check_block_driver(&BlockDriver::VirtioBlk, &Transport::Pci).is_ok());
+        assert!(check_block_driver(&BlockDriver::VirtioScsi, &Transport::Mmio).is_ok());
+        assert!(check_block_driver(&BlockDriver::VirtioMmio, &Transport::Mmio).is_ok());
+        assert!(check_block_driver(&BlockDriver::VirtioMmio, &Transport::Pci).is_err());
+        assert!(check_block_driver(&BlockDriver::VirtioBlk, &Transport::Mmio).is_err());
+    }
+}
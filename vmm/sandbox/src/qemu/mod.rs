@@ -26,8 +26,12 @@ use containerd_sandbox::error::{Error, Result};
 use futures_util::TryFutureExt;
 use log::{debug, error, trace, warn};
 use nix::{fcntl::OFlag, libc::kill, sys::stat::Mode};
-use qapi::qmp::quit;
+use qapi::{
+    qmp::{device_add, object_del, quit},
+    Dictionary,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 use tokio::{
     net::UnixStream,
@@ -45,7 +49,7 @@ use crate::{
     kata_config::KataConfig,
     param::ToCmdLineParams,
     qemu::{
-        config::QemuConfig,
+        config::{MemoryBackend, QemuConfig},
         devices::{
             block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
             char::{CharDevice, VIRT_SERIAL_PORT_DRIVER},
@@ -54,22 +58,26 @@ use crate::{
             virtio_net::VirtioNetDevice,
             QemuDevice, QemuHotAttachable,
         },
+        qmp::object_add,
         qmp_client::QmpClient,
         utils::detect_pid,
     },
     sandbox::KuasarSandboxer,
+    storage::STORAGE_DEVICE_ID_PREFIX,
     utils::{read_std, wait_channel, wait_pid},
-    vm::{BlockDriver, Pids, VcpuThreads, VM},
+    vm::{BlockDriver, IoLimits, Pids, VcpuThreads, VM},
 };
 
 pub mod config;
 mod devices;
 pub mod factory;
 pub mod hooks;
+mod qmp;
 mod qmp_client;
 mod utils;
 
 pub(crate) const QEMU_START_TIMEOUT_IN_SEC: u64 = 10;
+const MEMORY_BLOCK_SIZE_IN_MB: u64 = 128;
 
 // restart recovery is not supported yet,
 // so we annotate the QemuVM with Serialize and Deserlize,
@@ -82,13 +90,24 @@ pub struct QemuVM {
     devices: Vec<Box<dyn QemuDevice + Sync + Send>>,
     #[serde(skip)]
     hot_attached_devices: Vec<Box<dyn QemuHotAttachable + Sync + Send>>,
+    // ids of the hot attached devices, which are kept over a restart of the sandboxer
+    #[serde(default)]
+    hot_attached_ids: Vec<String>,
     fds: Vec<RawFd>,
     console_socket: String,
     agent_socket: String,
     netns: String,
     pids: Pids,
+    #[serde(default)]
+    hot_added_memory_slots: u8,
+    #[serde(default)]
+    hot_added_memory_in_mb: u64,
     #[serde(skip)]
     block_driver: BlockDriver,
+    #[serde(default)]
+    io_limits: IoLimits,
+    #[serde(default)]
+    iothread: Option<String>,
     #[serde(skip)]
     wait_chan: Option<Receiver<(u32, i128)>>,
     #[serde(skip)]
@@ -164,12 +183,18 @@ impl VM for QemuVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
-                    &Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
+                let transport = self.config.machine.transport();
+                let mut device = VirtioBlockDevice::new(
+                    &transport.to_driver(VIRTIO_BLK_DRIVER),
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                if let Transport::Mmio = transport {
+                    device.serial = Some(blk_info.id.to_string());
+                }
+                device.set_rate_limit(&self.io_limits.disk);
+                device.iothread = self.iothread.clone();
                 self.attach_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
@@ -182,7 +207,7 @@ impl VM for QemuVM {
                     &tap_info.id,
                     Some(tap_info.name),
                     &tap_info.mac_address,
-                    Transport::Pci,
+                    self.config.machine.transport(),
                     fd_ints,
                     vec![],
                 );
@@ -218,27 +243,29 @@ impl VM for QemuVM {
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                // the virtio-mmio transports of microvm are created at boot and can not hot plug
+                // devices, the block devices of microvm are only attached before it is started.
+                if let BlockDriver::VirtioMmio = self.block_driver {
+                    return Err(Error::Unimplemented(
+                        "hotplug unsupported on microvm".to_string(),
+                    ));
+                }
+                let mut device = VirtioBlockDevice::new(
                     "",
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                device.set_rate_limit(&self.io_limits.disk);
+                device.iothread = self.iothread.clone();
                 let (bus_addr, index) = self
                     .hot_attach_device(device, self.block_driver.to_bus_type())
                     .await?;
                 let addr = match self.block_driver {
-                    BlockDriver::VirtioBlk => {
-                        format!("0000:{}:{:02x}.0", bus_addr, index)
-                    }
-                    BlockDriver::VirtioMmio => {
-                        return Err(Error::Unimplemented(
-                            "virtio-mmio not implemented yet".to_string(),
-                        ));
-                    }
                     BlockDriver::VirtioScsi => {
                         format!("{}:{}", index / 256, index % 256)
                     }
+                    _ => format!("0000:{}:{:02x}.0", bus_addr, index),
                 };
                 Ok((self.block_driver.to_bus_type(), addr))
             }
@@ -268,11 +295,26 @@ impl VM for QemuVM {
 
     async fn hot_detach(&mut self, id: &str) -> Result<()> {
         let index = self.hot_attached_devices.iter().position(|x| x.id() == id);
-        let device = match index {
+        let device: Box<dyn QemuHotAttachable + Sync + Send> = match index {
+            Some(index) => self.hot_attached_devices.remove(index),
+            None if !self.hot_attached_ids.iter().any(|x| x == id) => {
+                return Ok(());
+            }
+            // the device was hot attached before a restart of the sandboxer,
+            // only block devices can be rebuilt from the id to detach them
+            None if id.starts_with(STORAGE_DEVICE_ID_PREFIX) => {
+                let mut device = VirtioBlockDevice::new("", id, None, false);
+                device.set_rate_limit(&self.io_limits.disk);
+                Box::new(device)
+            }
             None => {
+                warn!(
+                    "device {} hot attached before restart can not be detached",
+                    id
+                );
+                self.hot_attached_ids.retain(|x| x != id);
                 return Ok(());
             }
-            Some(index) => self.hot_attached_devices.remove(index),
         };
 
         let client = match self.get_client() {
@@ -289,10 +331,42 @@ impl VM for QemuVM {
             self.hot_attached_devices.push(device);
             return Err(e);
         }
+        self.hot_attached_ids.retain(|x| x != id);
         self.detach_from_bus(id);
         Ok(())
     }
 
+    // the file node is resized, it is under a throttle node if the disk is rate limited
+    async fn resize_block_device(&mut self, id: &str, size: u64) -> Result<()> {
+        let mut device = VirtioBlockDevice::new("", id, None, false);
+        device.set_rate_limit(&self.io_limits.disk);
+        let client = self.get_client()?;
+        client
+            .execute(qapi::qmp::block_resize {
+                device: None,
+                node_name: Some(device.file_node_name()),
+                size: size as i64,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn pause(&mut self) -> Result<()> {
+        let client = self.get_client()?;
+        client.execute(qapi::qmp::stop {}).await?;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        let client = self.get_client()?;
+        client.execute(qapi::qmp::cont {}).await?;
+        Ok(())
+    }
+
+    async fn hot_attached_devices(&mut self) -> Result<Vec<String>> {
+        Ok(self.hot_attached_ids.clone())
+    }
+
     async fn ping(&self) -> Result<()> {
         let client = self.get_client()?;
         let _res = client.execute(qapi::qmp::query_status {}).await?;
@@ -318,6 +392,84 @@ impl VM for QemuVM {
         // TODO: support get all vmm related pids
         Pids::default()
     }
+
+    async fn resize_memory(&mut self, extra_memory_in_mb: u64) -> Result<()> {
+        let current = self.config.memory.size_in_mb()?;
+        let memory_in_mb = current.saturating_sub(self.hot_added_memory_in_mb) + extra_memory_in_mb;
+        if memory_in_mb <= current {
+            return Ok(());
+        }
+        if self.config.memory.slots == 0 {
+            return Err(Error::Unimplemented(
+                "memory hotplug is not enabled".to_string(),
+            ));
+        }
+        if self.hot_added_memory_slots >= self.config.memory.slots {
+            return Err(Error::ResourceExhausted("memory slots".to_string()));
+        }
+        // guest kernel can only online memory in unit of memory block
+        let size = (memory_in_mb - current + MEMORY_BLOCK_SIZE_IN_MB - 1) / MEMORY_BLOCK_SIZE_IN_MB
+            * MEMORY_BLOCK_SIZE_IN_MB;
+        let max_mem = self.config.memory.max_mem_in_mb()?;
+        if current + size > max_mem {
+            return Err(Error::ResourceExhausted(format!(
+                "memory {}M exceeds the maxmem {}M",
+                current + size,
+                max_mem
+            )));
+        }
+
+        let index = self.hot_added_memory_slots;
+        let memdev = format!("hpmem{}", index);
+        let (qom_type, mut props) = match &self.config.memory.backend_type {
+            MemoryBackend::Ram => ("memory-backend-ram", Dictionary::new()),
+            MemoryBackend::File(p) => {
+                let mut props = Dictionary::new();
+                props.insert("mem-path".to_string(), Value::from(p.to_string()));
+                ("memory-backend-file", props)
+            }
+        };
+        props.insert("size".to_string(), Value::from(size * bytefmt::MIB));
+        props.insert(
+            "prealloc".to_string(),
+            Value::from(self.config.memory.pre_alloc),
+        );
+        props.insert("share".to_string(), Value::from(self.config.memory.shared));
+        let client = self.get_client()?;
+        client
+            .execute(object_add {
+                qom_type: qom_type.to_string(),
+                id: memdev.to_string(),
+                props,
+            })
+            .await?;
+        let mut args = Dictionary::new();
+        args.insert("memdev".to_string(), Value::from(memdev.to_string()));
+        let dimm = device_add {
+            driver: "pc-dimm".to_string(),
+            bus: None,
+            id: Some(format!("hpdimm{}", index)),
+            arguments: args,
+        };
+        if let Err(e) = client.execute(dimm).await {
+            client
+                .execute(object_del { id: memdev })
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        "failed to delete memory object after device_add failed, {:?}",
+                        e
+                    );
+                    qapi::Empty {}
+                });
+            return Err(e);
+        }
+        debug!("hot added {}M memory to vm {}", size, self.id);
+        self.hot_added_memory_slots += 1;
+        self.hot_added_memory_in_mb += size;
+        self.config.memory.size = format!("{}M", current + size);
+        Ok(())
+    }
 }
 
 impl QemuVM {
@@ -327,12 +479,17 @@ impl QemuVM {
             config: QemuConfig::default(),
             devices: vec![],
             hot_attached_devices: vec![],
+            hot_attached_ids: vec![],
             fds: vec![],
             console_socket: format!("{}/console.sock", base_dir),
             agent_socket: "".to_string(),
             netns: netns.to_string(),
             pids: Pids::default(),
+            hot_added_memory_slots: 0,
+            hot_added_memory_in_mb: 0,
             block_driver: Default::default(),
+            io_limits: IoLimits::default(),
+            iothread: None,
             wait_chan: None,
             client: None,
         }
@@ -477,6 +634,7 @@ impl QemuVM {
                 return Err(e);
             }
         };
+        self.hot_attached_ids.push(device.id());
         self.hot_attached_devices.push(Box::new(device));
         Ok((bus_addr, index))
     }
@@ -536,8 +694,12 @@ impl_recoverable!(QemuVM);
 
 pub async fn init_qemu_sandboxer(args: &Args) -> Result<KuasarSandboxer<QemuVMFactory, QemuHooks>> {
     // For compatibility with kata config
-    let config_path = std::env::var("KATA_CONFIG_PATH")
-        .unwrap_or_else(|_| "/usr/share/defaults/kata-containers/configuration.toml".to_string());
+    let config_path = match &args.kata_config {
+        Some(k) => k.to_string(),
+        None => std::env::var("KATA_CONFIG_PATH").unwrap_or_else(|_| {
+            "/usr/share/defaults/kata-containers/configuration.toml".to_string()
+        }),
+    };
 
     let path = std::path::Path::new(&config_path);
     if path.exists() {
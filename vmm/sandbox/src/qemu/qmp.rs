@@ -0,0 +1,58 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#![allow(non_camel_case_types)]
+
+use qapi::{qmp::QmpCommand, Dictionary};
+use serde::{Deserialize, Serialize};
+
+// object-add of qapi is generated from the old qemu schema which wraps the properties with "props",
+// newer qemu takes the properties flattened with the qom-type and id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct object_add {
+    #[serde(rename = "qom-type")]
+    pub qom_type: String,
+    pub id: String,
+    #[serde(flatten)]
+    pub props: Dictionary,
+}
+
+impl QmpCommand for object_add {}
+impl ::qapi_spec::Command for object_add {
+    const NAME: &'static str = "object-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+// a throttle filter node on top of the node of "file", the node is limited by the throttle group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct blockdev_add_throttle {
+    pub driver: String,
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+    #[serde(rename = "throttle-group")]
+    pub throttle_group: String,
+    pub file: String,
+}
+
+impl QmpCommand for blockdev_add_throttle {}
+impl ::qapi_spec::Command for blockdev_add_throttle {
+    const NAME: &'static str = "blockdev-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
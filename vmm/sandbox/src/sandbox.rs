@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc};
+use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -33,20 +33,33 @@ use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     sync::{Mutex, RwLock},
 };
+use ttrpc::r#async::Server;
 use vmm_common::{
-    api::sandbox_ttrpc::SandboxServiceClient, storage::Storage, ETC_HOSTS, ETC_RESOLV,
-    HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME, SHARED_DIR_SUFFIX,
+    api::{
+        sandbox::SandboxStats, sandbox_ttrpc::SandboxServiceClient,
+        sandboxer_ttrpc::create_sandboxer_service,
+    },
+    storage::Storage,
+    CHECKPOINT_DIR, ETC_HOSTS, ETC_RESOLV, HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME,
+    SHARED_DIR_SUFFIX,
 };
 
 use crate::{
+    annotation::{get_kernel_modules, HypervisorAnnotations},
     cgroup::SandboxCgroup,
     client::{
-        client_check, client_sync_clock, client_update_interfaces, client_update_routes,
-        new_sandbox_client,
+        client_check, client_get_sandbox_stats, client_pause_containers, client_resume_containers,
+        client_set_log_level, client_setup_kernel, client_sync_clock, client_update_interfaces,
+        client_update_routes, new_sandbox_client,
     },
     container::KuasarContainer,
     network::{Network, NetworkConfig},
-    utils::{get_dns_config, get_hostname, get_resources, get_sandbox_cgroup_parent_path},
+    sandboxer_service::SandboxerService,
+    storage::reconcile::StorageReport,
+    task_log::{forward_task_log, TaskLogOutput},
+    utils::{
+        get_dns_config, get_hostname, get_resources, get_sandbox_cgroup_parent_path, get_sysctls,
+    },
     vm::{Hooks, Recoverable, VMFactory, VM},
 };
 
@@ -84,7 +97,6 @@ macro_rules! _monitor {
 pub struct KuasarSandboxer<F: VMFactory, H: Hooks<F::VM>> {
     factory: F,
     hooks: H,
-    #[allow(dead_code)]
     config: SandboxConfig,
     #[allow(clippy::type_complexity)]
     sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<F::VM>>>>>>,
@@ -94,7 +106,7 @@ impl<F, H> KuasarSandboxer<F, H>
 where
     F: VMFactory,
     H: Hooks<F::VM>,
-    F::VM: VM + Sync + Send,
+    F::VM: VM + Sync + Send + 'static,
 {
     pub fn new(config: SandboxConfig, vmm_config: F::Config, hooks: H) -> Self {
         Self {
@@ -108,6 +120,32 @@ where
     pub fn log_level(&self) -> &str {
         &self.config.log_level
     }
+
+    /// Serve the SandboxerService on the unix socket at api_address of the config,
+    /// the server should be kept by the caller until the sandboxer exits.
+    pub async fn start_api_server(&self) -> Result<Option<Server>> {
+        let address = &self.config.api_address;
+        if address.is_empty() {
+            return Ok(None);
+        }
+        if let Err(e) = tokio::fs::remove_file(address).await {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(anyhow!("failed to remove socket {}, {}", address, e).into());
+            }
+        }
+        let service = create_sandboxer_service(Arc::new(Box::new(SandboxerService::new(
+            self.sandboxes.clone(),
+        ))));
+        let mut server = Server::new()
+            .bind(&format!("unix://{}", address))
+            .map_err(|e| anyhow!("failed to bind {}, {}", address, e))?
+            .register_service(service);
+        server
+            .start()
+            .await
+            .map_err(|e| anyhow!("failed to start api server, {}", e))?;
+        Ok(Some(server))
+    }
 }
 
 impl<F, H> KuasarSandboxer<F, H>
@@ -123,10 +161,31 @@ where
                 if t.is_dir() {
                     let path = Path::new(dir).join(entry.file_name());
                     match KuasarSandbox::recover(&path).await {
-                        Ok(sb) => {
+                        Ok(mut sb) => {
+                            // storages may leak if the sandboxer exits while handling them
+                            // the guest of a paused sandbox is not able to respond
+                            if matches!(sb.status, SandboxStatus::Running(_)) && !sb.paused {
+                                match sb.reconcile_storages().await {
+                                    // the sandbox is still recovered if it fails to dump,
+                                    // and the recovery of the others goes on
+                                    Ok(report) if !report.is_empty() => {
+                                        if let Err(e) = sb.dump().await {
+                                            warn!("failed to dump sandbox {}, {:?}", sb.id, e);
+                                        }
+                                    }
+                                    Ok(_) => {}
+                                    Err(e) => {
+                                        warn!("failed to reconcile storages of {}, {:?}", sb.id, e)
+                                    }
+                                }
+                            }
+                            if matches!(sb.status, SandboxStatus::Running(_)) && !sb.paused {
+                                sb.forward_task_log(&self.config.task_log_output).await;
+                            }
                             let sb_mutex = Arc::new(Mutex::new(sb));
                             let sb_clone = sb_mutex.clone();
                             monitor(sb_clone);
+                            reconcile(sb_mutex.clone(), self.config.storage_reconcile_interval);
                             self.sandboxes
                                 .write()
                                 .await
@@ -162,6 +221,9 @@ pub struct KuasarSandbox<V: VM> {
     pub(crate) exit_signal: Arc<ExitSignal>,
     #[serde(default)]
     pub(crate) sandbox_cgroups: SandboxCgroup,
+    /// The vm of a paused sandbox is still Running, but its vcpus are stopped
+    #[serde(default)]
+    pub(crate) paused: bool,
 }
 
 #[async_trait]
@@ -177,6 +239,14 @@ where
         if self.sandboxes.read().await.get(id).is_some() {
             return Err(Error::AlreadyExist("sandbox".to_string()));
         }
+        HypervisorAnnotations::parse(&s.sandbox)?
+            .validate(&self.config)
+            .await?;
+        get_kernel_modules(&s.sandbox, &self.config.allowed_kernel_modules)?;
+        // create the vm before the cgroups, the factory checks the annotations
+        // against the hypervisor and nothing is left behind if it fails
+        // TODO support network
+        let vm = self.factory.create_vm(id, &s).await?;
 
         let mut sandbox_cgroups = SandboxCgroup::default();
         let cgroup_parent_path = match get_sandbox_cgroup_parent_path(&s.sandbox) {
@@ -203,8 +273,6 @@ where
             }
         }
 
-        // TODO support network
-        let vm = self.factory.create_vm(id, &s).await?;
         let mut sandbox = KuasarSandbox {
             vm,
             id: id.to_string(),
@@ -218,6 +286,7 @@ where
             client: Arc::new(Mutex::new(None)),
             exit_signal: Arc::new(ExitSignal::default()),
             sandbox_cgroups,
+            paused: false,
         };
 
         // Handle pod network if it has a private network namespace
@@ -256,7 +325,7 @@ where
         let sandbox_mutex = self.sandbox(id).await?;
         let mut sandbox = sandbox_mutex.lock().await;
         self.hooks.pre_start(&mut sandbox).await?;
-        sandbox.start().await?;
+        sandbox.start(&self.config.allowed_kernel_modules).await?;
 
         // Currently only support cgroup V1, cgroup V2 is not supported now
         if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
@@ -283,8 +352,13 @@ where
             }
         }
 
+        sandbox.forward_task_log(&self.config.task_log_output).await;
         let sandbox_clone = sandbox_mutex.clone();
         monitor(sandbox_clone);
+        reconcile(
+            sandbox_mutex.clone(),
+            self.config.storage_reconcile_interval,
+        );
         self.hooks.post_start(&mut sandbox).await?;
         sandbox.dump().await?;
         Ok(())
@@ -354,20 +428,25 @@ where
     }
 
     async fn append_container(&mut self, id: &str, options: ContainerOption) -> Result<()> {
+        self.check_not_paused()?;
         let handler_chain = self.container_append_handlers(id, options)?;
         handler_chain.handle(self).await?;
+        self.update_vm_memory().await;
         self.dump().await?;
         Ok(())
     }
 
     async fn update_container(&mut self, id: &str, options: ContainerOption) -> Result<()> {
+        self.check_not_paused()?;
         let handler_chain = self.container_update_handlers(id, options).await?;
         handler_chain.handle(self).await?;
+        self.update_vm_memory().await;
         self.dump().await?;
         Ok(())
     }
 
     async fn remove_container(&mut self, id: &str) -> Result<()> {
+        self.check_not_paused()?;
         self.deference_container_storages(id).await?;
 
         let bundle = format!("{}/{}", self.get_sandbox_shared_path(), &id);
@@ -439,6 +518,7 @@ where
             .map_err(Error::IO)?;
         let mut sb = serde_json::from_slice::<KuasarSandbox<V>>(content.as_slice())
             .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+        sb.upgrade_overlay_storages().await;
         if let SandboxStatus::Running(_) = sb.status {
             sb.vm.recover().await?;
         }
@@ -454,7 +534,7 @@ impl<V> KuasarSandbox<V>
 where
     V: VM + Sync + Send,
 {
-    async fn start(&mut self) -> Result<()> {
+    async fn start(&mut self, allowed_kernel_modules: &[String]) -> Result<()> {
         let pid = self.vm.start().await?;
 
         if let Err(e) = self.init_client().await {
@@ -467,6 +547,11 @@ where
             return Err(e);
         }
 
+        if let Err(e) = self.setup_kernel(allowed_kernel_modules).await {
+            self.vm.stop(true).await.unwrap_or_default();
+            return Err(e);
+        }
+
         self.status = SandboxStatus::Running(pid);
         Ok(())
     }
@@ -487,6 +572,15 @@ where
                 );
             }
         }
+        if self.paused {
+            if let Err(e) = self.resume().await {
+                if !force {
+                    return Err(e);
+                }
+                warn!("failed to resume sandbox {} before stop, {:?}", self.id, e);
+                self.paused = false;
+            }
+        }
         let container_ids: Vec<String> = self.containers.keys().map(|k| k.to_string()).collect();
         if force {
             for id in container_ids {
@@ -498,6 +592,10 @@ where
             }
         }
 
+        // release the storages shared by the pod, like emptyDir volumes
+        let id = self.id.clone();
+        self.deference_container_storages(&id).await?;
+
         self.vm.stop(force).await?;
         if let Some(network) = self.network.as_mut() {
             network.destroy().await;
@@ -516,7 +614,7 @@ where
         self.id_generator
     }
 
-    async fn init_client(&mut self) -> Result<()> {
+    pub(crate) async fn init_client(&mut self) -> Result<()> {
         let mut client_guard = self.client.lock().await;
         if client_guard.is_none() {
             let addr = self.vm.socket_address();
@@ -530,6 +628,90 @@ where
         Ok(())
     }
 
+    /// Stats of the guest as a whole, which tells if the guest is over-committed
+    /// as it is invisible in the cgroup stats of the containers.
+    pub async fn stats(&mut self) -> Result<SandboxStats> {
+        self.init_client().await?;
+        let client_guard = self.client.lock().await;
+        let client = client_guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("sandbox client is not init"))?;
+        client_get_sandbox_stats(client).await
+    }
+
+    /// Freeze all the containers in the guest and then pause the vm, the paused state is
+    /// persisted in sandbox.json, container operations are rejected until it is resumed.
+    pub async fn pause(&mut self) -> Result<()> {
+        if !matches!(self.status, SandboxStatus::Running(_)) {
+            return Err(anyhow!(
+                "sandbox {} is {:?}, can not be paused",
+                self.id,
+                self.status
+            )
+            .into());
+        }
+        if self.paused {
+            return Ok(());
+        }
+        self.init_client().await?;
+        {
+            let client_guard = self.client.lock().await;
+            let client = client_guard
+                .as_ref()
+                .ok_or_else(|| anyhow!("sandbox client is not init"))?;
+            client_pause_containers(client).await?;
+            if let Err(e) = self.vm.pause().await {
+                client_resume_containers(client).await.unwrap_or_else(|e| {
+                    warn!("failed to resume containers of {}, {:?}", self.id, e)
+                });
+                return Err(e);
+            }
+        }
+        self.paused = true;
+        self.dump().await
+    }
+
+    pub async fn resume(&mut self) -> Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+        self.vm.resume().await?;
+        self.paused = false;
+        self.dump().await?;
+        self.init_client().await?;
+        let client_guard = self.client.lock().await;
+        let client = client_guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("sandbox client is not init"))?;
+        client_resume_containers(client).await
+    }
+
+    fn check_not_paused(&self) -> Result<()> {
+        if self.paused {
+            return Err(anyhow!("sandbox {} is paused", self.id).into());
+        }
+        Ok(())
+    }
+
+    pub async fn set_task_log_level(&mut self, level: &str) -> Result<()> {
+        self.init_client().await?;
+        let client_guard = self.client.lock().await;
+        let client = client_guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("sandbox client is not init"))?;
+        client_set_log_level(client, level).await
+    }
+
+    async fn forward_task_log(&self, output: &str) {
+        forward_task_log(
+            &self.id,
+            &self.vm.socket_address(),
+            &self.base_dir,
+            &TaskLogOutput::from(output),
+        )
+        .await;
+    }
+
     pub(crate) async fn setup_network(&mut self) -> Result<()> {
         if let Some(network) = self.network.as_ref() {
             let client_guard = self.client.lock().await;
@@ -541,6 +723,20 @@ where
         Ok(())
     }
 
+    // load the kernel modules and set the sysctls of the pod, before any container is created
+    async fn setup_kernel(&self, allowed_kernel_modules: &[String]) -> Result<()> {
+        let sysctls = get_sysctls(&self.data);
+        let kernel_modules = get_kernel_modules(&self.data, allowed_kernel_modules)?;
+        if sysctls.is_empty() && kernel_modules.is_empty() {
+            return Ok(());
+        }
+        let client_guard = self.client.lock().await;
+        let client = client_guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("sandbox client is not init"))?;
+        client_setup_kernel(client, sysctls, kernel_modules).await
+    }
+
     pub(crate) async fn sync_clock(&self) {
         let client_guard = self.client.lock().await;
         if let Some(client) = &*client_guard {
@@ -548,6 +744,38 @@ where
         }
     }
 
+    // grow the vm memory by the sum of container memory limits on top of the sandbox memory,
+    // container is still able to run in a vm with less memory, so it is not an error if failed.
+    async fn update_vm_memory(&mut self) {
+        let memory_in_bytes = self
+            .containers
+            .values()
+            .filter_map(|c| c.data.spec.as_ref())
+            .filter_map(|s| s.linux.as_ref())
+            .filter_map(|l| l.resources.as_ref())
+            .filter_map(|r| r.memory.as_ref())
+            .filter_map(|m| m.limit)
+            .sum::<u64>();
+        if memory_in_bytes == 0 {
+            return;
+        }
+        // tmpfs of the emptyDir volumes is charged to the guest memory
+        let memory_in_bytes = memory_in_bytes + self.memory_volumes_size();
+        let memory_in_mb = (memory_in_bytes + bytefmt::MIB - 1) / bytefmt::MIB;
+        match self.vm.resize_memory(memory_in_mb).await {
+            Ok(_) => {}
+            Err(Error::Unimplemented(e)) => {
+                debug!("skip resizing memory of sandbox {}: {}", self.id, e);
+            }
+            Err(e) => {
+                warn!(
+                    "failed to resize memory of sandbox {} to {}M: {:?}",
+                    self.id, memory_in_mb, e
+                );
+            }
+        }
+    }
+
     async fn setup_sandbox_files(&self) -> Result<()> {
         let shared_path = self.get_sandbox_shared_path();
         create_dir_all(&shared_path)
@@ -600,6 +828,17 @@ where
     pub fn get_sandbox_shared_path(&self) -> String {
         format!("{}/{}", self.base_dir, SHARED_DIR_SUFFIX)
     }
+
+    /// Host path of the checkpoint image of the container, which the guest writes to when the
+    /// container is checkpointed, and restores the container from if it is created from a checkpoint.
+    pub fn get_checkpoint_path(&self, container_id: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.get_sandbox_shared_path(),
+            CHECKPOINT_DIR,
+            container_id
+        )
+    }
 }
 
 // parse_dnsoptions parse DNS options into resolv.conf format content,
@@ -630,6 +869,29 @@ fn parse_dnsoptions(
 pub struct SandboxConfig {
     #[serde(default)]
     pub log_level: String,
+    /// Image paths that a pod is allowed to choose by the "io.kuasar.hypervisor.image" annotation
+    #[serde(default)]
+    pub valid_image_paths: Vec<String>,
+    /// Interval in seconds to reconcile the storages of running sandboxes, 0 to disable it
+    #[serde(default = "default_storage_reconcile_interval")]
+    pub storage_reconcile_interval: u64,
+    /// Where the logs of the guest agent go: "sandboxer" (default), "file" or "none"
+    #[serde(default)]
+    pub task_log_output: String,
+    /// Kernel modules that a pod is allowed to load by the "io.kuasar.kernel_modules" annotation
+    #[serde(default)]
+    pub allowed_kernel_modules: Vec<String>,
+    /// The "io.kuasar.hypervisor.kernel_params" and "io.kuasar.hypervisor.enable_debug"
+    /// annotations that a pod is allowed to set, none of them is allowed by default
+    #[serde(default)]
+    pub allowed_annotations: Vec<String>,
+    /// Unix socket to serve the SandboxerService on, like volume resize, empty to disable it
+    #[serde(default)]
+    pub api_address: String,
+}
+
+fn default_storage_reconcile_interval() -> u64 {
+    60
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -680,6 +942,44 @@ fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
     });
 }
 
+// reconcile the storages periodically until the sandbox is not running
+fn reconcile<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>, interval: u64) {
+    if interval == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        // what can not be repaired is reported again and again, only warn when it changes
+        let mut last_report = StorageReport::default();
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            let mut sandbox = sandbox_mutex.lock().await;
+            if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                return;
+            }
+            if sandbox.paused {
+                continue;
+            }
+            match sandbox.reconcile_storages().await {
+                Ok(report) => {
+                    if report != last_report && !report.is_empty() {
+                        warn!(
+                            "storages of sandbox {} reconciled: {:?}",
+                            sandbox.id, report
+                        );
+                    }
+                    if report.is_repaired() {
+                        sandbox.dump().await.unwrap_or_else(|e| {
+                            warn!("failed to dump sandbox {}, {:?}", sandbox.id, e);
+                        });
+                    }
+                    last_report = report;
+                }
+                Err(e) => warn!("failed to reconcile storages of {}, {:?}", sandbox.id, e),
+            }
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     mod dns {
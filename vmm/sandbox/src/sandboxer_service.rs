@@ -0,0 +1,138 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use containerd_sandbox::error::Error;
+use tokio::sync::{Mutex, RwLock};
+use ttrpc::{r#async::TtrpcContext, Code};
+use vmm_common::api::{empty::Empty, sandbox::SandboxStats, sandboxer::*, sandboxer_ttrpc};
+
+use crate::{sandbox::KuasarSandbox, vm::VM};
+
+type Sandboxes<V> = Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<V>>>>>>;
+
+/// The operations on sandboxes that are not in the sandbox api of containerd,
+/// served by the sandboxer on a unix socket of the host.
+pub struct SandboxerService<V> {
+    sandboxes: Sandboxes<V>,
+}
+
+impl<V> SandboxerService<V> {
+    pub(crate) fn new(sandboxes: Sandboxes<V>) -> Self {
+        Self { sandboxes }
+    }
+
+    async fn sandbox(&self, id: &str) -> ttrpc::Result<Arc<Mutex<KuasarSandbox<V>>>> {
+        self.sandboxes
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| ttrpc_error(Error::NotFound(id.to_string())))
+    }
+}
+
+#[async_trait]
+impl<V> sandboxer_ttrpc::SandboxerService for SandboxerService<V>
+where
+    V: VM + Sync + Send + 'static,
+{
+    async fn pause_sandbox(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SandboxRequest,
+    ) -> ttrpc::Result<Empty> {
+        let sandbox_mutex = self.sandbox(&req.sandbox_id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.pause().await.map_err(ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn resume_sandbox(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SandboxRequest,
+    ) -> ttrpc::Result<Empty> {
+        let sandbox_mutex = self.sandbox(&req.sandbox_id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.resume().await.map_err(ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn get_sandbox_stats(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SandboxRequest,
+    ) -> ttrpc::Result<SandboxStats> {
+        let sandbox_mutex = self.sandbox(&req.sandbox_id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.stats().await.map_err(ttrpc_error)
+    }
+
+    async fn set_task_log_level(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SetTaskLogLevelRequest,
+    ) -> ttrpc::Result<Empty> {
+        let sandbox_mutex = self.sandbox(&req.sandbox_id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox
+            .set_task_log_level(&req.level)
+            .await
+            .map_err(ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn resize_sandbox_volume(
+        &self,
+        _ctx: &TtrpcContext,
+        req: ResizeSandboxVolumeRequest,
+    ) -> ttrpc::Result<Empty> {
+        let sandbox_mutex = self.sandbox(&req.sandbox_id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox
+            .resize_volume(&req.host_path, req.size)
+            .await
+            .map_err(ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn get_sandbox_volume_stats(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SandboxRequest,
+    ) -> ttrpc::Result<GetSandboxVolumeStatsResponse> {
+        let sandbox_mutex = self.sandbox(&req.sandbox_id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        let mut resp = GetSandboxVolumeStatsResponse::new();
+        resp.stats = sandbox.volume_stats().await.map_err(ttrpc_error)?;
+        Ok(resp)
+    }
+}
+
+fn ttrpc_error(e: Error) -> ttrpc::Error {
+    let code = match e {
+        Error::NotFound(_) => Code::NOT_FOUND,
+        Error::AlreadyExist(_) => Code::ALREADY_EXISTS,
+        Error::InvalidArgument(_) => Code::INVALID_ARGUMENT,
+        Error::Unimplemented(_) => Code::UNIMPLEMENTED,
+        Error::ResourceExhausted(_) => Code::RESOURCE_EXHAUSTED,
+        _ => Code::INTERNAL,
+    };
+    ttrpc::Error::RpcStatus(ttrpc::get_status(code, e.to_string()))
+}
@@ -0,0 +1,277 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use tokio::io::AsyncReadExt;
+
+// the btrfs superblock is the farthest one from the start of the device
+pub const PROBE_SIZE: u64 = BTRFS_MAGIC_OFFSET as u64 + 4096;
+
+const EXT_SUPERBLOCK_OFFSET: usize = 1024;
+const EXT_MAGIC_OFFSET: usize = EXT_SUPERBLOCK_OFFSET + 0x38;
+const EXT_MAGIC: &[u8] = &[0x53, 0xef];
+const EXT_FEATURE_COMPAT_OFFSET: usize = EXT_SUPERBLOCK_OFFSET + 0x5c;
+const EXT_FEATURE_INCOMPAT_OFFSET: usize = EXT_SUPERBLOCK_OFFSET + 0x60;
+const EXT_FEATURE_RO_COMPAT_OFFSET: usize = EXT_SUPERBLOCK_OFFSET + 0x64;
+const EXT3_FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x4;
+const EXT3_FEATURE_INCOMPAT_JOURNAL_DEV: u32 = 0x8;
+// extents, 64bit, mmp, flex_bg, ea_inode, dirdata, csum_seed, large_dir, inline_data, encrypt
+const EXT4_FEATURE_INCOMPAT: u32 =
+    0x40 | 0x80 | 0x100 | 0x200 | 0x400 | 0x1000 | 0x2000 | 0x4000 | 0x8000 | 0x10000;
+// huge_file, gdt_csum, dir_nlink, extra_isize, quota, bigalloc, metadata_csum
+const EXT4_FEATURE_RO_COMPAT: u32 = 0x8 | 0x10 | 0x20 | 0x40 | 0x100 | 0x200 | 0x400;
+
+const XFS_MAGIC: &[u8] = b"XFSB";
+const BTRFS_MAGIC_OFFSET: usize = 0x10040;
+const BTRFS_MAGIC: &[u8] = b"_BHRfS_M";
+const EROFS_MAGIC_OFFSET: usize = 1024;
+const EROFS_MAGIC: &[u8] = &[0xe2, 0xe1, 0xf5, 0xe0];
+const SQUASHFS_MAGIC: &[u8] = b"hsqs";
+
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: &[u8] = &[0x55, 0xaa];
+const FAT_TYPE_OFFSET: usize = 0x36;
+const FAT32_TYPE_OFFSET: usize = 0x52;
+const MBR_PARTITION_TABLE_OFFSET: usize = 446;
+const MBR_PARTITION_ENTRY_SIZE: usize = 16;
+const MBR_PARTITION_ENTRIES: usize = 4;
+const MBR_PARTITION_TYPE_OFFSET: usize = 4;
+const GPT_HEADER_OFFSET: usize = 512;
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+
+const PARTITION_TABLE_TYPES: &[&str] = &["gpt", "dos"];
+
+/// Probe the filesystem type or the partition table type from the first bytes of a device,
+/// the names are the same as the TYPE or PTTYPE returned by blkid.
+pub fn probe_fstype(buf: &[u8]) -> Option<&'static str> {
+    if has_magic(buf, 0, XFS_MAGIC) {
+        return Some("xfs");
+    }
+    if has_magic(buf, 0, SQUASHFS_MAGIC) {
+        return Some("squashfs");
+    }
+    if has_magic(buf, EROFS_MAGIC_OFFSET, EROFS_MAGIC) {
+        return Some("erofs");
+    }
+    if has_magic(buf, EXT_MAGIC_OFFSET, EXT_MAGIC) {
+        return Some(probe_ext(buf));
+    }
+    if has_magic(buf, BTRFS_MAGIC_OFFSET, BTRFS_MAGIC) {
+        return Some("btrfs");
+    }
+    if has_magic(buf, BOOT_SIGNATURE_OFFSET, BOOT_SIGNATURE) {
+        if has_magic(buf, FAT_TYPE_OFFSET, b"FAT") || has_magic(buf, FAT32_TYPE_OFFSET, b"FAT32   ")
+        {
+            return Some("vfat");
+        }
+        if has_magic(buf, GPT_HEADER_OFFSET, GPT_SIGNATURE) {
+            return Some("gpt");
+        }
+        // other boot sectors, like ntfs or exfat, have the signature too,
+        // they are left to blkid if the partition entries are not valid.
+        if is_valid_mbr(buf) {
+            return Some("dos");
+        }
+    }
+    None
+}
+
+pub fn is_partition_table(fstype: &str) -> bool {
+    PARTITION_TABLE_TYPES.contains(&fstype)
+}
+
+pub async fn probe_fstype_of<P: AsRef<Path>>(path: P) -> Result<Option<&'static str>> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| anyhow!("failed to open {}, {}", path.display(), e))?;
+    let mut buf = vec![];
+    file.take(PROBE_SIZE)
+        .read_to_end(&mut buf)
+        .await
+        .map_err(|e| anyhow!("failed to read {}, {}", path.display(), e))?;
+    Ok(probe_fstype(&buf))
+}
+
+fn probe_ext(buf: &[u8]) -> &'static str {
+    let compat = read_u32_le(buf, EXT_FEATURE_COMPAT_OFFSET);
+    let incompat = read_u32_le(buf, EXT_FEATURE_INCOMPAT_OFFSET);
+    let ro_compat = read_u32_le(buf, EXT_FEATURE_RO_COMPAT_OFFSET);
+    if incompat & EXT3_FEATURE_INCOMPAT_JOURNAL_DEV != 0 {
+        return "jbd";
+    }
+    if incompat & EXT4_FEATURE_INCOMPAT != 0 || ro_compat & EXT4_FEATURE_RO_COMPAT != 0 {
+        return "ext4";
+    }
+    if compat & EXT3_FEATURE_COMPAT_HAS_JOURNAL != 0 {
+        return "ext3";
+    }
+    "ext2"
+}
+
+// the boot indicator of every partition entry is 0x00 or 0x80, and at least one entry is used
+fn is_valid_mbr(buf: &[u8]) -> bool {
+    let mut used = false;
+    for i in 0..MBR_PARTITION_ENTRIES {
+        let offset = MBR_PARTITION_TABLE_OFFSET + i * MBR_PARTITION_ENTRY_SIZE;
+        match buf.get(offset..offset + MBR_PARTITION_ENTRY_SIZE) {
+            Some(entry) if entry[0] == 0x00 || entry[0] == 0x80 => {
+                used |= entry[MBR_PARTITION_TYPE_OFFSET] != 0;
+            }
+            _ => return false,
+        }
+    }
+    used
+}
+
+fn has_magic(buf: &[u8], offset: usize, magic: &[u8]) -> bool {
+    buf.get(offset..offset + magic.len()) == Some(magic)
+}
+
+fn read_u32_le(buf: &[u8], offset: usize) -> u32 {
+    buf.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::{is_partition_table, probe_fstype, probe_fstype_of, PROBE_SIZE};
+
+    // build a small image with the given bytes written at the offsets
+    fn image(size: usize, patches: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut buf = vec![0u8; size];
+        for (offset, bytes) in patches {
+            buf[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        buf
+    }
+
+    fn ext(compat: u32, incompat: u32, ro_compat: u32) -> Vec<u8> {
+        image(
+            4096,
+            &[
+                (1024 + 0x38, &[0x53, 0xef]),
+                (1024 + 0x5c, &compat.to_le_bytes()),
+                (1024 + 0x60, &incompat.to_le_bytes()),
+                (1024 + 0x64, &ro_compat.to_le_bytes()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_probe_fstype() {
+        let cases: Vec<(&str, Vec<u8>, Option<&str>)> = vec![
+            ("empty", vec![], None),
+            ("zeroed", image(PROBE_SIZE as usize, &[]), None),
+            ("ext2", ext(0, 0x2, 0x1), Some("ext2")),
+            ("ext3", ext(0x4, 0x2, 0x1), Some("ext3")),
+            ("ext4 extents", ext(0x4, 0x2 | 0x40, 0x1), Some("ext4")),
+            ("ext4 metadata_csum", ext(0x4, 0x2, 0x400), Some("ext4")),
+            ("ext journal device", ext(0, 0x8, 0), Some("jbd")),
+            ("xfs", image(4096, &[(0, b"XFSB")]), Some("xfs")),
+            (
+                "btrfs",
+                image(PROBE_SIZE as usize, &[(0x10040, b"_BHRfS_M")]),
+                Some("btrfs"),
+            ),
+            (
+                "btrfs truncated",
+                image(0x10040 + 4, &[(0x10040, b"_BHR")]),
+                None,
+            ),
+            (
+                "erofs",
+                image(4096, &[(1024, &[0xe2, 0xe1, 0xf5, 0xe0])]),
+                Some("erofs"),
+            ),
+            ("squashfs", image(4096, &[(0, b"hsqs")]), Some("squashfs")),
+            (
+                "fat16",
+                image(512, &[(0x36, b"FAT16   "), (510, &[0x55, 0xaa])]),
+                Some("vfat"),
+            ),
+            (
+                "fat32",
+                image(512, &[(0x52, b"FAT32   "), (510, &[0x55, 0xaa])]),
+                Some("vfat"),
+            ),
+            (
+                "fat without signature",
+                image(512, &[(0x36, b"FAT16   ")]),
+                None,
+            ),
+            (
+                "dos",
+                image(
+                    1024,
+                    &[(446, &[0x80]), (450, &[0x83]), (510, &[0x55, 0xaa])],
+                ),
+                Some("dos"),
+            ),
+            (
+                "dos without partitions",
+                image(1024, &[(510, &[0x55, 0xaa])]),
+                None,
+            ),
+            (
+                "ntfs",
+                image(
+                    1024,
+                    &[(3, b"NTFS    "), (446, &[0x33, 0xc0]), (510, &[0x55, 0xaa])],
+                ),
+                None,
+            ),
+            (
+                "gpt",
+                image(1024, &[(510, &[0x55, 0xaa]), (512, b"EFI PART")]),
+                Some("gpt"),
+            ),
+        ];
+        for (name, buf, expected) in cases {
+            assert_eq!(probe_fstype(&buf), expected, "case {}", name);
+        }
+    }
+
+    #[test]
+    fn test_is_partition_table() {
+        assert!(is_partition_table("gpt"));
+        assert!(is_partition_table("dos"));
+        assert!(!is_partition_table("ext4"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_fstype_of() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("erofs.img");
+        let buf = image(8192, &[(1024, &[0xe2, 0xe1, 0xf5, 0xe0])]);
+        tokio::fs::write(&path, &buf).await.unwrap();
+        assert_eq!(probe_fstype_of(&path).await.unwrap(), Some("erofs"));
+
+        let path = tmp_dir.path().join("unknown.img");
+        tokio::fs::write(&path, image(8192, &[])).await.unwrap();
+        assert_eq!(probe_fstype_of(&path).await.unwrap(), None);
+
+        assert!(probe_fstype_of(tmp_dir.path().join("missing.img"))
+            .await
+            .is_err());
+    }
+}
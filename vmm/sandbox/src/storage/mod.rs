@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, os::unix::fs::PermissionsExt, path::Path};
 
 use anyhow::anyhow;
 use containerd_sandbox::{
@@ -22,26 +22,48 @@ use containerd_sandbox::{
     spec::Mount,
 };
 use containerd_shim::mount::mount_rootfs;
-use log::debug;
+use log::{debug, warn};
 use nix::libc::MNT_DETACH;
 pub use utils::*;
 use vmm_common::{
+    api::sandbox::VolumeStats,
     mount::{bind_mount, unmount, MNT_NOFOLLOW},
-    storage::{Storage, DRIVEREPHEMERALTYPE},
+    storage::{
+        mount_source, Storage, DRIVEREPHEMERALTYPE, DRIVERLOCALTYPE, DRIVEROVERLAYFSTYPE,
+        LAYER_OPTION_PREFIX, LOCAL_STORAGE_DIR,
+    },
     KUASAR_STATE_DIR,
 };
 
 use crate::{
+    client::{client_get_volume_stats, client_resize_volume},
     device::{BlockDeviceInfo, DeviceInfo},
     sandbox::KUASAR_GUEST_SHARE_DIR,
-    storage::mount::{get_mount_info, is_bind, is_bind_shm, is_overlay},
+    storage::mount::{
+        get_block_layers, get_mount_info, is_bind, is_bind_shm, is_block_image, is_empty_dir,
+        is_overlay, tmpfs_size_in_bytes,
+    },
+    utils::get_host_memory_in_mb,
     vm::{BlockDriver, VM},
     KuasarSandbox,
 };
 
+pub mod fstype;
 pub mod mount;
+pub mod reconcile;
 pub mod utils;
 
+pub(crate) const STORAGE_ID_PREFIX: &str = "storage";
+pub(crate) const STORAGE_DEVICE_ID_PREFIX: &str = "blk";
+
+// the image of the writable layer of an overlay assembled in the guest, it is sparse
+// so only the data written by the container takes the disk space.
+const UPPER_IMAGE_NAME: &str = "kuasar-upper.img";
+const UPPER_IMAGE_SIZE_IN_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+// the image of the scratch disk of an emptyDir on disk, it is sparse as well.
+const SCRATCH_IMAGE_NAME: &str = "kuasar-scratch.img";
+const SCRATCH_IMAGE_SIZE_IN_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
 impl<V> KuasarSandbox<V>
 where
     V: VM + Sync + Send,
@@ -52,13 +74,13 @@ where
             return Ok(());
         }
 
-        let id = format!("storage{}", self.increment_and_get_id());
+        let id = format!("{}{}", STORAGE_ID_PREFIX, self.increment_and_get_id());
         debug!(
             "attach storage to container {} for mount {:?} with id {}",
             container_id, m, id
         );
 
-        if is_block_device(&*m.source).await? {
+        if is_block_device(&*m.source).await? || is_block_image(m).await? {
             self.handle_block_device(&id, container_id, m).await?;
             return Ok(());
         }
@@ -74,23 +96,66 @@ where
             return Ok(());
         }
 
+        if is_empty_dir(m) {
+            self.handle_empty_dir_mount(&id, container_id, m).await?;
+            return Ok(());
+        }
+
         if is_bind(m) {
             self.handle_bind_mount(&id, container_id, m).await?;
             return Ok(());
         }
 
         if is_overlay(m) {
-            self.handle_overlay_mount(&id, container_id, m).await?;
+            if let Some(layers) = get_block_layers(m).await? {
+                self.handle_block_overlay_mount(&id, container_id, m, layers)
+                    .await?;
+            } else {
+                self.handle_overlay_mount(&id, container_id, m).await?;
+            }
             return Ok(());
         }
 
         Ok(())
     }
 
+    /// The overlay storages saved by the older versions take the source of the mount, which is
+    /// always "overlay", as the host source, take the upperdir or lowerdir from the overlay mounted
+    /// in the shared dir as the host source instead, so that the storages can be told apart.
+    pub async fn upgrade_overlay_storages(&mut self) {
+        let shared_path = self.get_sandbox_shared_path();
+        for s in self
+            .storages
+            .iter_mut()
+            .filter(|s| is_overlay_storage(s) && s.host_source == "overlay")
+        {
+            let mount_point = format!("{}/{}", shared_path, s.id);
+            match get_mount_info(&mount_point).await {
+                Ok(Some(mi)) if mi.fs_type == "overlay" => {
+                    s.host_source = mount_source(&Mount {
+                        destination: "".to_string(),
+                        r#type: mi.fs_type,
+                        source: mi.device,
+                        options: mi.options,
+                    });
+                }
+                Ok(_) => warn!("no overlay mounted on {} of storage {}", mount_point, s.id),
+                Err(e) => warn!("failed to get mount info of {}, {}", mount_point, e),
+            }
+        }
+    }
+
     pub async fn deference_storage(&mut self, container_id: &str, m: &Mount) -> Result<()> {
+        let mut layer_ids = vec![];
         for s in &mut self.storages {
             if s.is_for_mount(m) {
                 s.defer(container_id);
+                layer_ids.extend(layer_storage_ids(s));
+            }
+        }
+        for s in &mut self.storages {
+            if layer_ids.contains(&s.id) {
+                s.defer(container_id);
             }
         }
         self.gc_storages().await?;
@@ -107,7 +172,11 @@ where
         } else {
             m.source.clone()
         };
-        let device_id = format!("blk{}", self.increment_and_get_id());
+        let device_id = format!(
+            "{}{}",
+            STORAGE_DEVICE_ID_PREFIX,
+            self.increment_and_get_id()
+        );
         let (bus_type, addr) = self
             .vm
             .hot_attach(DeviceInfo::Block(BlockDeviceInfo {
@@ -140,6 +209,7 @@ where
             fstype: get_fstype(&source).await?,
             options,
             mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, id),
+            host_options: vec![],
         };
 
         storage.refer(container_id);
@@ -190,6 +260,7 @@ where
             fstype: "bind".to_string(),
             options: vec![],
             mount_point: format!("{}/{}", KUASAR_STATE_DIR, &storage_id),
+            host_options: m.options.clone(),
         };
 
         storage.refer(container_id);
@@ -216,7 +287,7 @@ where
             .map_err(|e| anyhow!("mount rootfs: {}", e))?;
 
         let mut storage = Storage {
-            host_source: m.source.clone(),
+            host_source: mount_source(m),
             r#type: m.r#type.clone(),
             id: storage_id.to_string(),
             device_id: None,
@@ -228,6 +299,7 @@ where
             fstype: "bind".to_string(),
             options: vec![],
             mount_point: format!("{}/{}", KUASAR_STATE_DIR, &storage_id),
+            host_options: m.options.clone(),
         };
 
         storage.refer(container_id);
@@ -235,6 +307,124 @@ where
         Ok(())
     }
 
+    // attach the lower layers as readonly block devices, and the overlay is mounted in guest,
+    // with the upper layer on a writable image next to the upperdir on the host.
+    async fn handle_block_overlay_mount(
+        &mut self,
+        storage_id: &str,
+        container_id: &str,
+        m: &Mount,
+        layers: Vec<MountInfo>,
+    ) -> Result<()> {
+        let mut layer_ids = vec![];
+        let mut lower_dirs = vec![];
+        for layer in layers {
+            let layer_mount = Mount {
+                destination: "".to_string(),
+                r#type: layer.fs_type.to_string(),
+                source: layer.device.to_string(),
+                options: vec!["ro".to_string()],
+            };
+            let res = match self
+                .storages
+                .iter_mut()
+                .find(|s| s.is_for_mount(&layer_mount))
+            {
+                // the layer may be shared with other containers
+                Some(s) => {
+                    s.refer(container_id);
+                    Ok(s.id.to_string())
+                }
+                None => {
+                    let id = format!("{}{}", STORAGE_ID_PREFIX, self.increment_and_get_id());
+                    self.handle_block_device(&id, container_id, &layer_mount)
+                        .await
+                        .map(|_| id)
+                }
+            };
+            match res {
+                Ok(id) => {
+                    lower_dirs.push(format!("{}{}", KUASAR_GUEST_SHARE_DIR, id));
+                    layer_ids.push(id);
+                }
+                Err(e) => {
+                    for s in &mut self.storages {
+                        if layer_ids.contains(&s.id) {
+                            s.defer(container_id);
+                        }
+                    }
+                    self.gc_storages().await.unwrap_or_default();
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut options = vec![format!("lowerdir={}", lower_dirs.join(":"))];
+        if let Some(upper_dir) = m.options.iter().find_map(|o| o.strip_prefix("upperdir=")) {
+            match self.attach_upper_image(container_id, upper_dir).await {
+                Ok(id) => {
+                    let upper_mount_point = format!("{}{}", KUASAR_GUEST_SHARE_DIR, id);
+                    options.push(format!("upperdir={}/upper", upper_mount_point));
+                    options.push(format!("workdir={}/work", upper_mount_point));
+                    layer_ids.push(id);
+                }
+                Err(e) => {
+                    for s in &mut self.storages {
+                        if layer_ids.contains(&s.id) {
+                            s.defer(container_id);
+                        }
+                    }
+                    self.gc_storages().await.unwrap_or_default();
+                    return Err(e);
+                }
+            }
+        } else {
+            options.push("ro".to_string());
+        }
+
+        let mut storage = Storage {
+            host_source: mount_source(m),
+            r#type: m.r#type.clone(),
+            id: storage_id.to_string(),
+            device_id: None,
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: "overlay".to_string(),
+            driver: DRIVEROVERLAYFSTYPE.to_string(),
+            driver_options: layer_ids
+                .iter()
+                .map(|id| format!("{}{}", LAYER_OPTION_PREFIX, id))
+                .collect(),
+            fstype: "overlay".to_string(),
+            options,
+            mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
+            host_options: vec![],
+        };
+        storage.refer(container_id);
+        self.storages.push(storage);
+        Ok(())
+    }
+
+    // the writable layer is an ext4 image in the snapshot dir of the upperdir, so it is kept on
+    // the disk of the host and removed with the snapshot.
+    async fn attach_upper_image(&mut self, container_id: &str, upper_dir: &str) -> Result<String> {
+        let snapshot_dir = Path::new(upper_dir).parent().ok_or_else(|| {
+            Error::InvalidArgument(format!("no parent dir of upperdir {}", upper_dir))
+        })?;
+        let image = snapshot_dir.join(UPPER_IMAGE_NAME).display().to_string();
+        create_ext4_image(&image, UPPER_IMAGE_SIZE_IN_BYTES).await?;
+        let upper_mount = Mount {
+            destination: "".to_string(),
+            r#type: "ext4".to_string(),
+            source: image,
+            options: vec![],
+        };
+        let id = format!("{}{}", STORAGE_ID_PREFIX, self.increment_and_get_id());
+        self.handle_block_device(&id, container_id, &upper_mount)
+            .await?;
+        Ok(id)
+    }
+
     async fn handle_tmpfs_mount(
         &mut self,
         storage_id: &str,
@@ -255,18 +445,92 @@ where
             fstype: "tmpfs".to_string(),
             options: vec![],
             mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
+            host_options: vec![],
         };
-        // only handle size option because other options may not supported in guest
-        for o in &mount_info.options {
-            if o.starts_with("size=") {
-                storage.options.push(o.to_string());
+        // only handle size option because other options may not supported in guest, and a tmpfs
+        // mounted without a size is half of the host memory, only an explicit size, such as the
+        // sizeLimit of an emptyDir, is passed to the guest and charged to the guest memory.
+        let default_size = get_host_memory_in_mb().await? * bytefmt::MIB / 2;
+        if let Some(size) = tmpfs_size_in_bytes(&mount_info.options) {
+            if size + bytefmt::MIB < default_size {
+                storage.options.push(format!("size={}", size));
             }
         }
         storage.refer(container_id);
+        // emptyDir is shared by all containers of the pod, keep it until the sandbox stops
+        if is_empty_dir(m) {
+            storage.refer(&self.id);
+        }
+        self.storages.push(storage);
+        Ok(())
+    }
+
+    // emptyDir on disk is realised as a local dir in the guest instead of the shared dir, the dir
+    // is on a scratch disk, which is a sparse image in the emptyDir dir on the host, so the disk
+    // usage is still charged to the emptyDir by kubelet.
+    async fn handle_empty_dir_mount(
+        &mut self,
+        storage_id: &str,
+        container_id: &str,
+        m: &Mount,
+    ) -> Result<()> {
+        let mode = tokio::fs::metadata(&m.source)
+            .await
+            .map_err(|e| anyhow!("failed to get metadata of {}, {}", m.source, e))?
+            .permissions()
+            .mode()
+            & 0o7777;
+        let image = Path::new(&m.source)
+            .join(SCRATCH_IMAGE_NAME)
+            .display()
+            .to_string();
+        create_ext4_image(&image, SCRATCH_IMAGE_SIZE_IN_BYTES).await?;
+        let scratch_mount = Mount {
+            destination: "".to_string(),
+            r#type: "ext4".to_string(),
+            source: image,
+            options: vec![],
+        };
+        // the scratch disk lives as long as the sandbox, like the emptyDir
+        let scratch_id = format!("{}{}", STORAGE_ID_PREFIX, self.increment_and_get_id());
+        let sandbox_id = self.id.to_string();
+        self.handle_block_device(&scratch_id, &sandbox_id, &scratch_mount)
+            .await?;
+        // the guest only creates the dirs of local storages in the local storage dir
+        let scratch_dir = format!("{}/{}", LOCAL_STORAGE_DIR, scratch_id);
+        if let Some(scratch) = self.storages.iter_mut().find(|s| s.id == scratch_id) {
+            scratch.mount_point = scratch_dir.clone();
+        }
+        let mut storage = Storage {
+            host_source: m.source.clone(),
+            r#type: m.r#type.clone(),
+            id: storage_id.to_string(),
+            device_id: None,
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: scratch_dir,
+            driver: DRIVERLOCALTYPE.to_string(),
+            driver_options: vec![format!("{}{}", LAYER_OPTION_PREFIX, scratch_id)],
+            fstype: "local".to_string(),
+            options: vec![format!("mode={:o}", mode)],
+            mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
+            host_options: vec![],
+        };
+        storage.refer(container_id);
+        storage.refer(&self.id);
         self.storages.push(storage);
         Ok(())
     }
 
+    /// Total size of the emptyDir volumes backed by the guest memory.
+    pub(crate) fn memory_volumes_size(&self) -> u64 {
+        self.storages
+            .iter()
+            .filter(|s| s.driver == DRIVEREPHEMERALTYPE && s.ref_container.contains_key(&self.id))
+            .filter_map(|s| tmpfs_size_in_bytes(&s.options))
+            .sum()
+    }
+
     async fn gc_storages(&mut self) -> Result<()> {
         let storage_infos: Vec<(Option<String>, String, String)> = self
             .storages
@@ -274,12 +538,17 @@ where
             .filter(|&x| x.ref_count() == 0)
             .map(|s| (s.device_id.clone(), s.id.clone(), s.fstype.clone()))
             .collect();
+        // detach all of them even if some failed, the failed ones are kept to be released later
+        let mut res = Ok(());
         for info in storage_infos {
-            self.detach_storage(info.0.clone(), &info.1, &info.2)
-                .await?;
+            if let Err(e) = self.detach_storage(info.0.clone(), &info.1, &info.2).await {
+                warn!("failed to detach storage {}: {:?}", info.1, e);
+                res = Err(e);
+                continue;
+            }
             self.storages.retain(|x| x.id != info.1);
         }
-        Ok(())
+        res
     }
 
     async fn detach_storage(
@@ -306,6 +575,58 @@ where
         Ok(())
     }
 
+    /// Grow the block device of the storage whose host source is host_path to size in bytes,
+    /// and grow the filesystem of it in the guest.
+    pub async fn resize_volume(&mut self, host_path: &str, size: u64) -> Result<()> {
+        let storage = self
+            .storages
+            .iter()
+            .find(|s| s.host_source == host_path)
+            .ok_or_else(|| Error::NotFound(format!("no storage of volume {}", host_path)))?;
+        let device_id = storage.device_id.clone().ok_or_else(|| {
+            Error::InvalidArgument(format!("volume {} is not a block device", host_path))
+        })?;
+        let mount_point = storage.mount_point.to_string();
+
+        self.vm.resize_block_device(&device_id, size).await?;
+        self.init_client().await?;
+        let client_guard = self.client.lock().await;
+        if let Some(client) = &*client_guard {
+            client_resize_volume(client, &mount_point, size).await?;
+        }
+        Ok(())
+    }
+
+    /// Stats of the volumes mounted in the guest, keyed by the host path of the volume,
+    /// as the usage of them can not be seen on the host.
+    pub async fn volume_stats(&mut self) -> Result<HashMap<String, VolumeStats>> {
+        let guest_storages: HashMap<String, String> = self
+            .storages
+            .iter()
+            .filter(|s| s.need_guest_handle)
+            .map(|s| (s.mount_point.to_string(), s.host_source.to_string()))
+            .collect();
+        if guest_storages.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        self.init_client().await?;
+        let client_guard = self.client.lock().await;
+        let client = client_guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("sandbox client is not init"))?;
+        let stats =
+            client_get_volume_stats(client, guest_storages.keys().cloned().collect()).await?;
+        Ok(stats
+            .into_iter()
+            .filter_map(|s| {
+                guest_storages
+                    .get(&s.mount_point)
+                    .map(|host_path| (host_path.to_string(), s))
+            })
+            .collect())
+    }
+
     pub async fn deference_container_storages(&mut self, container_id: &str) -> Result<()> {
         for storage in self.storages.iter_mut() {
             if storage.ref_container.contains_key(container_id) {
@@ -317,6 +638,20 @@ where
     }
 }
 
+// the overlay mounted on the host and shared to the guest
+fn is_overlay_storage(storage: &Storage) -> bool {
+    storage.r#type == "overlay" && storage.fstype == "bind"
+}
+
+/// Ids of the storages that the storage is assembled from, they are mounted in the guest before it.
+pub fn layer_storage_ids(storage: &Storage) -> Vec<String> {
+    storage
+        .driver_options
+        .iter()
+        .filter_map(|o| o.strip_prefix(LAYER_OPTION_PREFIX).map(|x| x.to_string()))
+        .collect()
+}
+
 pub struct MountInfo {
     pub device: String,
     pub mount_point: String,
@@ -18,7 +18,14 @@ use anyhow::anyhow;
 use containerd_sandbox::{error::Result, spec::Mount};
 use vmm_common::DEV_SHM;
 
-use crate::{storage::MountInfo, utils::read_file};
+use crate::{
+    storage::{is_block_device, is_regular_file, MountInfo},
+    utils::read_file,
+};
+
+// filesystems of image layers that can be passed through to the guest as block devices
+const BLOCK_LAYER_FSTYPES: [&str; 3] = ["erofs", "ext4", "xfs"];
+const K8S_EMPTY_DIR: &str = "/volumes/kubernetes.io~empty-dir/";
 
 pub fn is_bind_shm(m: &Mount) -> bool {
     is_bind(m) && m.destination == DEV_SHM
@@ -28,6 +35,24 @@ pub fn is_bind(m: &Mount) -> bool {
     m.r#type == "bind" && !m.source.is_empty()
 }
 
+/// The emptyDir volume of kubernetes, which is a bind mount of a dir in the pod dir of kubelet,
+/// the volume with medium "Memory" is a tmpfs mounted on the dir.
+pub fn is_empty_dir(m: &Mount) -> bool {
+    is_bind(m) && m.source.contains(K8S_EMPTY_DIR)
+}
+
+/// The size of a tmpfs in bytes from its "size=" mount option.
+pub fn tmpfs_size_in_bytes(options: &[String]) -> Option<u64> {
+    let size = options.iter().find_map(|o| o.strip_prefix("size="))?;
+    let (num, unit) = match size.char_indices().last()? {
+        (i, 'k') | (i, 'K') => (&size[..i], 1 << 10),
+        (i, 'm') | (i, 'M') => (&size[..i], 1 << 20),
+        (i, 'g') | (i, 'G') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    num.parse::<u64>().ok().map(|n| n * unit)
+}
+
 pub fn is_overlay(m: &Mount) -> bool {
     m.r#type == "overlay"
 }
@@ -58,3 +83,127 @@ pub async fn get_mount_info(mount_point: &str) -> Result<Option<MountInfo>> {
     }
     Ok(None)
 }
+
+/// A single layer image file, like the erofs layer of a readonly snapshot,
+/// which can be attached as a readonly block device.
+pub async fn is_block_image(m: &Mount) -> Result<bool> {
+    if !BLOCK_LAYER_FSTYPES.contains(&m.r#type.as_str()) || m.source.is_empty() {
+        return Ok(false);
+    }
+    if !m.options.iter().any(|o| o == "ro") {
+        return Ok(false);
+    }
+    Ok(is_regular_file(&m.source).await.unwrap_or_default())
+}
+
+/// Get the block devices of the lower layers of an overlay mount, if every lower layer is
+/// a readonly mount of a block device, such as the loop devices of erofs layers,
+/// then the layers can be attached to the guest and the overlay is assembled in the guest.
+pub async fn get_block_layers(m: &Mount) -> Result<Option<Vec<MountInfo>>> {
+    let lower_dirs = match m.options.iter().find_map(|o| o.strip_prefix("lowerdir=")) {
+        None => return Ok(None),
+        Some(l) => l.split(':').collect::<Vec<&str>>(),
+    };
+    let mut layers = vec![];
+    for dir in lower_dirs {
+        let mi = match get_mount_info(dir).await? {
+            None => return Ok(None),
+            Some(mi) => mi,
+        };
+        if !BLOCK_LAYER_FSTYPES.contains(&mi.fs_type.as_str())
+            || !mi.options.iter().any(|o| o == "ro")
+            || !is_block_device(&mi.device).await?
+        {
+            return Ok(None);
+        }
+        layers.push(mi);
+    }
+    Ok(Some(layers))
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::spec::Mount;
+    use temp_dir::TempDir;
+
+    use super::{get_block_layers, is_block_image, is_empty_dir, tmpfs_size_in_bytes};
+
+    fn mount(r#type: &str, source: &str, options: &[&str]) -> Mount {
+        Mount {
+            destination: "".to_string(),
+            r#type: r#type.to_string(),
+            source: source.to_string(),
+            options: options.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_is_block_image() {
+        let tmp_dir = TempDir::new().unwrap();
+        let layer = tmp_dir.child("layer.erofs");
+        tokio::fs::write(&layer, "").await.unwrap();
+        let layer = layer.to_str().unwrap();
+
+        assert!(is_block_image(&mount("erofs", layer, &["ro", "loop"]))
+            .await
+            .unwrap());
+        // writable image is not passed through
+        assert!(!is_block_image(&mount("erofs", layer, &["loop"]))
+            .await
+            .unwrap());
+        assert!(!is_block_image(&mount("bind", layer, &["ro"]))
+            .await
+            .unwrap());
+        assert!(
+            !is_block_image(&mount("erofs", tmp_dir.path().to_str().unwrap(), &["ro"]))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_block_layers() {
+        let tmp_dir = TempDir::new().unwrap();
+        let lower = tmp_dir.path().to_str().unwrap();
+        // the lower dir of the overlayfs snapshotter is not a mount point
+        let m = mount(
+            "overlay",
+            "overlay",
+            &[
+                &format!("lowerdir={}", lower),
+                "upperdir=/upper",
+                "workdir=/work",
+            ],
+        );
+        assert!(get_block_layers(&m).await.unwrap().is_none());
+        assert!(get_block_layers(&mount("overlay", "overlay", &[]))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_is_empty_dir() {
+        let source = "/var/lib/kubelet/pods/uid/volumes/kubernetes.io~empty-dir/cache";
+        assert!(is_empty_dir(&mount("bind", source, &["rbind", "rw"])));
+        assert!(!is_empty_dir(&mount(
+            "bind",
+            "/var/lib/kubelet/pods/uid/volumes/kubernetes.io~configmap/config",
+            &["rbind", "ro"]
+        )));
+        assert!(!is_empty_dir(&mount("overlay", source, &[])));
+    }
+
+    #[test]
+    fn test_tmpfs_size_in_bytes() {
+        let options = |o: &[&str]| o.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+        assert_eq!(
+            tmpfs_size_in_bytes(&options(&["rw", "size=65536k"])),
+            Some(64 << 20)
+        );
+        assert_eq!(tmpfs_size_in_bytes(&options(&["size=2G"])), Some(2 << 30));
+        assert_eq!(tmpfs_size_in_bytes(&options(&["size=4096"])), Some(4096));
+        assert_eq!(tmpfs_size_in_bytes(&options(&["size=50%"])), None);
+        assert_eq!(tmpfs_size_in_bytes(&options(&["rw", "nosuid"])), None);
+    }
+}
@@ -0,0 +1,288 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::anyhow;
+use containerd_sandbox::error::{Error, Result};
+use containerd_shim::mount::mount_rootfs;
+use log::{debug, warn};
+use nix::libc::MNT_DETACH;
+use serde::Serialize;
+use vmm_common::mount::{bind_mount, unmount, MNT_NOFOLLOW};
+
+use crate::{
+    storage::{STORAGE_DEVICE_ID_PREFIX, STORAGE_ID_PREFIX},
+    utils::read_file,
+    vm::VM,
+    KuasarSandbox,
+};
+
+const STORAGE_REPORT_FILE: &str = "storage_report.json";
+
+/// The discrepancies found between the storages of a sandbox, the mounts in the shared dir
+/// and the devices of the vm, and how they are repaired.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct StorageReport {
+    /// storages referenced by no container, which are released
+    pub unreferenced_storages: Vec<String>,
+    /// mounts in the shared dir that belong to no storage, which are unmounted
+    pub orphan_mounts: Vec<String>,
+    /// mounts of bind storages that are gone, which are mounted again
+    pub missing_mounts: Vec<String>,
+    /// storage devices attached to the vm that belong to no storage, which are detached
+    pub orphan_devices: Vec<String>,
+    /// devices of storages that are not attached to the vm, only reported
+    pub missing_devices: Vec<String>,
+    /// repairs that failed, they will be retried in the next reconciliation
+    pub errors: Vec<String>,
+}
+
+impl StorageReport {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Whether the storages, the mounts or the devices were changed by the reconciliation.
+    pub fn is_repaired(&self) -> bool {
+        !self.unreferenced_storages.is_empty()
+            || !self.orphan_mounts.is_empty()
+            || !self.missing_mounts.is_empty()
+            || !self.orphan_devices.is_empty()
+    }
+}
+
+impl<V> KuasarSandbox<V>
+where
+    V: VM + Sync + Send,
+{
+    /// Compare the storages with the mounts in the shared dir and the devices of the vm,
+    /// repair what can be repaired, and save the report in the base dir of the sandbox.
+    pub async fn reconcile_storages(&mut self) -> Result<StorageReport> {
+        let mut report = StorageReport {
+            unreferenced_storages: self
+                .storages
+                .iter()
+                .filter(|s| s.ref_count() == 0)
+                .map(|s| s.id.to_string())
+                .collect(),
+            ..Default::default()
+        };
+        if !report.unreferenced_storages.is_empty() {
+            if let Err(e) = self.gc_storages().await {
+                report
+                    .errors
+                    .push(format!("failed to release storages: {}", e));
+            }
+        }
+
+        self.reconcile_mounts(&mut report).await?;
+        self.reconcile_devices(&mut report).await;
+
+        let content = serde_json::to_string(&report)
+            .map_err(|e| anyhow!("failed to marshal storage report, {}", e))?;
+        let report_path = Path::new(&self.base_dir).join(STORAGE_REPORT_FILE);
+        tokio::fs::write(&report_path, content).await?;
+        Ok(report)
+    }
+
+    async fn reconcile_mounts(&mut self, report: &mut StorageReport) -> Result<()> {
+        let shared_path = self.get_sandbox_shared_path();
+        let mounts = read_file("/proc/mounts").await?;
+        let mount_points = storage_mount_points(&mounts, &shared_path);
+        let expected: HashSet<String> = self
+            .storages
+            .iter()
+            .filter(|s| s.fstype == "bind")
+            .map(|s| format!("{}/{}", shared_path, s.id))
+            .collect();
+
+        for mp in mount_points.iter().filter(|mp| !expected.contains(*mp)) {
+            report.orphan_mounts.push(mp.to_string());
+            if let Err(e) = unmount(mp, MNT_DETACH | MNT_NOFOLLOW) {
+                report
+                    .errors
+                    .push(format!("failed to unmount {}: {}", mp, e));
+                continue;
+            }
+            remove_mount_point(mp).await.unwrap_or_else(|e| {
+                debug!("failed to remove mount point {}: {}", mp, e);
+            });
+        }
+
+        for s in self.storages.iter().filter(|s| s.fstype == "bind") {
+            let mp = format!("{}/{}", shared_path, s.id);
+            if mount_points.contains(&mp) {
+                continue;
+            }
+            report.missing_mounts.push(mp.to_string());
+            // the options of overlay storages recorded before they were kept are unknown
+            let overlay = s.r#type == "overlay";
+            if (overlay && s.host_options.is_empty()) || !Path::new(&mp).exists() {
+                report
+                    .errors
+                    .push(format!("can not mount {} of storage {} again", mp, s.id));
+                continue;
+            }
+            let res = if overlay {
+                mount_rootfs(Some(&s.r#type), Some(&s.r#type), &s.host_options, &mp)
+                    .map_err(|e| anyhow!("mount rootfs: {}", e))
+            } else {
+                bind_mount(&s.host_source, &mp, &s.host_options)
+            };
+            if let Err(e) = res {
+                report
+                    .errors
+                    .push(format!("failed to mount {} again: {}", mp, e));
+            }
+        }
+        Ok(())
+    }
+
+    async fn reconcile_devices(&mut self, report: &mut StorageReport) {
+        let devices = match self.vm.hot_attached_devices().await {
+            Ok(d) => d,
+            Err(Error::Unimplemented(e)) => {
+                debug!("skip reconciling devices of sandbox {}: {}", self.id, e);
+                return;
+            }
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("failed to get the devices of vm: {}", e));
+                return;
+            }
+        };
+        let (orphans, missing) = diff_storage_devices(
+            &devices,
+            self.storages.iter().filter_map(|s| s.device_id.as_deref()),
+        );
+        for id in orphans {
+            if let Err(e) = self.vm.hot_detach(&id).await {
+                report
+                    .errors
+                    .push(format!("failed to detach device {}: {}", id, e));
+            }
+            report.orphan_devices.push(id);
+        }
+        report.missing_devices = missing;
+    }
+}
+
+// mount points of storages, which are the direct children of the shared dir named by storage id
+fn storage_mount_points(mounts: &str, shared_path: &str) -> Vec<String> {
+    let mut mount_points = vec![];
+    for mp in mounts.lines().filter_map(|l| l.split_whitespace().nth(1)) {
+        let path = Path::new(mp);
+        let is_storage = path.parent() == Some(Path::new(shared_path))
+            && path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with(STORAGE_ID_PREFIX))
+                .unwrap_or_default();
+        if is_storage && !mount_points.iter().any(|x| x == mp) {
+            mount_points.push(mp.to_string());
+        }
+    }
+    mount_points
+}
+
+// returns the storage devices attached to the vm without a storage,
+// and the devices of storages that are not attached.
+fn diff_storage_devices<'a>(
+    attached: &[String],
+    expected: impl Iterator<Item = &'a str>,
+) -> (Vec<String>, Vec<String>) {
+    let expected: Vec<&str> = expected.collect();
+    let orphans = attached
+        .iter()
+        .filter(|d| d.starts_with(STORAGE_DEVICE_ID_PREFIX) && !expected.contains(&d.as_str()))
+        .map(|d| d.to_string())
+        .collect();
+    let missing = expected
+        .iter()
+        .filter(|d| !attached.iter().any(|a| a == *d))
+        .map(|d| d.to_string())
+        .collect();
+    (orphans, missing)
+}
+
+async fn remove_mount_point(mp: &str) -> Result<()> {
+    if Path::new(mp).is_dir() {
+        tokio::fs::remove_dir(mp).await?;
+    } else {
+        tokio::fs::remove_file(mp).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_storage_devices, storage_mount_points, StorageReport};
+
+    #[test]
+    fn test_storage_mount_points() {
+        let mounts = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+/dev/sda1 /run/kuasar/sb/shared/storage1 ext4 rw,relatime 0 0
+overlay /run/kuasar/sb/shared/storage3 overlay rw,lowerdir=/l,upperdir=/u,workdir=/w 0 0
+/dev/sda1 /run/kuasar/sb/shared/storage3 ext4 rw,relatime 0 0
+/dev/sda1 /run/kuasar/sb/shared/c1/hostname ext4 rw,relatime 0 0
+/dev/sda1 /run/kuasar/sb/shared/storage1/sub ext4 rw,relatime 0 0
+/dev/sda1 /run/kuasar/other/shared/storage2 ext4 rw,relatime 0 0";
+        assert_eq!(
+            storage_mount_points(mounts, "/run/kuasar/sb/shared"),
+            vec![
+                "/run/kuasar/sb/shared/storage1".to_string(),
+                "/run/kuasar/sb/shared/storage3".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_storage_devices() {
+        let attached = vec![
+            "blk1".to_string(),
+            "blk3".to_string(),
+            "virtio-serial0".to_string(),
+        ];
+        let (orphans, missing) = diff_storage_devices(&attached, vec!["blk1", "blk2"].into_iter());
+        assert_eq!(orphans, vec!["blk3".to_string()]);
+        assert_eq!(missing, vec!["blk2".to_string()]);
+
+        let (orphans, missing) = diff_storage_devices(&[], std::iter::empty());
+        assert!(orphans.is_empty());
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_storage_report_is_empty() {
+        assert!(StorageReport::default().is_empty());
+        let report = StorageReport {
+            orphan_devices: vec!["blk1".to_string()],
+            ..Default::default()
+        };
+        assert!(!report.is_empty());
+        assert!(report.is_repaired());
+
+        let report = StorageReport {
+            missing_devices: vec!["blk1".to_string()],
+            ..Default::default()
+        };
+        assert!(!report.is_empty());
+        assert!(!report.is_repaired());
+    }
+}
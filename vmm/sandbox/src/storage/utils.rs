@@ -18,8 +18,11 @@ use std::{fs::FileType, os::unix::fs::FileTypeExt, path::Path, process::Stdio};
 
 use anyhow::anyhow;
 use containerd_sandbox::error::Result;
+use log::debug;
 use tokio::process::Command;
 
+use crate::storage::fstype::{is_partition_table, probe_fstype_of};
+
 pub async fn is_block_device<P: AsRef<Path>>(path: P) -> Result<bool> {
     let file_type = match get_file_type(path).await {
         Ok(t) => t,
@@ -52,6 +55,56 @@ pub async fn get_file_type<P: AsRef<Path>>(path: P) -> Result<FileType> {
 }
 
 pub async fn get_fstype(path: &str) -> Result<String> {
+    match probe_fstype_of(path).await {
+        Ok(Some(t)) if is_partition_table(t) => {
+            return Err(anyhow!("{} has a {} partition table but no filesystem", path, t).into());
+        }
+        Ok(Some(t)) => return Ok(t.to_string()),
+        Ok(None) => debug!("unknown fstype of {}, fallback to blkid", path),
+        Err(e) => debug!(
+            "failed to probe fstype of {}: {}, fallback to blkid",
+            path, e
+        ),
+    }
+    get_fstype_by_blkid(path).await
+}
+
+/// Create a sparse image of size and format it as ext4, an existing image is reused.
+pub async fn create_ext4_image(path: &str, size: u64) -> Result<()> {
+    if Path::new(path).exists() {
+        return Ok(());
+    }
+    let tmp_path = format!("{}.tmp", path);
+    let file = tokio::fs::File::create(&tmp_path)
+        .await
+        .map_err(|e| anyhow!("failed to create image {}, {}", tmp_path, e))?;
+    file.set_len(size)
+        .await
+        .map_err(|e| anyhow!("failed to set size of image {}, {}", tmp_path, e))?;
+    let output = Command::new("mkfs.ext4")
+        .args(["-q", "-F", &tmp_path])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .map_err(|e| anyhow!("failed to execute command {}", e))?;
+    if !output.status.success() {
+        tokio::fs::remove_file(&tmp_path).await.unwrap_or_default();
+        return Err(anyhow!(
+            "failed to execute command mkfs.ext4, exit code: {}, error message: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+    // rename after formatted, so a half formatted image is never reused
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .map_err(|e| anyhow!("failed to rename image {} to {}, {}", tmp_path, path, e))?;
+    Ok(())
+}
+
+async fn get_fstype_by_blkid(path: &str) -> Result<String> {
     let output = Command::new("blkid")
         .args(["-p", "-s", "TYPE", "-s", "PTTYPE", "-o", "export", path])
         .stdout(Stdio::piped())
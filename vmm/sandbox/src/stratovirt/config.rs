@@ -257,6 +257,8 @@ pub struct StratoVirtConfig {
     pub kernel: Kernel,
     pub smp: SMP,
     pub memory: Memory,
+    #[param(key = "mem-path")]
+    pub mem_path: Option<String>,
     #[param(key = "pidfile")]
     pub pid_file: String,
     #[param(key = "D")]
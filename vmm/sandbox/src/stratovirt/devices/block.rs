@@ -19,7 +19,7 @@ use containerd_sandbox::error::Result;
 use log::{debug, error};
 use qapi::{
     qmp::{
-        blockdev_add, blockdev_del, device_add, device_del, BlockdevCacheOptions, BlockdevOptions,
+        blockdev_del, device_add, device_del, BlockdevCacheOptions, BlockdevOptions,
         BlockdevOptionsBase, BlockdevOptionsFile, BlockdevOptionsGenericFormat, BlockdevOptionsRaw,
         BlockdevRef,
     },
@@ -30,7 +30,7 @@ use serde_json::Value;
 
 use crate::{
     device::Device,
-    stratovirt::{devices::HotAttachable, qmp_client::QmpClient},
+    stratovirt::{devices::HotAttachable, qmp::blockdev_add, qmp_client::QmpClient},
 };
 
 #[allow(dead_code)]
@@ -54,6 +54,8 @@ pub struct VirtioBlockDevice {
     pub readonly: Option<bool>,
     #[property(param = "drive", generator = "crate::utils::bool_to_on_off")]
     pub direct: Option<bool>,
+    #[property(param = "drive", key = "throttling.iops-total")]
+    pub iops: Option<u64>,
     #[property(param = "device", predicate = "self.addr.len()>0")]
     pub bus: Option<String>,
     #[property(param = "device", predicate = "self.addr.len()>0")]
@@ -79,6 +81,7 @@ impl VirtioBlockDevice {
             r#if: None,
             readonly: read_only,
             direct: None,
+            iops: None,
             bus: None,
             addr: "".to_string(),
         }
@@ -109,14 +112,19 @@ impl HotAttachable for VirtioBlockDevice {
         debug!("hot detach device {}", self.id);
         let device_id = format!("virtio-{}", self.id());
         client.delete_device(&device_id).await?;
-        client.execute(self.to_blockdev_del()).await?;
+        if let Err(e) = client.execute(self.to_blockdev_del()).await {
+            error!(
+                "failed to delete blockdev {} after device_del, {:?}",
+                self.id, e
+            );
+        }
         Ok(())
     }
 }
 
 impl VirtioBlockDevice {
     fn to_blockdev_add(&self) -> blockdev_add {
-        blockdev_add(BlockdevOptions::raw {
+        let options = BlockdevOptions::raw {
             base: BlockdevOptionsBase {
                 node_name: Some(self.id.to_string()),
                 read_only: self.readonly,
@@ -154,7 +162,11 @@ impl VirtioBlockDevice {
                 size: None,
                 offset: None,
             },
-        })
+        };
+        blockdev_add {
+            options,
+            iops: self.iops,
+        }
     }
 
     fn to_device_add(&self, rp_id: &str) -> device_add {
@@ -193,6 +205,7 @@ mod tests {
     use qapi::qmp::BlockdevOptions;
 
     use super::{VirtioBlockDevice, VIRTIO_BLK_DRIVER};
+    use crate::param::ToCmdLineParams;
 
     #[test]
     fn test_block_device_add_qmp_commands() {
@@ -204,7 +217,7 @@ mod tests {
             Some(false),
         );
 
-        let blockdev_add_qmp_cmd = virtio_blk_device.to_blockdev_add().0;
+        let blockdev_add_qmp_cmd = virtio_blk_device.to_blockdev_add().options;
         let blockdev_add_qmp_json_str = serde_json::to_string(&blockdev_add_qmp_cmd).unwrap();
         println!(
             "blockdev_add qmp cmd json string: {}",
@@ -283,4 +296,25 @@ mod tests {
         let expected_params_str = r#"{"id":"virtio-drive-0"}"#;
         assert_eq!(expected_params_str, device_del_qmp_json_str);
     }
+
+    #[test]
+    fn test_block_device_iops() {
+        let mut virtio_blk_device = VirtioBlockDevice::new(
+            VIRTIO_BLK_DRIVER,
+            "drive-0",
+            "virtio-drive-0",
+            Some("/dev/dm-8".to_string()),
+            Some(false),
+        );
+        virtio_blk_device.iops = Some(1000);
+
+        let params = virtio_blk_device.to_cmdline_params("-");
+        let drive = params.iter().position(|x| x == "-drive").unwrap();
+        assert!(params[drive + 1].ends_with(",throttling.iops-total=1000"));
+
+        let blockdev_add_qmp_cmd =
+            serde_json::to_value(virtio_blk_device.to_blockdev_add()).unwrap();
+        assert_eq!(blockdev_add_qmp_cmd["throttling.iops-total"], 1000);
+        assert_eq!(blockdev_add_qmp_cmd["node-name"], "drive-0");
+    }
 }
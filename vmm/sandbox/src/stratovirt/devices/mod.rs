@@ -34,8 +34,11 @@ pub mod pcie_rootbus;
 pub mod rng;
 pub mod rootport;
 pub mod serial;
+pub mod vfio;
 pub mod vhost_user_fs;
+pub mod vhost_user_net;
 pub mod virtio_net;
+pub mod virtserialport;
 pub mod vsock;
 
 pub(crate) const PCIE_ROOTPORT_CAPACITY: usize = 10;
@@ -65,6 +68,8 @@ impl<T> StratoVirtDevice for T where T: Device + ToCmdLineParams + GetAndSetDevi
 #[async_trait]
 pub trait HotAttachable {
     async fn execute_hot_attach(&self, client: &QmpClient, bus_id: &str) -> Result<()>;
+    // the device is detached once device_del succeeds,
+    // failures of removing its backends afterwards are only logged.
     async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()>;
 }
 
@@ -0,0 +1,122 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::debug;
+use qapi::{qmp::device_add, Dictionary};
+use sandbox_derive::CmdLineParams;
+use serde_json::Value;
+
+use crate::{
+    device::Transport,
+    stratovirt::{devices::HotAttachable, qmp_client::QmpClient},
+};
+
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("device")]
+pub struct VfioDevice {
+    #[property(ignore_key)]
+    pub driver: String,
+    pub id: String,
+    #[property(key = "host")]
+    pub bdf: String,
+    #[property(param = "device", predicate = "self.addr.len()>0")]
+    pub bus: Option<String>,
+    #[property(param = "device", predicate = "self.addr.len()>0")]
+    pub addr: String,
+}
+
+impl_device_no_bus!(VfioDevice);
+impl_set_get_device_addr!(VfioDevice);
+
+impl VfioDevice {
+    pub fn new(id: &str, bdf: &str, bus: Option<String>) -> Self {
+        Self {
+            driver: Transport::Pci.to_driver("vfio"),
+            id: id.to_string(),
+            bdf: bdf.to_string(),
+            bus,
+            addr: "".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl HotAttachable for VfioDevice {
+    async fn execute_hot_attach(&self, client: &QmpClient, rp_id: &str) -> Result<()> {
+        debug!("hot attach vfio device {}", self.id);
+        client.execute(self.to_device_add(rp_id)).await?;
+        Ok(())
+    }
+
+    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
+        debug!("hot detach vfio device {}", self.id);
+        client.delete_device(&self.id).await?;
+        Ok(())
+    }
+}
+
+impl VfioDevice {
+    fn to_device_add(&self, rp_id: &str) -> device_add {
+        let mut args = Dictionary::new();
+        args.insert("host".to_string(), Value::from(self.bdf.to_string()));
+        args.insert("addr".to_string(), Value::from("0x0"));
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(rp_id.to_string()),
+            id: Some(self.id.to_string()),
+            arguments: args,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VfioDevice;
+    use crate::{
+        param::ToCmdLineParams,
+        stratovirt::devices::{device::GetAndSetDeviceAddr, DEFAULT_PCIE_BUS},
+    };
+
+    #[test]
+    fn test_vfio_device_params() {
+        let mut device =
+            VfioDevice::new("intf-1", "0000:b4:05.1", Some(DEFAULT_PCIE_BUS.to_string()));
+        device.set_device_addr(6);
+        let params = device.to_cmdline_params("-");
+        let expected_params: Vec<String> = vec![
+            "-device",
+            "vfio-pci,id=intf-1,host=0000:b4:05.1,bus=pcie.0,addr=0x6",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(expected_params, params);
+    }
+
+    #[test]
+    fn test_vfio_device_add_qmp_commands() {
+        let device = VfioDevice::new("intf-1", "0000:b4:05.1", None);
+        let cmd = device.to_device_add("pcie.1");
+        let cmd_json_str = serde_json::to_string(&cmd).unwrap();
+        let cmd_json: serde_json::Value = serde_json::from_str(&cmd_json_str).unwrap();
+        assert_eq!(cmd_json["driver"], "vfio-pci");
+        assert_eq!(cmd_json["id"], "intf-1");
+        assert_eq!(cmd_json["bus"], "pcie.1");
+        assert_eq!(cmd_json["host"], "0000:b4:05.1");
+    }
+}
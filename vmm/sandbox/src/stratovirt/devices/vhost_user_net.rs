@@ -0,0 +1,269 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, error};
+use qapi::{
+    qmp::{chardev_remove, device_add},
+    Dictionary,
+};
+use sandbox_derive::CmdLineParams;
+use serde_json::{json, Value};
+
+use crate::{
+    device::Transport,
+    stratovirt::{
+        devices::{virtio_net::VIRTIO_NET_DRIVER, HotAttachable},
+        qmp::{chardev_add, netdev_add, netdev_del},
+        qmp_client::QmpClient,
+    },
+};
+
+pub const VHOST_USER_NETDEV_TYPE: &str = "vhost-user";
+
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("chardev", "netdev", "device")]
+pub struct VhostUserNetDevice {
+    #[property(param = "chardev", ignore_key)]
+    pub chardev_type: String,
+    #[property(param = "netdev", ignore_key)]
+    pub netdev_type: String,
+    #[property(param = "device", ignore_key)]
+    pub driver: String,
+    #[property(param = "chardev", key = "id")]
+    #[property(param = "netdev", key = "chardev")]
+    pub chardev_id: String,
+    #[property(param = "chardev", key = "path")]
+    pub socket_path: String,
+    #[property(param = "netdev", key = "id")]
+    #[property(param = "device", key = "netdev")]
+    pub netdev_id: String,
+    #[property(param = "device")]
+    pub id: String,
+    #[property(param = "device", key = "mac")]
+    pub mac_address: String,
+    #[property(param = "device", predicate = "self.addr.len()>0")]
+    pub bus: Option<String>,
+    #[property(param = "device", predicate = "self.addr.len()>0")]
+    pub addr: String,
+}
+
+impl_device_no_bus!(VhostUserNetDevice);
+impl_set_get_device_addr!(VhostUserNetDevice);
+
+impl VhostUserNetDevice {
+    pub fn new(
+        id: &str,
+        socket_path: &str,
+        mac_address: &str,
+        transport: Transport,
+        bus: Option<String>,
+    ) -> Self {
+        Self {
+            chardev_type: "socket".to_string(),
+            netdev_type: VHOST_USER_NETDEV_TYPE.to_string(),
+            driver: transport.to_driver(VIRTIO_NET_DRIVER),
+            chardev_id: format!("char-{}", id),
+            socket_path: socket_path.to_string(),
+            netdev_id: format!("net-{}", id),
+            id: id.to_string(),
+            mac_address: mac_address.to_string(),
+            bus,
+            addr: "".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl HotAttachable for VhostUserNetDevice {
+    async fn execute_hot_attach(&self, client: &QmpClient, rp_id: &str) -> Result<()> {
+        debug!("hot attach vhost-user net device {}", self.id);
+        client.execute(self.to_chardev_add()).await?;
+        if let Err(e) = client.execute(self.to_netdev_add()).await {
+            self.remove_chardev(client).await;
+            return Err(e);
+        }
+        if let Err(e) = client.execute(self.to_device_add(rp_id)).await {
+            client
+                .execute(self.to_netdev_del())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("failed to delete netdev after device_add failed, {:?}", e);
+                    qapi::Empty {}
+                });
+            self.remove_chardev(client).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
+        debug!("hot detach vhost-user net device {}", self.id);
+        client.delete_device(&self.id).await?;
+        if let Err(e) = client.execute(self.to_netdev_del()).await {
+            error!(
+                "failed to delete netdev {} after device_del, {:?}",
+                self.id, e
+            );
+        }
+        if let Err(e) = client.execute(self.to_chardev_remove()).await {
+            error!(
+                "failed to remove chardev {} after device_del, {:?}",
+                self.chardev_id, e
+            );
+        }
+        Ok(())
+    }
+}
+
+impl VhostUserNetDevice {
+    async fn remove_chardev(&self, client: &QmpClient) {
+        client
+            .execute(self.to_chardev_remove())
+            .await
+            .unwrap_or_else(|e| {
+                error!("failed to remove chardev after hot attach failed, {:?}", e);
+                qapi::Empty {}
+            });
+    }
+
+    fn to_chardev_add(&self) -> chardev_add {
+        chardev_add {
+            id: self.chardev_id.to_string(),
+            backend: json!({
+                "type": "socket",
+                "data": {
+                    "addr": {
+                        "type": "unix",
+                        "data": { "path": self.socket_path },
+                    },
+                    "server": false,
+                },
+            }),
+        }
+    }
+
+    fn to_netdev_add(&self) -> netdev_add {
+        let mut props = Dictionary::new();
+        props.insert(
+            "type".to_string(),
+            Value::from(self.netdev_type.to_string()),
+        );
+        props.insert(
+            "chardev".to_string(),
+            Value::from(self.chardev_id.to_string()),
+        );
+        netdev_add {
+            id: self.netdev_id.to_string(),
+            props,
+        }
+    }
+
+    fn to_device_add(&self, rp_id: &str) -> device_add {
+        let mut args = Dictionary::new();
+        args.insert(
+            "netdev".to_string(),
+            Value::from(self.netdev_id.to_string()),
+        );
+        args.insert("mac".to_string(), Value::from(self.mac_address.to_string()));
+        args.insert("addr".to_string(), Value::from("0x0"));
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(rp_id.to_string()),
+            id: Some(self.id.to_string()),
+            arguments: args,
+        }
+    }
+
+    fn to_netdev_del(&self) -> netdev_del {
+        netdev_del {
+            id: self.netdev_id.to_string(),
+        }
+    }
+
+    fn to_chardev_remove(&self) -> chardev_remove {
+        chardev_remove {
+            id: self.chardev_id.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::VhostUserNetDevice;
+    use crate::{
+        device::Transport,
+        param::ToCmdLineParams,
+        stratovirt::devices::{device::GetAndSetDeviceAddr, DEFAULT_PCIE_BUS},
+    };
+
+    #[test]
+    fn test_vhost_user_net_device_params() {
+        let mut device = VhostUserNetDevice::new(
+            "intf-1",
+            "/var/run/vhost-user/tap07dc8d7f-fd",
+            "fa:16:3e:ce:ac:af",
+            Transport::Pci,
+            Some(DEFAULT_PCIE_BUS.to_string()),
+        );
+        device.set_device_addr(7);
+        let params = device.to_cmdline_params("-");
+        assert!(params
+            .iter()
+            .any(|x| x == "socket,id=char-intf-1,path=/var/run/vhost-user/tap07dc8d7f-fd"));
+        assert!(params
+            .iter()
+            .any(|x| x == "vhost-user,chardev=char-intf-1,id=net-intf-1"));
+        assert!(params.iter().any(|x| x
+            == "virtio-net-pci,netdev=net-intf-1,id=intf-1,mac=fa:16:3e:ce:ac:af,bus=pcie.0,addr=0x7"));
+    }
+
+    #[test]
+    fn test_vhost_user_net_hot_attach_qmp_commands() {
+        let device = VhostUserNetDevice::new(
+            "intf-1",
+            "/var/run/vhost-user/tap07dc8d7f-fd",
+            "fa:16:3e:ce:ac:af",
+            Transport::Pci,
+            None,
+        );
+        let chardev_add = serde_json::to_value(device.to_chardev_add()).unwrap();
+        assert_eq!(
+            chardev_add,
+            json!({
+                "id": "char-intf-1",
+                "backend": {
+                    "type": "socket",
+                    "data": {
+                        "addr": {
+                            "type": "unix",
+                            "data": { "path": "/var/run/vhost-user/tap07dc8d7f-fd" },
+                        },
+                        "server": false,
+                    },
+                },
+            })
+        );
+        let netdev_add = serde_json::to_value(device.to_netdev_add()).unwrap();
+        assert_eq!(
+            netdev_add,
+            json!({"id": "net-intf-1", "type": "vhost-user", "chardev": "char-intf-1"})
+        );
+    }
+}
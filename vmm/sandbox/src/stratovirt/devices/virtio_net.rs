@@ -16,9 +16,22 @@ limitations under the License.
 
 use std::os::unix::io::RawFd;
 
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, error};
+use qapi::{qmp::device_add, Dictionary};
 use sandbox_derive::CmdLineParams;
+use serde_json::Value;
 
-use crate::{device::Transport, network::NetType};
+use crate::{
+    device::Transport,
+    network::NetType,
+    stratovirt::{
+        devices::HotAttachable,
+        qmp::{netdev_add, netdev_del},
+        qmp_client::QmpClient,
+    },
+};
 
 pub const VIRTIO_NET_DRIVER: &str = "virtio-net";
 
@@ -167,8 +180,123 @@ impl VirtioNetDevice {
     }
 }
 
+#[async_trait]
+impl HotAttachable for VirtioNetDevice {
+    async fn execute_hot_attach(&self, client: &QmpClient, rp_id: &str) -> Result<()> {
+        debug!("hot attach net device {}", self.id);
+        let mut fd_names = self.fd_names("fd");
+        fd_names.extend(self.fd_names("vhostfd"));
+        let res = self.hot_attach_by_fds(client, rp_id, &fd_names).await;
+        if res.is_err() {
+            // the fds sent are not taken by any device if the netdev or device is not added
+            client.close_fds(&fd_names).await;
+        }
+        res
+    }
+
+    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
+        debug!("hot detach net device {}", self.id);
+        client.delete_device(&self.device_id).await?;
+        if let Err(e) = client.execute(self.to_netdev_del()).await {
+            error!(
+                "failed to delete netdev {} after device_del, {:?}",
+                self.id, e
+            );
+        }
+        Ok(())
+    }
+}
+
+impl VirtioNetDevice {
+    async fn hot_attach_by_fds(
+        &self,
+        client: &QmpClient,
+        rp_id: &str,
+        fd_names: &[String],
+    ) -> Result<()> {
+        for (name, fd) in fd_names.iter().zip(self.fds.iter().chain(&self.vhostfds)) {
+            client.send_fd(name, *fd).await?;
+        }
+        client.execute(self.to_netdev_add()).await?;
+        if let Err(e) = client.execute(self.to_device_add(rp_id)).await {
+            client
+                .execute(self.to_netdev_del())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("failed to delete netdev after device_add failed, {:?}", e);
+                    qapi::Empty {}
+                });
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // names of the fds sent to stratovirt by getfd
+    fn fd_names(&self, kind: &str) -> Vec<String> {
+        let fds = if kind == "vhostfd" {
+            &self.vhostfds
+        } else {
+            &self.fds
+        };
+        (0..fds.len())
+            .map(|i| format!("{}-{}{}", self.id, kind, i))
+            .collect()
+    }
+
+    // the tap is opened by the ifname if no fds are given,
+    // as stratovirt runs in the netns of the sandbox.
+    fn to_netdev_add(&self) -> netdev_add {
+        let mut props = Dictionary::new();
+        if !self.fds.is_empty() {
+            props.insert(
+                "fds".to_string(),
+                Value::from(self.fd_names("fd").join(":")),
+            );
+        } else if let Some(ifname) = &self.ifname {
+            props.insert("ifname".to_string(), Value::from(ifname.to_string()));
+        }
+        if self.vhost {
+            props.insert("vhost".to_string(), Value::from(true));
+            if !self.vhostfds.is_empty() {
+                props.insert(
+                    "vhostfds".to_string(),
+                    Value::from(self.fd_names("vhostfd").join(":")),
+                );
+            }
+        }
+        if let Some(queues) = self.queues {
+            props.insert("queues".to_string(), Value::from(queues));
+        }
+        netdev_add {
+            id: self.id.to_string(),
+            props,
+        }
+    }
+
+    fn to_device_add(&self, rp_id: &str) -> device_add {
+        let mut args = Dictionary::new();
+        args.insert("netdev".to_string(), Value::from(self.id.to_string()));
+        args.insert("mac".to_string(), Value::from(self.mac_address.to_string()));
+        args.insert("addr".to_string(), Value::from("0x0"));
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(rp_id.to_string()),
+            id: Some(self.device_id.to_string()),
+            arguments: args,
+        }
+    }
+
+    fn to_netdev_del(&self) -> netdev_del {
+        netdev_del {
+            id: self.id.to_string(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use crate::{
         device::Transport,
         network::NetType,
@@ -248,4 +376,44 @@ mod tests {
                 == "virtio-net-device,netdev=intf-tap0,id=virtio-net-intf-tap0,mac=a1:b2:c3:d5:f4,mq=off")
             .is_some());
     }
+    #[test]
+    fn test_virtio_net_hot_attach_qmp_commands() {
+        let device = VirtioNetDevice::new()
+            .id("intf-1")
+            .name("tap_kuasar_1")
+            .mac_address("a1:b2:c3:d5:f4")
+            .transport(Transport::Pci)
+            .build();
+
+        let netdev_add = serde_json::to_value(device.to_netdev_add()).unwrap();
+        assert_eq!(
+            netdev_add,
+            json!({"id": "intf-1", "ifname": "tap_kuasar_1"})
+        );
+        let device_add = serde_json::to_value(device.to_device_add("pcie.1")).unwrap();
+        assert_eq!(
+            device_add,
+            json!({
+                "driver": "virtio-net-pci",
+                "id": "virtio-net-intf-1",
+                "bus": "pcie.1",
+                "netdev": "intf-1",
+                "mac": "a1:b2:c3:d5:f4",
+                "addr": "0x0",
+            })
+        );
+        let netdev_del = serde_json::to_value(device.to_netdev_del()).unwrap();
+        assert_eq!(netdev_del, json!({"id": "intf-1"}));
+
+        let device = VirtioNetDevice::new()
+            .id("intf-2")
+            .name("tap_kuasar_2")
+            .fds(vec![20, 21])
+            .build();
+        let netdev_add = serde_json::to_value(device.to_netdev_add()).unwrap();
+        assert_eq!(
+            netdev_add,
+            json!({"id": "intf-2", "fds": "intf-2-fd0:intf-2-fd1"})
+        );
+    }
 }
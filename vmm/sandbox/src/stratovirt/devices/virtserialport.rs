@@ -0,0 +1,235 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, error};
+use qapi::{
+    qmp::{chardev_remove, device_add},
+    Dictionary,
+};
+use sandbox_derive::CmdLineParams;
+use serde_json::{json, Value};
+
+use crate::{
+    device::CharBackendType,
+    stratovirt::{devices::HotAttachable, qmp::chardev_add, qmp_client::QmpClient},
+};
+
+pub const VIRT_SERIAL_PORT_DRIVER: &str = "virtserialport";
+
+pub const CHARBACKEND_SOCKET: &str = "socket";
+pub const CHARBACKEND_PIPE: &str = "pipe";
+
+// VirtSerialPort is a port on the virtio-serial bus with its backend chardev,
+// the guest finds the port by the name under /dev/virtio-ports/
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("chardev", "device")]
+pub struct VirtSerialPort {
+    #[property(param = "chardev", ignore_key)]
+    pub backend: String,
+    #[property(param = "device", ignore_key)]
+    pub driver: String,
+    #[property(param = "device")]
+    pub id: String,
+    #[property(param = "chardev", key = "id")]
+    #[property(param = "device", key = "chardev")]
+    pub chardev_id: String,
+    #[property(param = "chardev")]
+    pub path: String,
+    #[property(
+        param = "chardev",
+        ignore_key,
+        predicate = "self.backend == \"socket\"",
+        generator = "crate::utils::bool_to_socket_server"
+    )]
+    pub server: bool,
+    #[property(
+        param = "chardev",
+        ignore_key,
+        predicate = "self.backend == \"socket\"",
+        generator = "crate::utils::bool_to_socket_nowait"
+    )]
+    pub nowait: bool,
+    #[property(param = "device")]
+    pub name: Option<String>,
+    #[property(ignore)]
+    pub addr: String,
+}
+
+impl_device_no_bus!(VirtSerialPort);
+impl_set_get_device_addr!(VirtSerialPort);
+
+impl VirtSerialPort {
+    pub fn new(
+        backend_type: CharBackendType,
+        id: &str,
+        chardev_id: &str,
+        name: Option<String>,
+    ) -> Self {
+        let (backend, path) = match backend_type {
+            CharBackendType::Pipe(p) => (CHARBACKEND_PIPE, p),
+            CharBackendType::Socket(p) => (CHARBACKEND_SOCKET, p),
+        };
+        Self {
+            backend: backend.to_string(),
+            driver: VIRT_SERIAL_PORT_DRIVER.to_string(),
+            id: id.to_string(),
+            chardev_id: chardev_id.to_string(),
+            path,
+            server: true,
+            nowait: true,
+            name,
+            addr: "".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl HotAttachable for VirtSerialPort {
+    async fn execute_hot_attach(&self, client: &QmpClient, bus_id: &str) -> Result<()> {
+        debug!("hot attach virtserialport {}", self.id);
+        client.execute(self.to_chardev_add()?).await?;
+        match client.execute(self.to_device_add(bus_id)).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                client
+                    .execute(self.to_chardev_remove())
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("failed to remove chardev after device_add failed, {:?}", e);
+                        qapi::Empty {}
+                    });
+                Err(e)
+            }
+        }
+    }
+
+    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
+        debug!("hot detach virtserialport {}", self.id);
+        client.delete_device(&self.id).await?;
+        if let Err(e) = client.execute(self.to_chardev_remove()).await {
+            error!(
+                "failed to remove chardev {} after device_del, {:?}",
+                self.chardev_id, e
+            );
+        }
+        Ok(())
+    }
+}
+
+impl VirtSerialPort {
+    fn to_chardev_add(&self) -> Result<chardev_add> {
+        match &*self.backend {
+            CHARBACKEND_PIPE => Ok(chardev_add {
+                id: self.chardev_id.to_string(),
+                backend: json!({
+                    "type": "pipe",
+                    "data": { "device": self.path },
+                }),
+            }),
+            _ => Err(anyhow!("no support hotplug of char device other than pipe").into()),
+        }
+    }
+
+    // the port is plugged to the virtio-serial bus of the device with bus_id
+    fn to_device_add(&self, bus_id: &str) -> device_add {
+        let mut args = Dictionary::new();
+        if let Some(x) = self.name.as_ref() {
+            args.insert("name".to_string(), Value::from(x.to_string()));
+        }
+        args.insert(
+            "chardev".to_string(),
+            Value::from(self.chardev_id.to_string()),
+        );
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(bus_id.to_string()),
+            id: Some(self.id.to_string()),
+            arguments: args,
+        }
+    }
+
+    fn to_chardev_remove(&self) -> chardev_remove {
+        chardev_remove {
+            id: self.chardev_id.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::VirtSerialPort;
+    use crate::{device::CharBackendType, param::ToCmdLineParams};
+
+    #[test]
+    fn test_virt_serial_port_params() {
+        let port = VirtSerialPort::new(
+            CharBackendType::Pipe("/run/kuasar/sandbox1/stdout".to_string()),
+            "virtioserial1",
+            "chardev1",
+            Some("chardev1".to_string()),
+        );
+        let params = port.to_cmdline_params("-");
+        assert!(params
+            .iter()
+            .any(|x| x == "pipe,id=chardev1,path=/run/kuasar/sandbox1/stdout"));
+        assert!(params
+            .iter()
+            .any(|x| x == "virtserialport,id=virtioserial1,chardev=chardev1,name=chardev1"));
+    }
+
+    #[test]
+    fn test_virt_serial_port_hot_attach_qmp_commands() {
+        let port = VirtSerialPort::new(
+            CharBackendType::Pipe("/run/kuasar/sandbox1/stdout".to_string()),
+            "virtioserial1",
+            "chardev1",
+            Some("chardev1".to_string()),
+        );
+        let chardev_add = serde_json::to_value(port.to_chardev_add().unwrap()).unwrap();
+        assert_eq!(
+            chardev_add,
+            json!({
+                "id": "chardev1",
+                "backend": {"type": "pipe", "data": {"device": "/run/kuasar/sandbox1/stdout"}},
+            })
+        );
+
+        let socket_port = VirtSerialPort::new(
+            CharBackendType::Socket("/run/kuasar/sandbox1/port.sock".to_string()),
+            "virtioserial2",
+            "chardev2",
+            None,
+        );
+        assert!(socket_port.to_chardev_add().is_err());
+
+        let device_add = serde_json::to_value(port.to_device_add("virtio-serial0")).unwrap();
+        assert_eq!(
+            device_add,
+            json!({
+                "driver": "virtserialport",
+                "id": "virtioserial1",
+                "bus": "virtio-serial0",
+                "name": "chardev1",
+                "chardev": "chardev1",
+            })
+        );
+    }
+}
@@ -16,6 +16,7 @@ limitations under the License.
 
 use async_trait::async_trait;
 use containerd_sandbox::SandboxOption;
+use log::warn;
 use tokio::fs::create_dir_all;
 use uuid::Uuid;
 use vmm_common::SHARED_DIR_SUFFIX;
@@ -32,9 +33,11 @@ use super::devices::{
     DEFAULT_SERIAL_DEVICE_ID, PCIE_ROOTPORT_CAPACITY,
 };
 use crate::{
+    annotation::{get_io_limits, HypervisorAnnotations},
     stratovirt::{
         config::{QmpSocket, StratoVirtVMConfig, MACHINE_TYPE_MICROVM},
         devices::vsock::{find_context_id, VSockDevice},
+        hooks::check_annotations,
         StratoVirtVM,
     },
     utils::get_netns,
@@ -64,6 +67,13 @@ impl VMFactory for StratoVirtVMFactory {
         let netns = get_netns(&s.sandbox);
         let mut vm = StratoVirtVM::new(id, &netns, &s.base_dir);
         vm.config = self.default_config.to_stratovirt_config().await?;
+        vm.io_limits = get_io_limits(&s.sandbox)?;
+        if vm.io_limits.disk.bandwidth > 0 {
+            warn!(
+                "bandwidth limit of block devices is not supported by stratovirt, ignore {}",
+                vm.io_limits.disk.bandwidth
+            );
+        }
         vm.config.uuid = Uuid::new_v4().to_string();
         vm.config.name = format!("sandbox-{}", id);
         vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
@@ -159,6 +169,7 @@ impl VMFactory for StratoVirtVMFactory {
             vm.create_pcie_root_ports(PCIE_ROOTPORT_CAPACITY)?;
         }
 
+        check_annotations(&HypervisorAnnotations::parse(&s.sandbox)?, &vm)?;
         Ok(vm)
     }
 }
@@ -15,14 +15,30 @@ limitations under the License.
 */
 
 use async_trait::async_trait;
-use containerd_sandbox::error::Result;
+use containerd_sandbox::{
+    data::SandboxData,
+    error::{Error, Result},
+};
 
 use crate::{
+    annotation::HypervisorAnnotations,
+    device::{Device, Transport},
     sandbox::KuasarSandbox,
-    stratovirt::{config::StratoVirtVMConfig, StratoVirtVM},
-    vm::Hooks,
+    stratovirt::{
+        config::{StratoVirtConfig, StratoVirtVMConfig},
+        devices::{
+            block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
+            device::GetAndSetDeviceAddr,
+            DEFAULT_PCIE_BUS,
+        },
+        StratoVirtVM,
+    },
+    utils::get_total_resources,
+    vm::{BlockDriver, Hooks},
 };
 
+const HUGEPAGES_PATH: &str = "/dev/hugepages";
+
 pub struct StratoVirtHooks {
     #[allow(dead_code)]
     config: StratoVirtVMConfig,
@@ -36,8 +52,18 @@ impl StratoVirtHooks {
 
 #[async_trait]
 impl Hooks<StratoVirtVM> for StratoVirtHooks {
-    async fn pre_start(&self, _sandbox: &mut KuasarSandbox<StratoVirtVM>) -> Result<()> {
-        // TODO
+    async fn post_create(&self, sandbox: &mut KuasarSandbox<StratoVirtVM>) -> Result<()> {
+        // the network devices can not be limited by the hypervisor, limit the interfaces instead
+        if let Some(network) = &sandbox.network {
+            network.set_rate_limit(&sandbox.vm.io_limits.net).await?;
+        }
+        Ok(())
+    }
+
+    async fn pre_start(&self, sandbox: &mut KuasarSandbox<StratoVirtVM>) -> Result<()> {
+        process_config(&sandbox.data, &mut sandbox.vm.config)?;
+        // annotations are applied after the resources so that they can override the sizing
+        process_annotation(&sandbox.data, &mut sandbox.vm)?;
         Ok(())
     }
 
@@ -48,3 +74,175 @@ impl Hooks<StratoVirtVM> for StratoVirtHooks {
         Ok(())
     }
 }
+
+/// Check the annotations that stratovirt can not apply, before the vm is created.
+pub(crate) fn check_annotations(
+    annotations: &HypervisorAnnotations,
+    vm: &StratoVirtVM,
+) -> Result<()> {
+    let transport = vm.config.machine.transport();
+    match (&annotations.block_driver, &transport) {
+        (None, _)
+        | (Some(BlockDriver::VirtioBlk), _)
+        | (Some(BlockDriver::VirtioMmio), Transport::Mmio) => {}
+        (Some(driver), _) => {
+            return Err(Error::InvalidArgument(format!(
+                "block driver {} is not supported by stratovirt {}",
+                driver.to_driver_string(),
+                vm.config.machine.r#type
+            )));
+        }
+    }
+    if annotations.image.is_some() && !vm.devices.iter().any(|d| d.id() == "rootfs") {
+        return Err(Error::InvalidArgument(
+            "can not change image of vm without rootfs".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn process_annotation(data: &SandboxData, vm: &mut StratoVirtVM) -> Result<()> {
+    let annotations = HypervisorAnnotations::parse(data)?;
+    check_annotations(&annotations, vm)?;
+    let config = &mut vm.config;
+    if let Some(vcpus) = annotations.vcpus {
+        config.smp.cpus = vcpus;
+    }
+    if let Some(memory_in_mb) = annotations.memory_in_mb {
+        config.memory.size = format!("{}M", memory_in_mb);
+    }
+    match annotations.hugepages {
+        Some(true) => config.mem_path = Some(HUGEPAGES_PATH.to_string()),
+        // a file backed memory set in the config is not hugepages, keep it
+        Some(false) if config.mem_path.as_deref() == Some(HUGEPAGES_PATH) => config.mem_path = None,
+        _ => {}
+    }
+    annotations.append_kernel_params(&mut config.kernel.kernel_params);
+
+    let transport = config.machine.transport();
+    if let Some(driver) = annotations.block_driver {
+        vm.block_driver = driver;
+    }
+
+    if let Some(image) = &annotations.image {
+        let index = vm
+            .devices
+            .iter()
+            .position(|d| d.id() == "rootfs")
+            .ok_or_else(|| {
+                Error::InvalidArgument("can not change image of vm without rootfs".to_string())
+            })?;
+        let mut image_device = VirtioBlockDevice::new(
+            &transport.to_driver(VIRTIO_BLK_DRIVER),
+            "rootfs",
+            "blk-0",
+            Some(image.to_string()),
+            Some(true),
+        );
+        image_device.bus = Some(DEFAULT_PCIE_BUS.to_string());
+        // keep the slot of the replaced device on the bus
+        image_device.addr = vm.devices[index].get_device_addr();
+        vm.devices[index] = Box::new(image_device);
+        vm.config.kernel.image = Some(image.to_string());
+    }
+    Ok(())
+}
+
+fn process_config(data: &SandboxData, config: &mut StratoVirtConfig) -> Result<()> {
+    // the overhead of the pod is also counted in the vm
+    if let Some(resources) = get_total_resources(data) {
+        if resources.cpu_period > 0 && resources.cpu_quota > 0 {
+            // get ceil of cpus if it is not integer
+            let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
+            config.smp.cpus = base as u32;
+        }
+        if resources.memory_limit_in_bytes > 0 {
+            let mem_in_mb =
+                (resources.memory_limit_in_bytes as u64 + bytefmt::MIB - 1) / bytefmt::MIB;
+            config.memory.size = format!("{}M", mem_in_mb);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::data::SandboxData;
+
+    use super::{process_annotation, process_config};
+    use crate::{
+        annotation::{
+            tests::sandbox_data, ANNOTATION_KEY_BLOCK_DRIVER, ANNOTATION_KEY_HUGEPAGES,
+            ANNOTATION_KEY_MEMORY, ANNOTATION_KEY_VCPUS,
+        },
+        param::ToCmdLineParams,
+        stratovirt::{config::StratoVirtVMConfig, StratoVirtVM},
+    };
+
+    fn smp_and_memory_params(params: &[String]) -> (String, String) {
+        let smp = params.iter().position(|x| x == "-smp").unwrap();
+        let memory = params.iter().position(|x| x == "-m").unwrap();
+        (params[smp + 1].clone(), params[memory + 1].clone())
+    }
+
+    #[tokio::test]
+    async fn test_process_config() {
+        let mut vmconfig = StratoVirtVMConfig::default();
+        vmconfig.common.initrd_path = "/var/lib/kuasar/initrd".to_string();
+        let mut config = vmconfig.to_stratovirt_config().await.unwrap();
+
+        // no pod resources, the vm keeps the default sizing
+        process_config(&SandboxData::default(), &mut config).unwrap();
+        let params = config.to_cmdline_params("-");
+        assert_eq!(
+            smp_and_memory_params(&params),
+            ("cpus=1".to_string(), "1024M".to_string())
+        );
+
+        // the vm is sized by the resources and the overhead of the pod
+        process_config(&sandbox_data(&[]), &mut config).unwrap();
+        let params = config.to_cmdline_params("-");
+        assert_eq!(
+            smp_and_memory_params(&params),
+            ("cpus=2".to_string(), "1152M".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_annotation() {
+        let mut vmconfig = StratoVirtVMConfig::default();
+        vmconfig.common.initrd_path = "/var/lib/kuasar/initrd".to_string();
+        let mut vm = StratoVirtVM::new("sandbox1", "", "/run/kuasar/sandbox1");
+        vm.config = vmconfig.to_stratovirt_config().await.unwrap();
+
+        let data = sandbox_data(&[
+            (ANNOTATION_KEY_VCPUS, "4"),
+            (ANNOTATION_KEY_MEMORY, "4096"),
+            (ANNOTATION_KEY_HUGEPAGES, "true"),
+        ]);
+        process_config(&data, &mut vm.config).unwrap();
+        process_annotation(&data, &mut vm).unwrap();
+        let params = vm.config.to_cmdline_params("-");
+        assert_eq!(
+            smp_and_memory_params(&params),
+            ("cpus=4".to_string(), "4096M".to_string())
+        );
+        let mem_path = params.iter().position(|x| x == "-mem-path").unwrap();
+        assert_eq!(params[mem_path + 1], "/dev/hugepages");
+
+        let data = sandbox_data(&[(ANNOTATION_KEY_HUGEPAGES, "false")]);
+        process_annotation(&data, &mut vm).unwrap();
+        assert!(vm.config.mem_path.is_none());
+        vm.config.mem_path = Some("/dev/shm/sandbox1".to_string());
+        process_annotation(&data, &mut vm).unwrap();
+        assert_eq!(vm.config.mem_path.as_deref(), Some("/dev/shm/sandbox1"));
+
+        let data = sandbox_data(&[(ANNOTATION_KEY_VCPUS, "0")]);
+        assert!(process_annotation(&data, &mut vm).is_err());
+        let data = sandbox_data(&[(ANNOTATION_KEY_MEMORY, "1G")]);
+        assert!(process_annotation(&data, &mut vm).is_err());
+        // virtio-scsi is not supported by stratovirt
+        let data = sandbox_data(&[(ANNOTATION_KEY_BLOCK_DRIVER, "virtio-scsi")]);
+        assert!(process_annotation(&data, &mut vm).is_err());
+    }
+}
@@ -47,21 +47,30 @@ use self::{
 use crate::{
     args::Args,
     device::{Bus, BusType, DeviceInfo, Slot, SlotStatus},
-    impl_recoverable, load_config,
+    impl_recoverable,
+    kata_config::Hypervisor,
+    load_config, load_kata_config,
     param::ToCmdLineParams,
     sandbox::KuasarSandboxer,
+    storage::STORAGE_DEVICE_ID_PREFIX,
     stratovirt::{
         config::StratoVirtConfig,
         devices::{
-            block::VirtioBlockDevice, rootport::PCIERootPorts, virtio_net::VirtioNetDevice,
-            StratoVirtDevice, StratoVirtHotAttachable, DEFAULT_PCIE_BUS,
+            block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
+            rootport::PCIERootPorts,
+            vfio::VfioDevice,
+            vhost_user_net::VhostUserNetDevice,
+            virtio_net::VirtioNetDevice,
+            virtserialport::VirtSerialPort,
+            HotAttachable, StratoVirtDevice, StratoVirtHotAttachable, DEFAULT_PCIE_BUS,
+            DEFAULT_SERIAL_DEVICE_ID,
         },
         qmp_client::QmpClient,
         utils::detect_pid,
         virtiofs::VirtiofsDaemon,
     },
     utils::{read_std, wait_channel, wait_pid},
-    vm::{BlockDriver, Pids, VcpuThreads, VM},
+    vm::{BlockDriver, IoLimits, Pids, VcpuThreads, VM},
 };
 
 pub mod config;
@@ -87,6 +96,9 @@ pub struct StratoVirtVM {
     devices: Vec<Box<dyn StratoVirtDevice + Sync + Send>>,
     #[serde(skip)]
     hot_attached_devices: Vec<Box<dyn StratoVirtHotAttachable + Sync + Send>>,
+    // ids of the hot attached devices, which are kept over a restart of the sandboxer
+    #[serde(default)]
+    hot_attached_ids: Vec<String>,
     fds: Vec<RawFd>,
     console_socket: String,
     agent_socket: String,
@@ -103,6 +115,8 @@ pub struct StratoVirtVM {
     pcie_root_bus: Option<PcieRootBus>,
     #[serde(skip)]
     pcie_root_ports_pool: Option<PCIERootPorts>,
+    #[serde(default)]
+    io_limits: IoLimits,
 }
 
 #[async_trait]
@@ -209,8 +223,44 @@ impl VM for StratoVirtVM {
                     .build();
                 self.attach_to_bus(virtio_net_device)?;
             }
-            _ => {
-                todo!()
+            DeviceInfo::Block(blk_info) => {
+                let mut device = VirtioBlockDevice::new(
+                    &self.config.machine.transport().to_driver(VIRTIO_BLK_DRIVER),
+                    &blk_info.id,
+                    &format!("virtio-{}", blk_info.id),
+                    Some(blk_info.path),
+                    Some(blk_info.read_only),
+                );
+                device.bus = Some(DEFAULT_PCIE_BUS.to_string());
+                device.iops = self.disk_iops();
+                self.attach_to_bus(device)?;
+            }
+            DeviceInfo::Physical(vfio_info) => {
+                let device = VfioDevice::new(
+                    &vfio_info.id,
+                    &vfio_info.bdf,
+                    Some(DEFAULT_PCIE_BUS.to_string()),
+                );
+                self.attach_to_bus(device)?;
+            }
+            DeviceInfo::VhostUser(vhost_user_info) => {
+                let device = VhostUserNetDevice::new(
+                    &vhost_user_info.id,
+                    &vhost_user_info.socket_path,
+                    &vhost_user_info.mac_address,
+                    self.config.machine.transport(),
+                    Some(DEFAULT_PCIE_BUS.to_string()),
+                );
+                self.attach_to_bus(device)?;
+            }
+            DeviceInfo::Char(char_info) => {
+                let device = VirtSerialPort::new(
+                    char_info.backend,
+                    &char_info.id,
+                    &char_info.chardev_id,
+                    Some(char_info.name),
+                );
+                self.attach_device(device);
             }
         };
         Ok(())
@@ -219,36 +269,149 @@ impl VM for StratoVirtVM {
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     "",
                     &blk_info.id,
                     "",
                     Some(blk_info.path),
                     Some(blk_info.read_only),
                 );
+                device.iops = self.disk_iops();
                 let index = self.hot_attach_device(device).await?;
                 let addr = format!("0000:00:{:02x}.0", index);
                 Ok((self.block_driver.to_bus_type(), addr))
             }
-            DeviceInfo::Tap(_tap_info) => Err(Error::Unimplemented(
-                "hot attach for tap device".to_string(),
-            )),
-            DeviceInfo::Physical(_vfio_info) => Err(Error::Unimplemented(
-                "hot attach for vfio device".to_string(),
-            )),
-            DeviceInfo::VhostUser(_vhost_user_info) => Err(Error::Unimplemented(
-                "hot attach for vhost_user device".to_string(),
-            )),
-            DeviceInfo::Char(_char_info) => Err(Error::Unimplemented(
-                "hot attach for char device".to_string(),
-            )),
+            DeviceInfo::Tap(tap_info) => {
+                let device = VirtioNetDevice::new()
+                    .id(&tap_info.id)
+                    .name(&tap_info.name)
+                    .mac_address(&tap_info.mac_address)
+                    .transport(self.config.machine.transport())
+                    .fds(tap_info.fds.clone())
+                    .build();
+                let res = self.hot_attach_device(device).await;
+                // stratovirt holds its own copies of the fds sent by getfd
+                for fd in tap_info.fds {
+                    nix::unistd::close(fd).unwrap_or_default();
+                }
+                let index = res?;
+                Ok((BusType::PCI, format!("0000:00:{:02x}.0", index)))
+            }
+            DeviceInfo::Physical(vfio_info) => {
+                let device = VfioDevice::new(&vfio_info.id, &vfio_info.bdf, None);
+                let index = self.hot_attach_device(device).await?;
+                Ok((BusType::PCI, format!("0000:00:{:02x}.0", index)))
+            }
+            DeviceInfo::VhostUser(vhost_user_info) => {
+                if !self.memory_shared() {
+                    return Err(Error::InvalidArgument(format!(
+                        "vhost-user device {} requires the guest memory to be shared",
+                        vhost_user_info.id
+                    )));
+                }
+                let device = VhostUserNetDevice::new(
+                    &vhost_user_info.id,
+                    &vhost_user_info.socket_path,
+                    &vhost_user_info.mac_address,
+                    self.config.machine.transport(),
+                    None,
+                );
+                let index = self.hot_attach_device(device).await?;
+                Ok((BusType::PCI, format!("0000:00:{:02x}.0", index)))
+            }
+            DeviceInfo::Char(char_info) => {
+                let device = VirtSerialPort::new(
+                    char_info.backend,
+                    &char_info.id,
+                    &char_info.chardev_id,
+                    Some(char_info.name.clone()),
+                );
+                // virtserialport is plugged to the virtio-serial bus, no root port is needed
+                let client = self.get_client()?;
+                device
+                    .execute_hot_attach(client, DEFAULT_SERIAL_DEVICE_ID)
+                    .await?;
+                self.hot_attached_ids.push(device.id());
+                self.hot_attached_devices.push(Box::new(device));
+                // address is not important for char devices as guest finds the device by the name
+                Ok((BusType::PCI, char_info.name))
+            }
         }
     }
 
-    async fn hot_detach(&mut self, _id: &str) -> Result<()> {
+    async fn hot_detach(&mut self, id: &str) -> Result<()> {
+        let index = self.hot_attached_devices.iter().position(|x| x.id() == id);
+        let device: Box<dyn StratoVirtHotAttachable + Sync + Send> = match index {
+            Some(index) => self.hot_attached_devices.remove(index),
+            None if !self.hot_attached_ids.iter().any(|x| x == id) => {
+                return Ok(());
+            }
+            // the device was hot attached before a restart of the sandboxer,
+            // only block devices can be rebuilt from the id to detach them
+            None if id.starts_with(STORAGE_DEVICE_ID_PREFIX) => {
+                let mut device = VirtioBlockDevice::new("", id, "", None, None);
+                device.iops = self.disk_iops();
+                Box::new(device)
+            }
+            None => {
+                warn!(
+                    "device {} hot attached before restart can not be detached",
+                    id
+                );
+                self.hot_attached_ids.retain(|x| x != id);
+                return Ok(());
+            }
+        };
+
+        let client = match self.get_client() {
+            Ok(c) => c,
+            Err(e) => {
+                // rollback, add it back to the list
+                self.hot_attached_devices.push(device);
+                return Err(e);
+            }
+        };
+
+        // only a failed device_del is rolled back, the device is gone from the vm otherwise
+        if let Err(e) = device.execute_hot_detach(client).await {
+            // rollback, add it back to the list
+            self.hot_attached_devices.push(device);
+            return Err(e);
+        }
+        self.hot_attached_ids.retain(|x| x != id);
+        self.release_rootport_slot(id);
         Ok(())
     }
 
+    // the device is attached to the node named by the device id
+    async fn resize_block_device(&mut self, id: &str, size: u64) -> Result<()> {
+        let client = self.get_client()?;
+        client
+            .execute(qapi::qmp::block_resize {
+                device: None,
+                node_name: Some(id.to_string()),
+                size: size as i64,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn pause(&mut self) -> Result<()> {
+        let client = self.get_client()?;
+        client.execute(qapi::qmp::stop {}).await?;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        let client = self.get_client()?;
+        client.execute(qapi::qmp::cont {}).await?;
+        Ok(())
+    }
+
+    async fn hot_attached_devices(&mut self) -> Result<Vec<String>> {
+        Ok(self.hot_attached_ids.clone())
+    }
+
     async fn ping(&self) -> Result<()> {
         let client = self.get_client()?;
         let _res = client.execute(qapi::qmp::query_status {}).await?;
@@ -291,6 +454,7 @@ impl StratoVirtVM {
             config: StratoVirtConfig::default(),
             devices: vec![],
             hot_attached_devices: vec![],
+            hot_attached_ids: vec![],
             fds: vec![],
             console_socket: format!("{}/console.sock", base_dir),
             agent_socket: "".to_string(),
@@ -302,6 +466,28 @@ impl StratoVirtVM {
             pcie_root_ports_pool: None,
             pcie_root_bus: None,
             pids: Pids::default(),
+            io_limits: IoLimits::default(),
+        }
+    }
+
+    // the backend of vhost-user devices maps the guest memory,
+    // which is shared if it is file backed or mem-share is on.
+    fn memory_shared(&self) -> bool {
+        let machine = &self.config.machine;
+        self.config.mem_path.is_some()
+            || machine
+                .r#type
+                .split(',')
+                .chain(machine.options.as_deref().unwrap_or_default().split(','))
+                .any(|o| o == "mem-share=on")
+    }
+
+    // stratovirt only supports the iops limit of block devices
+    fn disk_iops(&self) -> Option<u64> {
+        if self.io_limits.disk.ops > 0 {
+            Some(self.io_limits.disk.ops)
+        } else {
+            None
         }
     }
 
@@ -433,11 +619,18 @@ impl StratoVirtVM {
         device: T,
     ) -> Result<usize> {
         let (rp_id, rp_index) = self.get_empty_rootport_slot(device.id())?;
-        let client = self
-            .client
-            .as_ref()
-            .ok_or_else(|| anyhow!("qmp client is not init"))?;
-        device.execute_hot_attach(client, &rp_id).await?;
+        let client = match self.get_client() {
+            Ok(c) => c,
+            Err(e) => {
+                self.release_rootport_slot(&device.id());
+                return Err(e);
+            }
+        };
+        if let Err(e) = device.execute_hot_attach(client, &rp_id).await {
+            self.release_rootport_slot(&device.id());
+            return Err(e);
+        }
+        self.hot_attached_ids.push(device.id());
         self.hot_attached_devices.push(Box::new(device));
         Ok(rp_index)
     }
@@ -533,6 +726,16 @@ impl StratoVirtVM {
         Err(Error::ResourceExhausted("slot of rootport".to_string()))
     }
 
+    fn release_rootport_slot(&mut self, device_id: &str) {
+        if let Some(rp) = self
+            .pcie_root_ports_pool
+            .as_mut()
+            .and_then(|p| p.root_ports.iter_mut().find(|rp| rp.device_id == device_id))
+        {
+            rp.device_id = "".to_string();
+        }
+    }
+
     fn create_vitiofs_daemon(&mut self, daemon_path: &str, base_dir: &str, shared_path: &str) {
         self.virtiofs_daemon = Some(VirtiofsDaemon {
             path: daemon_path.to_string(),
@@ -557,8 +760,12 @@ impl_recoverable!(StratoVirtVM);
 pub async fn init_stratovirt_sandboxer(
     args: &Args,
 ) -> Result<KuasarSandboxer<StratoVirtVMFactory, StratoVirtHooks>> {
-    let (config, persist_dir_path) =
-        load_config::<StratoVirtVMConfig>(args, CONFIG_STRATOVIRT_PATH).await?;
+    let (config, persist_dir_path) = match &args.kata_config {
+        Some(k) => {
+            load_kata_config(args, k, "stratovirt", Hypervisor::to_stratovirt_config).await?
+        }
+        None => load_config::<StratoVirtVMConfig>(args, CONFIG_STRATOVIRT_PATH).await?,
+    };
     let hooks = StratoVirtHooks::new(config.hypervisor.clone());
     let mut s = KuasarSandboxer::new(config.sandbox, config.hypervisor, hooks);
     if !persist_dir_path.is_empty() {
@@ -566,3 +773,25 @@ pub async fn init_stratovirt_sandboxer(
     }
     Ok(s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{devices::create_pcie_root_bus, StratoVirtVM};
+
+    #[test]
+    fn test_release_rootport_slot() {
+        let mut vm = StratoVirtVM::new("sandbox1", "", "/run/kuasar/sandbox1");
+        vm.pcie_root_bus = create_pcie_root_bus();
+        vm.create_pcie_root_ports(2).unwrap();
+
+        let (rp_id, _) = vm.get_empty_rootport_slot("blk1".to_string()).unwrap();
+        assert_eq!(rp_id, "pcie.1");
+        let (rp_id, _) = vm.get_empty_rootport_slot("blk2".to_string()).unwrap();
+        assert_eq!(rp_id, "pcie.2");
+        assert!(vm.get_empty_rootport_slot("blk3".to_string()).is_err());
+
+        vm.release_rootport_slot("blk1");
+        let (rp_id, _) = vm.get_empty_rootport_slot("blk3".to_string()).unwrap();
+        assert_eq!(rp_id, "pcie.1");
+    }
+}
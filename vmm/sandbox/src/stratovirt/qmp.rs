@@ -126,3 +126,71 @@ pub struct CpuInfoArm {}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct CpuInfoX86 {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct netdev_add {
+    #[serde(rename = "id")]
+    pub id: ::std::string::String,
+    #[serde(flatten)]
+    pub props: ::qapi::Dictionary,
+}
+
+impl QmpCommand for netdev_add {}
+impl ::qapi_spec::Command for netdev_add {
+    const NAME: &'static str = "netdev_add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct netdev_del {
+    #[serde(rename = "id")]
+    pub id: ::std::string::String,
+}
+
+impl QmpCommand for netdev_del {}
+impl ::qapi_spec::Command for netdev_del {
+    const NAME: &'static str = "netdev_del";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi::Empty;
+}
+
+// the backend is a json value like {"type": "pipe", "data": {"device": "/path/to/pipe"}}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct chardev_add {
+    #[serde(rename = "id")]
+    pub id: ::std::string::String,
+    #[serde(rename = "backend")]
+    pub backend: ::serde_json::Value,
+}
+
+impl QmpCommand for chardev_add {}
+impl ::qapi_spec::Command for chardev_add {
+    const NAME: &'static str = "chardev-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi::Empty;
+}
+
+// blockdev-add of stratovirt takes an extra iops limit, which is not in the qapi schema of qemu
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct blockdev_add {
+    #[serde(flatten)]
+    pub options: ::qapi::qmp::BlockdevOptions,
+    #[serde(
+        rename = "throttling.iops-total",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub iops: Option<u64>,
+}
+
+impl QmpCommand for blockdev_add {}
+impl ::qapi_spec::Command for blockdev_add {
+    const NAME: &'static str = "blockdev-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi::Empty;
+}
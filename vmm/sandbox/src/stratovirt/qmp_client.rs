@@ -14,29 +14,44 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::sync::Arc;
+use std::{
+    io::{self, IoSlice},
+    os::unix::io::{AsRawFd, RawFd},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use containerd_sandbox::error::Result;
-use futures_util::StreamExt;
-use log::error;
+use futures_util::{ready, StreamExt};
+use log::{error, warn};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
 use qapi::{
     futures::{QapiService, QmpStreamTokio},
-    qmp::{device_del, Event, QmpCommand},
+    qmp::{closefd, device_del, getfd, Event, QmpCommand},
 };
 use tokio::{
-    io::WriteHalf,
-    net::UnixStream,
+    io::{AsyncWrite, Interest},
+    net::{unix::OwnedWriteHalf, UnixStream},
     sync::{
         oneshot::{channel, Sender},
         Mutex,
     },
     task::JoinHandle,
+    time::timeout,
 };
 
+const QMP_EVENT_TIMEOUT_IN_SEC: u64 = 10;
+
 pub struct QmpClient {
-    qmp: QapiService<QmpStreamTokio<WriteHalf<UnixStream>>>,
+    qmp: QapiService<QmpStreamTokio<QmpWriter>>,
     watchers: Arc<Mutex<Vec<QmpEventWatcher>>>,
+    // the fds to be sent with the next command written to the socket
+    pending_fds: Arc<std::sync::Mutex<Vec<RawFd>>>,
+    // held while a command is executed, so that the pending fds go with the getfd command only
+    cmd_lock: Mutex<()>,
     #[allow(dead_code)]
     handle: JoinHandle<()>,
 }
@@ -48,7 +63,14 @@ pub struct QmpEventWatcher {
 
 impl QmpClient {
     pub async fn new(socket_addr: &str) -> Result<Self> {
-        let stream = qapi::futures::QmpStreamTokio::open_uds(socket_addr).await?;
+        let socket = UnixStream::connect(socket_addr).await?;
+        let (r, w) = socket.into_split();
+        let pending_fds = Arc::new(std::sync::Mutex::new(vec![]));
+        let w = QmpWriter {
+            inner: w,
+            pending_fds: pending_fds.clone(),
+        };
+        let stream = QmpStreamTokio::open_split(r, w).await?;
         let stream = stream.negotiate().await?;
         let (service, mut events) = stream.into_parts();
         let event_watchers = Arc::new(Mutex::new(Vec::<QmpEventWatcher>::new()));
@@ -76,12 +98,15 @@ impl QmpClient {
         let client = Self {
             qmp: service,
             watchers: event_watchers,
+            pending_fds,
+            cmd_lock: Mutex::new(()),
             handle,
         };
         Ok(client)
     }
 
     pub async fn execute<C: QmpCommand + 'static>(&self, cmd: C) -> Result<C::Ok> {
+        let _guard = self.cmd_lock.lock().await;
         match self.qmp.execute(cmd).await {
             Ok(r) => Ok(r),
             Err(e) => Err(anyhow!("failed to execute qmp, {}", e).into()),
@@ -102,13 +127,51 @@ impl QmpClient {
             let mut watchers = self.watchers.lock().await;
             watchers.push(watcher);
         }
-        match self.qmp.execute(cmd).await {
-            Ok(r) => {
-                //TODO  add timeout
-                let _ = rx.await;
-                Ok(r)
-            }
+        let executed = {
+            let _guard = self.cmd_lock.lock().await;
+            self.qmp.execute(cmd).await
+        };
+        let res: Result<C::Ok> = match executed {
+            Ok(r) => match timeout(Duration::from_secs(QMP_EVENT_TIMEOUT_IN_SEC), rx).await {
+                Ok(Ok(_)) => Ok(r),
+                Ok(Err(e)) => Err(anyhow!("failed to wait for qmp event, {}", e).into()),
+                Err(_) => Err(anyhow!("timeout waiting for qmp event").into()),
+            },
             Err(e) => Err(anyhow!("failed to execute qmp, {}", e).into()),
+        };
+        // the receiver is dropped by now, remove the watcher if the event never came
+        self.watchers.lock().await.retain(|w| !w.sender.is_closed());
+        res
+    }
+
+    /// Send the fd to stratovirt by getfd, the fd can be referred by fd_name in later commands,
+    /// the caller still owns the fd and should close it.
+    pub async fn send_fd(&self, fd_name: &str, fd: RawFd) -> Result<()> {
+        let _guard = self.cmd_lock.lock().await;
+        *self.pending_fds.lock().unwrap() = vec![fd];
+        let res = self
+            .qmp
+            .execute(getfd {
+                fdname: fd_name.to_string(),
+            })
+            .await;
+        // the fds are not sent if the command failed to be written
+        self.pending_fds.lock().unwrap().clear();
+        res.map_err(|e| anyhow!("failed to send fd {} by getfd, {}", fd_name, e))?;
+        Ok(())
+    }
+
+    /// Close the fds sent by send_fd, which are not taken by any device.
+    pub async fn close_fds(&self, fd_names: &[String]) {
+        for name in fd_names {
+            if let Err(e) = self
+                .execute(closefd {
+                    fdname: name.to_string(),
+                })
+                .await
+            {
+                warn!("failed to close fd {} in stratovirt, {}", name, e);
+            }
         }
     }
 
@@ -133,3 +196,53 @@ impl QmpClient {
         Ok(())
     }
 }
+
+/// The writer of the qmp socket, qapi can not carry ancillary data,
+/// so the pending fds are sent with the next write, which is the getfd command.
+struct QmpWriter {
+    inner: OwnedWriteHalf,
+    pending_fds: Arc<std::sync::Mutex<Vec<RawFd>>>,
+}
+
+impl AsyncWrite for QmpWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut fds = this.pending_fds.lock().unwrap();
+        if fds.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        let stream: &UnixStream = this.inner.as_ref();
+        loop {
+            ready!(stream.poll_write_ready(cx))?;
+            match stream.try_io(Interest::WRITABLE, || {
+                sendmsg::<()>(
+                    stream.as_raw_fd(),
+                    &[IoSlice::new(buf)],
+                    &[ControlMessage::ScmRights(&fds[..])],
+                    MsgFlags::empty(),
+                    None,
+                )
+                .map_err(io::Error::from)
+            }) {
+                Ok(n) => {
+                    fds.clear();
+                    return Poll::Ready(Ok(n));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,203 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{os::unix::io::FromRawFd, path::Path, str::FromStr};
+
+use log::{log, warn, Level};
+use serde::Deserialize;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+use vmm_common::TASK_LOG_PORT;
+
+use crate::client::connect_to_socket;
+
+const TASK_LOG_FILE: &str = "task.log";
+
+/// Where the logs forwarded from the guest agent go
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaskLogOutput {
+    Sandboxer,
+    File,
+    None,
+}
+
+impl Default for TaskLogOutput {
+    fn default() -> Self {
+        Self::Sandboxer
+    }
+}
+
+impl TaskLogOutput {
+    pub fn from(s: &str) -> Self {
+        match s {
+            "file" => Self::File,
+            "none" => Self::None,
+            _ => Self::Sandboxer,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskLogRecord {
+    level: String,
+    #[serde(default)]
+    target: String,
+    msg: String,
+}
+
+// the logs are served on another port of the vsock that the ttrpc server listens on,
+// it is not possible if the agent is connected by a serial port.
+fn log_address(address: &str) -> Option<String> {
+    if !address.starts_with("vsock://") && !address.starts_with("hvsock://") {
+        return None;
+    }
+    address
+        .rsplit_once(':')
+        .map(|(addr, _)| format!("{}:{}", addr, TASK_LOG_PORT))
+}
+
+/// Forward the logs of the guest agent to the sandboxer log, tagged with the sandbox id,
+/// or to the task.log file in the base dir of the sandbox, until the connection is closed.
+pub(crate) async fn forward_task_log(
+    id: &str,
+    agent_address: &str,
+    base_dir: &str,
+    output: &TaskLogOutput,
+) {
+    if output == &TaskLogOutput::None {
+        return;
+    }
+    let address = match log_address(agent_address) {
+        Some(a) => a,
+        None => {
+            warn!(
+                "task log of {} can not be forwarded from {}",
+                id, agent_address
+            );
+            return;
+        }
+    };
+    let fd = match connect_to_socket(&address).await {
+        Ok(fd) => fd,
+        Err(e) => {
+            warn!("failed to connect task log of {}: {}", id, e);
+            return;
+        }
+    };
+    let id = id.to_string();
+    let log_file = match output {
+        TaskLogOutput::File => Some(Path::new(base_dir).join(TASK_LOG_FILE)),
+        _ => None,
+    };
+    // the fd is a connected unix socket or vsock, both of which are read as a stream
+    let stream = unsafe { std::os::unix::net::UnixStream::from_raw_fd(fd) };
+    let stream = match stream
+        .set_nonblocking(true)
+        .and_then(|_| UnixStream::from_std(stream))
+    {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("failed to read task log of {}: {}", id, e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        let mut file = match log_file {
+            Some(p) => match OpenOptions::new().create(true).append(true).open(p).await {
+                Ok(f) => Some(f),
+                Err(e) => {
+                    warn!("failed to open task log file of {}: {}", id, e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let mut lines = BufReader::new(stream).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match file.as_mut() {
+                Some(f) => {
+                    if let Some(l) = tag_record(&id, &line) {
+                        f.write_all(format!("{}\n", l).as_bytes())
+                            .await
+                            .unwrap_or_else(|e| warn!("failed to write task log of {}: {}", id, e));
+                    }
+                }
+                None => log_record(&id, &line),
+            }
+        }
+    });
+}
+
+fn log_record(id: &str, line: &str) {
+    match serde_json::from_str::<TaskLogRecord>(line) {
+        Ok(r) => {
+            let level = Level::from_str(&r.level).unwrap_or(Level::Info);
+            log!(level, "task of {}: [{}] {}", id, r.target, r.msg);
+        }
+        Err(_) => warn!("invalid task log record of {}: {}", id, line),
+    }
+}
+
+// add the sandbox id to the json record
+fn tag_record(id: &str, line: &str) -> Option<String> {
+    let mut record = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    record
+        .as_object_mut()?
+        .insert("sandbox_id".to_string(), id.into());
+    serde_json::to_string(&record).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{log_address, tag_record, TaskLogOutput};
+
+    #[test]
+    fn test_log_address() {
+        assert_eq!(
+            log_address("vsock://3:1024"),
+            Some("vsock://3:1026".to_string())
+        );
+        assert_eq!(
+            log_address("hvsock:///run/kuasar/abc/task.vsock:1024"),
+            Some("hvsock:///run/kuasar/abc/task.vsock:1026".to_string())
+        );
+        assert_eq!(log_address("/run/kuasar/abc/agent.sock"), None);
+    }
+
+    #[test]
+    fn test_tag_record() {
+        let line = tag_record(
+            "abc",
+            r#"{"time":1,"level":"INFO","target":"vmm_task","msg":"hi"}"#,
+        )
+        .unwrap();
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["sandbox_id"], "abc");
+        assert_eq!(v["msg"], "hi");
+        assert_eq!(tag_record("abc", "not json"), None);
+        assert_eq!(tag_record("abc", "[1]"), None);
+    }
+
+    #[test]
+    fn test_task_log_output() {
+        assert_eq!(TaskLogOutput::from("file"), TaskLogOutput::File);
+        assert_eq!(TaskLogOutput::from("none"), TaskLogOutput::None);
+        assert_eq!(TaskLogOutput::from(""), TaskLogOutput::Sandboxer);
+    }
+}
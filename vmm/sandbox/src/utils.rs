@@ -15,6 +15,7 @@ limitations under the License.
 */
 
 use std::{
+    collections::HashMap,
     os::unix::{
         io::RawFd,
         prelude::{AsRawFd, FromRawFd, OwnedFd},
@@ -26,7 +27,7 @@ use std::{
 
 use anyhow::anyhow;
 use containerd_sandbox::{
-    cri::api::v1::{DnsConfig, LinuxContainerResources},
+    cri::api::v1::{DnsConfig, LinuxContainerResources, NamespaceMode},
     data::SandboxData,
     error::{Error, Result},
 };
@@ -92,6 +93,25 @@ pub fn get_hostname(data: &SandboxData) -> String {
         .unwrap_or_default()
 }
 
+pub fn get_sysctls(data: &SandboxData) -> HashMap<String, String> {
+    data.config
+        .as_ref()
+        .and_then(|c| c.linux.as_ref())
+        .map(|l| l.sysctls.clone())
+        .unwrap_or_default()
+}
+
+/// Whether the containers of the pod share a pid namespace, by "shareProcessNamespace: true" of the pod.
+pub fn has_shared_pid_namespace(data: &SandboxData) -> bool {
+    data.config
+        .as_ref()
+        .and_then(|c| c.linux.as_ref())
+        .and_then(|l| l.security_context.as_ref())
+        .and_then(|s| s.namespace_options.as_ref())
+        .map(|n| n.pid() == NamespaceMode::Pod)
+        .unwrap_or_default()
+}
+
 pub fn get_dns_config(data: &SandboxData) -> Option<&DnsConfig> {
     data.config.as_ref().and_then(|c| c.dns_config.as_ref())
 }
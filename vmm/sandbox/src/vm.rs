@@ -71,6 +71,28 @@ pub trait VM: Serialize + Sync + Send {
     async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>>;
     async fn vcpus(&self) -> Result<VcpuThreads>;
     fn pids(&self) -> Pids;
+    // grow the memory of a running vm to at least the memory it is booted with plus
+    // extra_memory_in_mb, shrinking is not supported.
+    async fn resize_memory(&mut self, _extra_memory_in_mb: u64) -> Result<()> {
+        Err(Error::Unimplemented("resize memory".to_string()))
+    }
+    // notify the vm that the block device is grown to size in bytes
+    async fn resize_block_device(&mut self, _id: &str, _size: u64) -> Result<()> {
+        Err(Error::Unimplemented("resize block device".to_string()))
+    }
+    // stop the vcpus of the vm, the memory and devices are kept
+    async fn pause(&mut self) -> Result<()> {
+        Err(Error::Unimplemented("pause".to_string()))
+    }
+    async fn resume(&mut self) -> Result<()> {
+        Err(Error::Unimplemented("resume".to_string()))
+    }
+    // ids of the devices hot attached to the vm
+    async fn hot_attached_devices(&mut self) -> Result<Vec<String>> {
+        Err(Error::Unimplemented(
+            "list hot attached devices".to_string(),
+        ))
+    }
 }
 
 #[macro_export]
@@ -169,7 +191,7 @@ impl BlockDriver {
     pub fn to_bus_type(&self) -> BusType {
         match self {
             BlockDriver::VirtioBlk => BusType::PCI,
-            BlockDriver::VirtioMmio => BusType::NULL,
+            BlockDriver::VirtioMmio => BusType::MMIO,
             BlockDriver::VirtioScsi => BusType::SCSI,
         }
     }
@@ -212,3 +234,23 @@ pub struct Pids {
     pub vmm_pid: Option<u32>,
     pub affilicated_pids: Vec<u32>,
 }
+
+/// Limits of a device, the bandwidth is in bytes per second, zero means unlimited.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub bandwidth: u64,
+    pub ops: u64,
+}
+
+impl RateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.bandwidth == 0 && self.ops == 0
+    }
+}
+
+/// I/O limits applied to every block and network device of a sandbox.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IoLimits {
+    pub disk: RateLimit,
+    pub net: RateLimit,
+}
@@ -0,0 +1,287 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use containerd_shim::{
+    io_error,
+    protos::{
+        cgroups::metrics::{
+            BlkIOEntry, BlkIOStat, CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics, PidsStat,
+            Throttle,
+        },
+        protobuf::MessageField,
+    },
+    Error, Result,
+};
+use log::{debug, warn};
+
+use crate::mount::SYSFS_CGROUPPATH;
+
+pub const CGROUP_V1: u32 = 1;
+pub const CGROUP_V2: u32 = 2;
+
+const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
+const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
+
+/// Whether the cgroup2 filesystem is mounted at /sys/fs/cgroup, so that runc and
+/// the stats collection of containers work on the unified hierarchy.
+pub fn is_unified() -> bool {
+    Path::new(SYSFS_CGROUPPATH)
+        .join(CGROUP_CONTROLLERS)
+        .exists()
+}
+
+/// Enable all the available controllers for the children of the root cgroup,
+/// controllers are enabled one by one as failing to enable one of them should not stop others.
+pub async fn enable_controllers() -> Result<()> {
+    let root = Path::new(SYSFS_CGROUPPATH);
+    let controllers = tokio::fs::read_to_string(root.join(CGROUP_CONTROLLERS))
+        .await
+        .map_err(io_error!(e, "failed to read {}", CGROUP_CONTROLLERS))?;
+    let subtree_control = root.join(CGROUP_SUBTREE_CONTROL);
+    for c in controllers.split_whitespace() {
+        match tokio::fs::write(&subtree_control, format!("+{}", c)).await {
+            Ok(_) => debug!("cgroup controller {} enabled", c),
+            Err(e) => warn!("failed to enable cgroup controller {}: {}", c, e),
+        }
+    }
+    Ok(())
+}
+
+/// Collect metrics of the cgroup that the process is in, the cgroup v2 files are
+/// converted to the v1 metrics, which is what the task api reports.
+pub async fn collect_metrics_v2(pid: u32) -> Result<Metrics> {
+    let cgroup_file = format!("/proc/{}/cgroup", pid);
+    let content = tokio::fs::read_to_string(&cgroup_file)
+        .await
+        .map_err(io_error!(e, "failed to read {}", cgroup_file))?;
+    let path = unified_cgroup_path(&content).ok_or_else(|| {
+        Error::NotFoundError(format!("no cgroup v2 path of process {} found", pid))
+    })?;
+    let dir = Path::new(SYSFS_CGROUPPATH).join(path.trim_start_matches('/'));
+
+    let mut metrics = Metrics::new();
+    if let Some(s) = read_cgroup_file(&dir, "cpu.stat").await {
+        metrics.cpu = MessageField::some(parse_cpu_stat(&s));
+    }
+    if let Some(s) = read_cgroup_file(&dir, "memory.stat").await {
+        let mut memory = parse_memory_stat(&s);
+        let mut usage = MemoryEntry::new();
+        usage.usage = read_cgroup_value(&dir, "memory.current").await;
+        usage.limit = read_cgroup_value(&dir, "memory.max").await;
+        usage.max = read_cgroup_value(&dir, "memory.peak").await;
+        if let Some(events) = read_cgroup_file(&dir, "memory.events").await {
+            usage.failcnt = flat_keyed_value(&events, "max");
+        }
+        memory.hierarchical_memory_limit = usage.limit;
+        memory.usage = MessageField::some(usage);
+        metrics.memory = MessageField::some(memory);
+    }
+    if let Some(s) = read_cgroup_file(&dir, "pids.current").await {
+        let mut pids = PidsStat::new();
+        pids.current = parse_value(&s);
+        pids.limit = read_cgroup_value(&dir, "pids.max").await;
+        metrics.pids = MessageField::some(pids);
+    }
+    if let Some(s) = read_cgroup_file(&dir, "io.stat").await {
+        metrics.blkio = MessageField::some(parse_io_stat(&s));
+    }
+    Ok(metrics)
+}
+
+async fn read_cgroup_file(dir: &Path, name: &str) -> Option<String> {
+    tokio::fs::read_to_string(dir.join(name)).await.ok()
+}
+
+// a missing file or "max" is treated as 0, which means no limit in the v1 metrics
+async fn read_cgroup_value(dir: &Path, name: &str) -> u64 {
+    read_cgroup_file(dir, name)
+        .await
+        .map(|s| parse_value(&s))
+        .unwrap_or_default()
+}
+
+fn parse_value(content: &str) -> u64 {
+    content.trim().parse().unwrap_or_default()
+}
+
+// the line of the unified hierarchy in /proc/<pid>/cgroup is like "0::/kubepods/pod1/abc"
+pub fn unified_cgroup_path(content: &str) -> Option<&str> {
+    content.lines().find_map(|l| l.strip_prefix("0::"))
+}
+
+// value of the key in the flat keyed files like cpu.stat or memory.events
+pub fn flat_keyed_value(content: &str, key: &str) -> u64 {
+    content
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| parse_value(v))
+        .unwrap_or_default()
+}
+
+fn parse_cpu_stat(content: &str) -> CPUStat {
+    let value = |key: &str| flat_keyed_value(content, key);
+    let mut usage = CPUUsage::new();
+    usage.total = value("usage_usec") * 1000;
+    usage.user = value("user_usec") * 1000;
+    usage.kernel = value("system_usec") * 1000;
+    let mut throttling = Throttle::new();
+    throttling.periods = value("nr_periods");
+    throttling.throttled_periods = value("nr_throttled");
+    throttling.throttled_time = value("throttled_usec") * 1000;
+
+    let mut cpu = CPUStat::new();
+    cpu.usage = MessageField::some(usage);
+    cpu.throttling = MessageField::some(throttling);
+    cpu
+}
+
+// memory.stat of cgroup v2 is hierarchical, so the total_ fields are the same as the local ones.
+fn parse_memory_stat(content: &str) -> MemoryStat {
+    let value = |key: &str| flat_keyed_value(content, key);
+    let mut memory = MemoryStat::new();
+    memory.cache = value("file");
+    memory.rss = value("anon");
+    memory.rss_huge = value("anon_thp");
+    memory.mapped_file = value("file_mapped");
+    memory.dirty = value("file_dirty");
+    memory.writeback = value("file_writeback");
+    memory.pgfault = value("pgfault");
+    memory.pgmajfault = value("pgmajfault");
+    memory.inactive_anon = value("inactive_anon");
+    memory.active_anon = value("active_anon");
+    memory.inactive_file = value("inactive_file");
+    memory.active_file = value("active_file");
+    memory.unevictable = value("unevictable");
+    memory.total_cache = memory.cache;
+    memory.total_rss = memory.rss;
+    memory.total_rss_huge = memory.rss_huge;
+    memory.total_mapped_file = memory.mapped_file;
+    memory.total_dirty = memory.dirty;
+    memory.total_writeback = memory.writeback;
+    memory.total_pgfault = memory.pgfault;
+    memory.total_pgmajfault = memory.pgmajfault;
+    memory.total_inactive_anon = memory.inactive_anon;
+    memory.total_active_anon = memory.active_anon;
+    memory.total_inactive_file = memory.inactive_file;
+    memory.total_active_file = memory.active_file;
+    memory.total_unevictable = memory.unevictable;
+    memory
+}
+
+// lines like "8:0 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0"
+fn parse_io_stat(content: &str) -> BlkIOStat {
+    let mut blkio = BlkIOStat::new();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (major, minor) = match fields.next().and_then(|d| d.split_once(':')) {
+            Some((major, minor)) => (parse_value(major), parse_value(minor)),
+            None => continue,
+        };
+        for field in fields {
+            let (key, value) = match field.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let (op, entries) = match key {
+                "rbytes" => ("Read", &mut blkio.io_service_bytes_recursive),
+                "wbytes" => ("Write", &mut blkio.io_service_bytes_recursive),
+                "rios" => ("Read", &mut blkio.io_serviced_recursive),
+                "wios" => ("Write", &mut blkio.io_serviced_recursive),
+                _ => continue,
+            };
+            let mut entry = BlkIOEntry::new();
+            entry.op = op.to_string();
+            entry.major = major;
+            entry.minor = minor;
+            entry.value = parse_value(value);
+            entries.push(entry);
+        }
+    }
+    blkio
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        flat_keyed_value, parse_cpu_stat, parse_io_stat, parse_memory_stat, unified_cgroup_path,
+    };
+
+    #[test]
+    fn test_unified_cgroup_path() {
+        assert_eq!(
+            unified_cgroup_path("0::/kubepods/besteffort/pod1/abc\n"),
+            Some("/kubepods/besteffort/pod1/abc")
+        );
+        assert_eq!(unified_cgroup_path("12:pids:/abc\n11:memory:/abc\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cpu_stat() {
+        let content = "\
+usage_usec 1500
+user_usec 1000
+system_usec 500
+nr_periods 10
+nr_throttled 2
+throttled_usec 300
+";
+        let cpu = parse_cpu_stat(content);
+        assert_eq!(cpu.usage.total, 1500000);
+        assert_eq!(cpu.usage.user, 1000000);
+        assert_eq!(cpu.usage.kernel, 500000);
+        assert_eq!(cpu.throttling.periods, 10);
+        assert_eq!(cpu.throttling.throttled_periods, 2);
+        assert_eq!(cpu.throttling.throttled_time, 300000);
+    }
+
+    #[test]
+    fn test_parse_memory_stat() {
+        let content = "\
+anon 4096
+file 8192
+file_mapped 1024
+inactive_file 2048
+pgfault 7
+";
+        let memory = parse_memory_stat(content);
+        assert_eq!(memory.rss, 4096);
+        assert_eq!(memory.cache, 8192);
+        assert_eq!(memory.mapped_file, 1024);
+        assert_eq!(memory.total_inactive_file, 2048);
+        assert_eq!(memory.pgfault, 7);
+        assert_eq!(flat_keyed_value("low 0\nhigh 0\nmax 3\noom 1\n", "max"), 3);
+    }
+
+    #[test]
+    fn test_parse_io_stat() {
+        let content = "\
+8:0 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0
+253:16 rbytes=4096 wbytes=0 rios=1 wios=0 dbytes=0 dios=0
+";
+        let blkio = parse_io_stat(content);
+        assert_eq!(blkio.io_service_bytes_recursive.len(), 4);
+        assert_eq!(blkio.io_serviced_recursive.len(), 4);
+        let entry = &blkio.io_service_bytes_recursive[1];
+        assert_eq!(entry.op, "Write");
+        assert_eq!((entry.major, entry.minor, entry.value), (8, 0, 314773504));
+        let entry = &blkio.io_serviced_recursive[2];
+        assert_eq!(entry.op, "Read");
+        assert_eq!((entry.major, entry.minor, entry.value), (253, 16, 1));
+    }
+}
@@ -17,9 +17,13 @@ limitations under the License.
 use containerd_shim::{io_error, Error, Result};
 use tokio::fs::read_to_string;
 
+use crate::cgroup::{CGROUP_V1, CGROUP_V2};
+
 const SHAREFS_TYPE: &str = "task.sharefs_type";
 const LOG_LEVEL: &str = "task.log_level";
 const TASK_DEBUG: &str = "task.debug";
+const POLICY: &str = "task.policy";
+const CGROUP_VERSION: &str = "task.cgroup_version";
 
 macro_rules! parse_cmdline {
     ($param:ident, $key:ident, $field:expr) => {
@@ -42,6 +46,8 @@ pub struct TaskConfig {
     pub(crate) sharefs_type: String,
     pub(crate) log_level: String,
     pub(crate) debug: bool,
+    pub(crate) policy_path: String,
+    pub(crate) cgroup_version: u32,
 }
 
 impl Default for TaskConfig {
@@ -50,6 +56,8 @@ impl Default for TaskConfig {
             sharefs_type: "9p".to_string(),
             log_level: "info".to_string(),
             debug: false,
+            policy_path: "".to_string(),
+            cgroup_version: CGROUP_V1,
         }
     }
 }
@@ -66,7 +74,23 @@ impl TaskConfig {
             parse_cmdline!(param, SHAREFS_TYPE, config.sharefs_type, String::from);
             parse_cmdline!(param, LOG_LEVEL, config.log_level, String::from);
             parse_cmdline!(param, TASK_DEBUG, config.debug);
+            parse_cmdline!(param, POLICY, config.policy_path, String::from);
+            parse_cmdline!(
+                param,
+                CGROUP_VERSION,
+                config.cgroup_version,
+                parse_cgroup_version
+            );
         }
         Ok(config)
     }
 }
+
+// any value other than "2" falls back to cgroup v1
+fn parse_cgroup_version(v: &str) -> u32 {
+    if v == "2" {
+        CGROUP_V2
+    } else {
+        CGROUP_V1
+    }
+}
@@ -15,8 +15,12 @@ limitations under the License.
 */
 
 use std::{
-    convert::TryFrom, io::SeekFrom, os::unix::prelude::ExitStatusExt, path::Path,
-    process::ExitStatus, sync::Arc,
+    convert::TryFrom,
+    io::SeekFrom,
+    os::unix::prelude::ExitStatusExt,
+    path::{Component, Path, PathBuf},
+    process::ExitStatus,
+    sync::Arc,
 };
 
 use async_trait::async_trait;
@@ -31,18 +35,19 @@ use containerd_shim::{
     },
     error::{Error, Result},
     io::Stdio,
+    io_error,
     monitor::Topic,
     other, other_error,
     protos::{
         cgroups::metrics::Metrics,
         protobuf::{CodedInputStream, Message},
-        shim::oci::Options,
+        shim::oci::{CheckpointOptions, Options},
         types::task::ProcessInfo,
     },
     util::read_spec,
     ExitSignal,
 };
-use log::{debug, error};
+use log::{debug, error, warn};
 use nix::{sys::signalfd::signal::kill, unistd::Pid};
 use oci_spec::runtime::{LinuxResources, Process, Spec};
 use runc::{options::GlobalOpts, Runc, Spawner};
@@ -53,11 +58,14 @@ use tokio::{
     process::Command,
     sync::Mutex,
 };
-use vmm_common::{mount::get_mount_type, storage::Storage, KUASAR_STATE_DIR};
+use vmm_common::{mount::get_mount_type, storage::Storage, CHECKPOINT_DIR, KUASAR_STATE_DIR};
 
 use crate::{
+    cgroup,
     device::rescan_pci_bus,
     io::{convert_stdio, copy_io_or_console, create_io},
+    pid_ns::{ensure_sandbox_pid_ns, joins_sandbox_pid_ns},
+    policy::Policy,
     sandbox::SandboxResources,
     util::{read_io, read_storages, wait_pid},
 };
@@ -70,6 +78,9 @@ pub const GROUP_LABELS: [&str; 2] = [
 pub const INIT_PID_FILE: &str = "init.pid";
 
 const STORAGE_ANNOTATION: &str = "io.kuasar.storages";
+// runc runs the containers from the bundles in the guest, with the spec checked by the policy,
+// the bundles in the shared dir can be changed by the host after the check.
+const GUEST_BUNDLE_DIR: &str = "/run/kuasar/bundles";
 
 pub type ExecProcess = ProcessTemplate<KuasarExecLifecycle>;
 pub type InitProcess = ProcessTemplate<KuasarInitLifecycle>;
@@ -79,11 +90,16 @@ pub type KuasarContainer = ContainerTemplate<InitProcess, ExecProcess, KuasarExe
 #[derive(Clone)]
 pub(crate) struct KuasarFactory {
     sandbox: Arc<Mutex<SandboxResources>>,
+    policy: Arc<Policy>,
 }
 
 pub struct KuasarExecFactory {
+    sandbox: Arc<Mutex<SandboxResources>>,
+    policy: Arc<Policy>,
     runtime: Runc,
     bundle: String,
+    // the bundle in the shared dir, where the host puts the io of the execs
+    shared_bundle: String,
     io_uid: u32,
     io_gid: u32,
 }
@@ -102,6 +118,9 @@ pub struct KuasarInitLifecycle {
     runtime: Runc,
     opts: Options,
     bundle: String,
+    runc_root: PathBuf,
+    // image path in the guest to restore the container from, instead of creating it
+    checkpoint: String,
     exit_signal: Arc<ExitSignal>,
 }
 
@@ -119,14 +138,23 @@ impl ContainerFactory<KuasarContainer> for KuasarFactory {
         req: &CreateTaskRequest,
     ) -> containerd_shim::Result<KuasarContainer> {
         rescan_pci_bus().await?;
-        let bundle = format!("{}/{}", KUASAR_STATE_DIR, req.id);
-        let spec: Spec = read_spec(&bundle).await?;
+        let shared_bundle = format!("{}/{}", KUASAR_STATE_DIR, req.id);
+        let spec: Spec = read_spec(&shared_bundle).await?;
+        self.policy.check_create(req.id(), &spec)?;
+        if !req.checkpoint.is_empty() {
+            self.policy.check_restore(req.id())?;
+        }
+        if joins_sandbox_pid_ns(&spec) {
+            ensure_sandbox_pid_ns().await?;
+        }
         let annotations = spec.annotations().clone().unwrap_or_default();
         let storages = if let Some(storage_str) = annotations.get(STORAGE_ANNOTATION) {
             serde_json::from_str::<Vec<Storage>>(storage_str)?
         } else {
-            read_storages(&bundle, req.id()).await?
+            read_storages(&shared_bundle, req.id()).await?
         };
+        self.policy.check_storages(req.id(), &storages)?;
+        let bundle = create_guest_bundle(req.id(), &shared_bundle, spec).await?;
         self.sandbox
             .lock()
             .await
@@ -154,29 +182,35 @@ impl ContainerFactory<KuasarContainer> for KuasarFactory {
 
         let id = req.id();
 
-        let stdio = match read_io(&bundle, req.id(), None).await {
+        let stdio = match read_io(&shared_bundle, req.id(), None).await {
             Ok(io) => Stdio::new(&io.stdin, &io.stdout, &io.stderr, io.terminal),
             Err(_) => Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal()),
         };
 
         // for qemu, the io path is pci address for virtio-serial
         // that needs to be converted to the serial file path
-        let stdio = convert_stdio(&stdio).await?;
+        let stdio = convert_stdio(&stdio, &self.sandbox).await?;
 
-        let mut init = InitProcess::new(
-            id,
-            stdio,
-            KuasarInitLifecycle::new(runc.clone(), opts.clone(), &bundle),
-        );
+        let mut lifecycle = KuasarInitLifecycle::new(runc.clone(), opts.clone(), &bundle, ns);
+        if !req.checkpoint.is_empty() {
+            lifecycle.checkpoint = guest_checkpoint_path(&req.checkpoint, id)?;
+        }
+        let mut init = InitProcess::new(id, stdio, lifecycle);
 
-        self.do_create(&mut init).await?;
+        // the container restored from a checkpoint is created and started by runc restore when it starts
+        if init.lifecycle.checkpoint.is_empty() {
+            self.do_create(&mut init).await?;
+        }
         let container = KuasarContainer {
             id: id.to_string(),
             bundle: bundle.to_string(),
             init,
             process_factory: KuasarExecFactory {
+                sandbox: self.sandbox.clone(),
+                policy: self.policy.clone(),
                 runtime: runc,
                 bundle: bundle.to_string(),
+                shared_bundle,
                 io_uid: opts.io_uid,
                 io_gid: opts.io_gid,
             },
@@ -187,13 +221,16 @@ impl ContainerFactory<KuasarContainer> for KuasarFactory {
 
     async fn cleanup(&self, _ns: &str, c: &KuasarContainer) -> containerd_shim::Result<()> {
         self.sandbox.lock().await.defer_storages(&c.id).await?;
+        tokio::fs::remove_dir_all(&c.bundle)
+            .await
+            .unwrap_or_else(|e| warn!("failed to remove bundle {}: {}", c.bundle, e));
         Ok(())
     }
 }
 
 impl KuasarFactory {
-    pub fn new(sandbox: Arc<Mutex<SandboxResources>>) -> Self {
-        Self { sandbox }
+    pub fn new(sandbox: Arc<Mutex<SandboxResources>>, policy: Arc<Policy>) -> Self {
+        Self { sandbox, policy }
     }
 
     async fn do_create(&self, init: &mut InitProcess) -> Result<()> {
@@ -315,11 +352,12 @@ async fn get_last_runtime_error(bundle: &str) -> Result<String> {
 impl ProcessFactory<ExecProcess> for KuasarExecFactory {
     async fn create(&self, req: &ExecProcessRequest) -> Result<ExecProcess> {
         let p = get_spec_from_request(req)?;
-        let stdio = match read_io(&self.bundle, req.id(), Some(req.exec_id())).await {
+        self.policy.check_exec(req.id(), req.exec_id(), &p)?;
+        let stdio = match read_io(&self.shared_bundle, req.id(), Some(req.exec_id())).await {
             Ok(io) => Stdio::new(&io.stdin, &io.stdout, &io.stderr, io.terminal),
             Err(_) => Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal()),
         };
-        let stdio = convert_stdio(&stdio).await?;
+        let stdio = convert_stdio(&stdio, &self.sandbox).await?;
         Ok(ExecProcess {
             state: Status::CREATED,
             id: req.exec_id.to_string(),
@@ -346,6 +384,9 @@ impl ProcessFactory<ExecProcess> for KuasarExecFactory {
 #[async_trait]
 impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
     async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
+        if !self.checkpoint.is_empty() {
+            return self.restore(p).await;
+        }
         if let Err(e) = self.runtime.start(p.id.as_str()).await {
             return Err(runtime_error(&p.lifecycle.bundle, e, "OCI runtime start failed").await);
         }
@@ -418,6 +459,9 @@ impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
                 p.pid
             ));
         }
+        if cgroup::is_unified() {
+            return cgroup::collect_metrics_v2(p.pid as u32).await;
+        }
         containerd_shim::cgroup::collect_metrics(p.pid as u32)
     }
 
@@ -443,19 +487,263 @@ impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
 }
 
 impl KuasarInitLifecycle {
-    pub fn new(runtime: Runc, opts: Options, bundle: &str) -> Self {
+    pub fn new(runtime: Runc, opts: Options, bundle: &str, namespace: &str) -> Self {
         let work_dir = Path::new(bundle).join("work");
         let mut opts = opts;
         if opts.criu_path().is_empty() {
             opts.criu_path = work_dir.to_string_lossy().to_string();
         }
+        let runc_root = runc_root(namespace, &opts);
         Self {
             runtime,
             opts,
             bundle: bundle.to_string(),
+            runc_root,
+            checkpoint: "".to_string(),
             exit_signal: Default::default(),
         }
     }
+
+    /// Checkpoint the container by runc and criu, the image is written to a path on the shared dir,
+    /// so that the host is able to archive it.
+    pub async fn checkpoint(
+        &self,
+        container_id: &str,
+        path: &str,
+        opts: &CheckpointOptions,
+    ) -> Result<()> {
+        let image_path = if opts.image_path.is_empty() {
+            path
+        } else {
+            opts.image_path.as_str()
+        };
+        let image_path = guest_checkpoint_path(image_path, container_id)?;
+        let work_path = self.criu_work_path(container_id, &opts.work_path);
+        let mut args = vec![
+            "checkpoint".to_string(),
+            "--image-path".to_string(),
+            image_path,
+            "--work-path".to_string(),
+            work_path.to_string(),
+        ];
+        if !opts.exit {
+            args.push("--leave-running".to_string());
+        }
+        if opts.open_tcp {
+            args.push("--tcp-established".to_string());
+        }
+        if opts.external_unix_sockets {
+            args.push("--ext-unix-sk".to_string());
+        }
+        if opts.terminal {
+            args.push("--shell-job".to_string());
+        }
+        if opts.file_locks {
+            args.push("--file-locks".to_string());
+        }
+        for ns in opts.empty_namespaces.iter() {
+            args.push("--empty-ns".to_string());
+            args.push(ns.to_string());
+        }
+        if !opts.cgroups_mode.is_empty() {
+            args.push("--manage-cgroups-mode".to_string());
+            args.push(opts.cgroups_mode.to_string());
+        }
+        args.push(container_id.to_string());
+
+        let mut cmd = self.runc_command(&args);
+        cmd.stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        self.execute_runc(cmd, Box::new(|| {}), "checkpoint")
+            .await?;
+        // the work dir on the shared dir is kept only if the checkpoint failed, for the logs of criu
+        if !opts.work_path.is_empty() {
+            tokio::fs::remove_dir_all(&work_path)
+                .await
+                .unwrap_or_else(|e| warn!("failed to remove criu work dir {}: {}", work_path, e));
+        }
+        Ok(())
+    }
+
+    // runc restore creates and starts the container in one step, so the io is set up here
+    // instead of in do_create.
+    async fn restore(&self, p: &mut InitProcess) -> Result<()> {
+        let pid_path = Path::new(&self.bundle).join(INIT_PID_FILE);
+        let mut args = vec![
+            "restore".to_string(),
+            "--detach".to_string(),
+            "--bundle".to_string(),
+            self.bundle.to_string(),
+            "--pid-file".to_string(),
+            pid_path.to_string_lossy().to_string(),
+            "--image-path".to_string(),
+            self.checkpoint.to_string(),
+            "--work-path".to_string(),
+            self.criu_work_path(&p.id, ""),
+        ];
+        let no_pivot_root = self.opts.no_pivot_root
+            || matches!(get_mount_type("/"), Ok(m_type) if m_type == *"rootfs");
+        if no_pivot_root {
+            args.push("--no-pivot".to_string());
+        }
+        if self.opts.no_new_keyring {
+            args.push("--no-new-keyring".to_string());
+        }
+        let (socket, pio) = if p.stdio.terminal {
+            let s = ConsoleSocket::new().await?;
+            args.push("--console-socket".to_string());
+            args.push(s.path.to_string_lossy().to_string());
+            (Some(s), None)
+        } else {
+            let pio = create_io(&p.id, self.opts.io_uid, self.opts.io_gid, &p.stdio)?;
+            (None, Some(pio))
+        };
+        args.push(p.id.to_string());
+
+        let mut cmd = self.runc_command(&args);
+        let io = pio.as_ref().and_then(|x| x.io.clone());
+        if let Some(io) = io.as_ref() {
+            io.set(&mut cmd)
+                .map_err(io_error!(e, "failed to set io of restore"))?;
+        }
+        let after_start = Box::new(move || {
+            if let Some(io) = io.as_ref() {
+                io.close_after_start();
+            }
+        });
+        if let Err(e) = self.execute_runc(cmd, after_start, "restore").await {
+            if let Some(s) = socket {
+                s.clean().await;
+            }
+            return Err(e);
+        }
+        copy_io_or_console(p, socket, pio, self.exit_signal.clone()).await?;
+        p.pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
+        p.state = Status::RUNNING;
+        Ok(())
+    }
+
+    pub async fn pause(&self, p: &mut InitProcess) -> Result<()> {
+        if let Err(e) = self.runtime.pause(&p.id).await {
+            return Err(runtime_error(&self.bundle, e, "OCI runtime pause failed").await);
+        }
+        p.state = Status::PAUSED;
+        Ok(())
+    }
+
+    pub async fn resume(&self, p: &mut InitProcess) -> Result<()> {
+        if let Err(e) = self.runtime.resume(&p.id).await {
+            return Err(runtime_error(&self.bundle, e, "OCI runtime resume failed").await);
+        }
+        p.state = Status::RUNNING;
+        Ok(())
+    }
+
+    // the work path from containerd is a host path too, so it is put beside the image
+    fn criu_work_path(&self, container_id: &str, work_path: &str) -> String {
+        if work_path.is_empty() {
+            self.opts.criu_path.to_string()
+        } else {
+            Path::new(KUASAR_STATE_DIR)
+                .join(CHECKPOINT_DIR)
+                .join(format!("{}-work", container_id))
+                .to_string_lossy()
+                .to_string()
+        }
+    }
+
+    // the runc client has no checkpoint or restore, so they are executed as the runc client does.
+    fn runc_command(&self, args: &[String]) -> Command {
+        let runtime = if self.opts.binary_name.is_empty() {
+            DEFAULT_COMMAND
+        } else {
+            self.opts.binary_name.as_str()
+        };
+        let mut cmd = Command::new(runtime);
+        cmd.arg("--root")
+            .arg(&self.runc_root)
+            .arg("--log")
+            .arg(Path::new(&self.bundle).join("log.json"))
+            .arg("--log-format")
+            .arg("json");
+        if self.opts.systemd_cgroup {
+            cmd.arg("--systemd-cgroup");
+        }
+        cmd.args(args);
+        cmd
+    }
+
+    async fn execute_runc(
+        &self,
+        cmd: Command,
+        after_start: Box<dyn Fn() + Send>,
+        action: &str,
+    ) -> Result<()> {
+        let (status, _, _, stderr) = ShimExecutor::default()
+            .execute(cmd, after_start, true)
+            .await
+            .map_err(other_error!(
+                e,
+                format!("failed to execute runc {}", action)
+            ))?;
+        if status.success() {
+            return Ok(());
+        }
+        let msg = get_last_runtime_error(&self.bundle)
+            .await
+            .unwrap_or_default();
+        Err(other!(
+            "OCI runtime {} failed: {}",
+            action,
+            if msg.is_empty() { stderr } else { msg }
+        ))
+    }
+}
+
+// write the spec checked by the policy to the bundle in the guest, the rootfs of a relative
+// path is still in the shared bundle.
+async fn create_guest_bundle(id: &str, shared_bundle: &str, mut spec: Spec) -> Result<String> {
+    let bundle = format!("{}/{}", GUEST_BUNDLE_DIR, id);
+    tokio::fs::create_dir_all(&bundle).await.map_err(io_error!(
+        e,
+        "failed to create bundle {}",
+        bundle
+    ))?;
+    let mut root = spec.root().clone();
+    if let Some(r) = root.as_mut() {
+        if r.path().is_relative() {
+            let path = Path::new(shared_bundle).join(r.path());
+            r.set_path(path);
+        }
+    }
+    spec.set_root(root);
+    let content = serde_json::to_vec(&spec).map_err(other_error!(e, "failed to encode spec"))?;
+    tokio::fs::write(Path::new(&bundle).join("config.json"), content)
+        .await
+        .map_err(io_error!(e, "failed to write spec to {}", bundle))?;
+    Ok(bundle)
+}
+
+// The checkpoint path from containerd is a path on the host, which is not visible in the guest,
+// so the image is put in the checkpoint dir on the shared dir, unless it is already in the shared dir.
+fn guest_checkpoint_path(path: &str, container_id: &str) -> Result<String> {
+    if Path::new(path)
+        .components()
+        .any(|c| c == Component::ParentDir)
+    {
+        return Err(Error::InvalidArgument(format!(
+            "checkpoint path {} should not contain \"..\"",
+            path
+        )));
+    }
+    if Path::new(path).starts_with(KUASAR_STATE_DIR) {
+        return Ok(path.to_string());
+    }
+    Ok(Path::new(KUASAR_STATE_DIR)
+        .join(CHECKPOINT_DIR)
+        .join(container_id)
+        .to_string_lossy()
+        .to_string())
 }
 
 #[async_trait]
@@ -554,6 +842,16 @@ fn get_spec_from_request(
 const DEFAULT_RUNC_ROOT: &str = "/run/containerd/runc";
 const DEFAULT_COMMAND: &str = "runc";
 
+fn runc_root(namespace: &str, opts: &Options) -> PathBuf {
+    let root = opts.root.as_str();
+    Path::new(if root.is_empty() {
+        DEFAULT_RUNC_ROOT
+    } else {
+        root
+    })
+    .join(namespace)
+}
+
 pub fn create_runc(
     runtime: &str,
     namespace: &str,
@@ -566,13 +864,7 @@ pub fn create_runc(
     } else {
         runtime
     };
-    let root = opts.root.as_str();
-    let root = Path::new(if root.is_empty() {
-        DEFAULT_RUNC_ROOT
-    } else {
-        root
-    })
-    .join(namespace);
+    let root = runc_root(namespace, opts);
 
     let log = bundle.as_ref().join("log.json");
     let mut gopts = GlobalOpts::default()
@@ -670,7 +962,21 @@ mod tests {
     use containerd_shim::util::{mkdir, write_str_to_file};
     use tokio::fs::remove_dir_all;
 
-    use crate::container::runtime_error;
+    use crate::container::{guest_checkpoint_path, runtime_error};
+
+    #[test]
+    fn test_guest_checkpoint_path() {
+        assert_eq!(
+            guest_checkpoint_path("/var/lib/containerd/checkpoint-1", "c1").unwrap(),
+            "/run/kuasar/state/checkpoints/c1"
+        );
+        assert_eq!(
+            guest_checkpoint_path("/run/kuasar/state/checkpoints/c0", "c1").unwrap(),
+            "/run/kuasar/state/checkpoints/c0"
+        );
+        assert!(guest_checkpoint_path("/run/kuasar/state/../../../etc", "c1").is_err());
+        assert!(guest_checkpoint_path("/var/lib/containerd/../checkpoint-1", "c1").is_err());
+    }
 
     #[tokio::test]
     async fn test_runtime_error_with_logfile() {
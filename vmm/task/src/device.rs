@@ -14,12 +14,15 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, fs::read_link, os::unix::prelude::FromRawFd, sync::Arc};
+use std::{
+    collections::HashMap, fs::read_link, os::unix::prelude::FromRawFd, path::Path, sync::Arc,
+};
 
-use containerd_shim::{other, Error, Result};
+use containerd_shim::{other, other_error, Error, Result};
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, warn};
 use netlink_sys::{protocols, SocketAddr, TokioSocket};
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
 use tokio::sync::{
     mpsc::{channel, Receiver, Sender},
     Mutex,
@@ -28,17 +31,29 @@ use tokio::sync::{
 pub const U_EVENT_ACTION: &str = "ACTION";
 pub const U_EVENT_ACTION_ADD: &str = "add";
 pub const U_EVENT_ACTION_REMOVE: &str = "remove";
+pub const U_EVENT_ACTION_CHANGE: &str = "change";
+pub const U_EVENT_ACTION_MOVE: &str = "move";
 pub const U_EVENT_DEV_PATH: &str = "DEVPATH";
+pub const U_EVENT_DEV_PATH_OLD: &str = "DEVPATH_OLD";
 pub const U_EVENT_SUB_SYSTEM: &str = "SUBSYSTEM";
 pub const U_EVENT_SEQ_NUM: &str = "SEQNUM";
 pub const U_EVENT_DEV_NAME: &str = "DEVNAME";
 pub const U_EVENT_INTERFACE: &str = "INTERFACE";
+pub const U_EVENT_MAJOR: &str = "MAJOR";
+pub const U_EVENT_MINOR: &str = "MINOR";
+pub const U_EVENT_PCI_SLOT_NAME: &str = "PCI_SLOT_NAME";
 
 lazy_static! {
     static ref DEVICE_CONVERTORS: Vec<DeviceConverter> = {
         let converters = vec![
             convert_to_scsi_device as DeviceConverter,
             convert_to_blk_device as DeviceConverter,
+            convert_to_mmio_blk_device as DeviceConverter,
+            convert_to_pmem_device as DeviceConverter,
+            convert_to_net_device as DeviceConverter,
+            convert_to_pci_device as DeviceConverter,
+            convert_to_vfio_device as DeviceConverter,
+            convert_to_serial_device as DeviceConverter,
         ];
         converters
     };
@@ -48,10 +63,14 @@ lazy_static! {
 pub struct Uevent {
     pub action: String,
     pub devpath: String,
+    pub devpath_old: String,
     pub devname: String,
     pub subsystem: String,
     seqnum: String,
     pub interface: String,
+    pub major: String,
+    pub minor: String,
+    pub pci_slot_name: String,
 }
 
 pub trait DeviceMatcher: Sync + Send + 'static {
@@ -68,11 +87,11 @@ pub struct DeviceSubscriber {
 }
 
 pub struct DeviceSubscription {
-    #[allow(dead_code)]
     pub(crate) id: u64,
     pub(crate) rx: Receiver<Device>,
 }
 
+#[derive(Clone)]
 pub struct DeviceMonitor {
     internal: Arc<Mutex<DeviceMonitorInternal>>,
 }
@@ -115,7 +134,6 @@ impl DeviceMonitor {
         DeviceSubscription { id, rx }
     }
 
-    #[allow(dead_code)]
     pub async fn unsubscribe(&self, id: u64) {
         let mut internal = self.internal.lock().await;
         let ss = &mut internal.subscribers;
@@ -127,6 +145,10 @@ impl DeviceMonitor {
         // init scsi device, some device may already exist before task start
         self.init_scsi_devices().await;
         self.init_blk_devices().await;
+        self.init_net_devices().await;
+        self.init_pci_devices().await;
+        self.init_vfio_devices().await;
+        self.init_serial_devices().await;
         tokio::spawn(async move {
             let mut socket = unsafe {
                 let fd = libc::socket(
@@ -200,6 +222,24 @@ impl DeviceMonitor {
             // should be a symlink of the name with "0:0:0:X"
             let dev_name = entry.file_name().to_str().unwrap().to_string();
             let metadata = entry.metadata().await.unwrap();
+            // nvdimm devices are named by the kernel as pmemX, the name is the address
+            // pmem0 -> ../../devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0012:00/ndbus0/region0/namespace0.0/block/pmem0
+            if dev_name.starts_with(PMEM_DEVICE_PREFIX) && metadata.is_symlink() {
+                let device = Device {
+                    path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                    addr: dev_name.to_string(),
+                    r#type: DeviceType::Nvdimm,
+                };
+                debug!("scan add device {:?} of devpath {}", device, dev_name);
+                let devpath = read_link(entry.path()).unwrap();
+                let devpath = devpath.to_str().unwrap().trim_start_matches("../..");
+                self.internal
+                    .lock()
+                    .await
+                    .add_device(devpath.to_string(), device)
+                    .await;
+                continue;
+            }
             // only handle virtio-block devices
             // vda -> ../../devices/pci0000:00/0000:00:02.0/virtio1/block/vda/
             // vda1 -> ../../devices/pci0000:00/0000:00:02.0/virtio1/block/vda/vda1/
@@ -210,6 +250,20 @@ impl DeviceMonitor {
                 let real_path = read_link(entry.path()).unwrap();
                 let real_path = real_path.to_str().unwrap();
                 let real_path_parts: Vec<&str> = real_path.split('/').collect();
+                // virtio-mmio block devices have no pci address in the path
+                // vda -> ../../devices/platform/LNRO0005:00/virtio0/block/vda/
+                if !real_path_parts.iter().any(|p| p.starts_with("0000:")) {
+                    let devpath = real_path.trim_start_matches("../..");
+                    if let Some(device) = read_mmio_blk_device(devpath, &dev_name) {
+                        debug!("scan add device {:?} of devpath {}", device, devpath);
+                        self.internal
+                            .lock()
+                            .await
+                            .add_device(devpath.to_string(), device)
+                            .await;
+                    }
+                    continue;
+                }
                 for part in real_path_parts {
                     if part.starts_with("0000:") {
                         let device = Device {
@@ -228,6 +282,103 @@ impl DeviceMonitor {
             }
         }
     }
+
+    // only the physical nics are tracked, which are hot plugged by the host
+    // eth0 -> ../../devices/pci0000:00/0000:00:05.0/virtio3/net/eth0
+    // lo -> ../../devices/virtual/net/lo
+    async fn init_net_devices(&self) {
+        for (name, devpath) in read_class_links(SYSFS_NET_PATH).await {
+            if devpath.starts_with(VIRTUAL_DEVICE_PREFIX) {
+                continue;
+            }
+            let device = Device {
+                path: format!("{}/{}", SYSFS_NET_PATH, name),
+                addr: name,
+                r#type: DeviceType::Net,
+            };
+            debug!("scan add device {:?} of devpath {}", device, devpath);
+            self.internal.lock().await.add_device(devpath, device).await;
+        }
+    }
+
+    // 0000:00:06.0 -> ../../../devices/pci0000:00/0000:00:06.0
+    async fn init_pci_devices(&self) {
+        for (bdf, devpath) in read_class_links(SYSFS_PCI_BUS_PREFIX).await {
+            let device = Device {
+                path: format!("{}{}", SYSFS_PATH, devpath),
+                addr: bdf,
+                r#type: DeviceType::Pci,
+            };
+            debug!("scan add device {:?} of devpath {}", device, devpath);
+            self.internal.lock().await.add_device(devpath, device).await;
+        }
+    }
+
+    // the nodes may not be created if /dev is not a devtmpfs,
+    // 12 -> ../../devices/virtual/vfio/12
+    async fn init_vfio_devices(&self) {
+        if let Some((major, minor)) = read_dev_number(SYSFS_VFIO_CONTAINER_PATH).await {
+            create_device_node(VFIO_CONTAINER_DEV_NAME, &major, &minor)
+                .unwrap_or_else(|e| warn!("failed to create vfio container device node: {}", e));
+        }
+        for (group, devpath) in read_class_links(SYSFS_VFIO_PATH).await {
+            let (major, minor) =
+                match read_dev_number(&format!("{}/{}", SYSFS_VFIO_PATH, group)).await {
+                    Some(n) => n,
+                    None => continue,
+                };
+            let event = Uevent {
+                action: U_EVENT_ACTION_ADD.to_string(),
+                devpath,
+                devname: format!("{}/{}", VFIO_DEV_DIR, group),
+                subsystem: VFIO_SUBSYSTEM.to_string(),
+                major,
+                minor,
+                ..Default::default()
+            };
+            self.internal.lock().await.handle_add_event(event).await;
+        }
+    }
+
+    // vport1p1 -> ../../devices/pci0000:00/0000:00:04.0/virtio2/virtio-ports/vport1p1
+    async fn init_serial_devices(&self) {
+        for (dev_name, devpath) in read_class_links(SYSFS_VIRTIO_PORTS_PATH).await {
+            if let Some(device) = read_serial_device(&devpath, &dev_name) {
+                debug!("scan add device {:?} of devpath {}", device, devpath);
+                self.internal.lock().await.add_device(devpath, device).await;
+            }
+        }
+    }
+}
+
+// read the entries of a sysfs class dir, and the devpaths they link to
+async fn read_class_links(dir: &str) -> Vec<(String, String)> {
+    let mut links = vec![];
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(e) => e,
+        Err(e) => {
+            debug!("failed to read {}: {}", dir, e);
+            return links;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Ok(target) = read_link(entry.path()) {
+            let target = target.to_string_lossy().to_string();
+            let devpath = format!("/{}", target.trim_start_matches("../"));
+            links.push((name, devpath));
+        }
+    }
+    links
+}
+
+// the dev file in sysfs is like "243:0"
+async fn read_dev_number(sysfs_dir: &str) -> Option<(String, String)> {
+    let dev = tokio::fs::read_to_string(format!("{}/dev", sysfs_dir))
+        .await
+        .ok()?;
+    let (major, minor) = dev.trim().split_once(':')?;
+    Some((major.to_string(), minor.to_string()))
 }
 
 impl DeviceMonitorInternal {
@@ -241,11 +392,28 @@ impl DeviceMonitorInternal {
                 debug!("received remove uevent {:?}", event);
                 self.handle_remove_event(event).await
             }
+            // the name of a virtio serial port is set after the port is added
+            U_EVENT_ACTION_CHANGE if event.subsystem == VIRTIO_PORTS_SUBSYSTEM => {
+                debug!("received change uevent {:?}", event);
+                self.handle_add_event(event).await
+            }
+            // net interfaces are renamed by moving
+            U_EVENT_ACTION_MOVE => {
+                debug!("received move uevent {:?}", event);
+                self.devices.remove(&event.devpath_old);
+                self.handle_add_event(event).await
+            }
             _ => {}
         }
     }
 
     async fn handle_add_event(&mut self, event: Uevent) {
+        // the vfio container node has no device to be tracked, but it is needed by all the groups
+        if Path::new(&event.devname).starts_with(VFIO_DEV_DIR) && !event.major.is_empty() {
+            if let Err(e) = create_device_node(&event.devname, &event.major, &event.minor) {
+                warn!("failed to create device node of {}: {}", event.devname, e);
+            }
+        }
         for converter in DEVICE_CONVERTORS.iter() {
             if let Some(device) = converter(&event) {
                 debug!("add device {:?} of devpath {}", device, event.devpath);
@@ -258,11 +426,13 @@ impl DeviceMonitorInternal {
     async fn add_device(&mut self, device_key: String, device: Device) {
         self.devices.insert(device_key, device.clone());
         let ss = &mut self.subscribers;
-        for s in ss.values() {
-            if s.matcher.is_match(&device) {
-                let _ = s.tx.send(device).await;
-                break;
-            }
+        // a full channel means the subscriber has got the device it waits for,
+        // do not block the uevents on it.
+        for s in ss
+            .values()
+            .filter(|s| !s.tx.is_closed() && s.matcher.is_match(&device))
+        {
+            let _ = s.tx.try_send(device.clone());
         }
     }
 
@@ -286,8 +456,12 @@ impl Uevent {
                     U_EVENT_DEV_NAME => event.devname = String::from(key_val[1]),
                     U_EVENT_SUB_SYSTEM => event.subsystem = String::from(key_val[1]),
                     U_EVENT_DEV_PATH => event.devpath = String::from(key_val[1]),
+                    U_EVENT_DEV_PATH_OLD => event.devpath_old = String::from(key_val[1]),
                     U_EVENT_SEQ_NUM => event.seqnum = String::from(key_val[1]),
                     U_EVENT_INTERFACE => event.interface = String::from(key_val[1]),
+                    U_EVENT_MAJOR => event.major = String::from(key_val[1]),
+                    U_EVENT_MINOR => event.minor = String::from(key_val[1]),
+                    U_EVENT_PCI_SLOT_NAME => event.pci_slot_name = String::from(key_val[1]),
                     _ => (),
                 }
             }
@@ -299,21 +473,37 @@ impl Uevent {
 #[allow(dead_code)]
 pub const SCSI_HOST_CHANNEL: &str = "0:0:";
 pub const SCSI_BLOCK_SUFFIX: &str = "block";
+pub const SYSFS_PATH: &str = "/sys";
 pub const SYSFS_SCSI_HOST_PATH: &str = "/sys/class/scsi_host";
 pub const SYSFS_SCSI_DEVICE_PATH: &str = "/sys/class/scsi_device";
 pub const SYSFS_BLK_DEVICE_PATH: &str = "/sys/class/block";
 pub const SYSFS_PCI_BUS_RESCAN_FILE: &str = "/sys/bus/pci/rescan";
-#[allow(dead_code)]
 pub const SYSFS_PCI_BUS_PREFIX: &str = "/sys/bus/pci/devices";
+pub const SYSFS_NET_PATH: &str = "/sys/class/net";
+pub const SYSFS_VFIO_PATH: &str = "/sys/class/vfio";
+pub const SYSFS_VFIO_CONTAINER_PATH: &str = "/sys/class/misc/vfio";
+pub const SYSFS_VIRTIO_PORTS_PATH: &str = "/sys/class/virtio-ports";
 pub const SYSTEM_DEV_PATH: &str = "/dev";
+pub const PMEM_DEVICE_PREFIX: &str = "pmem";
+pub const VIRTUAL_DEVICE_PREFIX: &str = "/devices/virtual/";
+pub const VFIO_DEV_DIR: &str = "vfio";
+pub const VFIO_CONTAINER_DEV_NAME: &str = "vfio/vfio";
+pub const VFIO_SUBSYSTEM: &str = "vfio";
+pub const VIRTIO_PORTS_SUBSYSTEM: &str = "virtio-ports";
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum DeviceType {
     #[allow(dead_code)]
     Blk,
+    MmioBlk,
+    Nvdimm,
     Scsi,
     #[allow(dead_code)]
     Ephemeral,
+    Net,
+    Pci,
+    Vfio,
+    Serial,
 }
 
 #[derive(Clone, Debug)]
@@ -377,6 +567,168 @@ pub fn convert_to_blk_device(event: &Uevent) -> Option<Device> {
     }
 }
 
+pub fn convert_to_mmio_blk_device(event: &Uevent) -> Option<Device> {
+    let path_parts: Vec<_> = event.devpath.split('/').collect();
+    let length = path_parts.len();
+    if path_parts.len() > 3
+        && event.subsystem == "block"
+        && !path_parts.iter().any(|p| p.starts_with("0000:"))
+        && path_parts[length - 3].starts_with("virtio")
+        && path_parts[length - 2] == "block"
+        && !event.devname.is_empty()
+    {
+        read_mmio_blk_device(&event.devpath, &event.devname)
+    } else {
+        None
+    }
+}
+
+pub fn convert_to_pmem_device(event: &Uevent) -> Option<Device> {
+    let path_parts: Vec<_> = event.devpath.split('/').collect();
+    let length = path_parts.len();
+    if path_parts.len() > 3
+        && event.subsystem == "block"
+        && path_parts[length - 3].starts_with("namespace")
+        && path_parts[length - 2] == "block"
+        && event.devname.starts_with(PMEM_DEVICE_PREFIX)
+    {
+        Some(Device {
+            path: format!("{}/{}", SYSTEM_DEV_PATH, &event.devname),
+            addr: event.devname.to_string(),
+            r#type: DeviceType::Nvdimm,
+        })
+    } else {
+        None
+    }
+}
+
+// virtio-mmio block device has no address for us to locate it,
+// so the host sets the serial of the device to the device id.
+fn read_mmio_blk_device(devpath: &str, dev_name: &str) -> Option<Device> {
+    let serial_path = format!("{}{}/serial", SYSFS_PATH, devpath.trim_end_matches('/'));
+    let serial = std::fs::read_to_string(&serial_path).unwrap_or_default();
+    let serial = serial.trim();
+    if serial.is_empty() {
+        debug!("no serial found in {} for mmio block device", serial_path);
+        return None;
+    }
+    Some(Device {
+        path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+        addr: serial.to_string(),
+        r#type: DeviceType::MmioBlk,
+    })
+}
+
+// the addr of a nic is its name, which is changed by a move event when renamed,
+// /devices/pci0000:00/0000:00:05.0/virtio3/net/eth0
+pub fn convert_to_net_device(event: &Uevent) -> Option<Device> {
+    if event.subsystem == "net"
+        && !event.interface.is_empty()
+        && !event.devpath.starts_with(VIRTUAL_DEVICE_PREFIX)
+    {
+        Some(Device {
+            path: format!("{}/{}", SYSFS_NET_PATH, &event.interface),
+            addr: event.interface.to_string(),
+            r#type: DeviceType::Net,
+        })
+    } else {
+        None
+    }
+}
+
+// the addr of a pci device is its address in the guest, like 0000:00:06.0
+pub fn convert_to_pci_device(event: &Uevent) -> Option<Device> {
+    if event.subsystem == "pci" && !event.pci_slot_name.is_empty() {
+        Some(Device {
+            path: format!("{}{}", SYSFS_PATH, &event.devpath),
+            addr: event.pci_slot_name.to_string(),
+            r#type: DeviceType::Pci,
+        })
+    } else {
+        None
+    }
+}
+
+// a vfio group is added when a device is bound to vfio-pci, the addr is the group number,
+// /devices/virtual/vfio/12 with DEVNAME=vfio/12
+pub fn convert_to_vfio_device(event: &Uevent) -> Option<Device> {
+    let group = event
+        .devname
+        .strip_prefix(VFIO_DEV_DIR)?
+        .strip_prefix('/')?;
+    if event.subsystem == VFIO_SUBSYSTEM
+        && !group.is_empty()
+        && group.chars().all(|c| c.is_ascii_digit())
+    {
+        Some(Device {
+            path: format!("{}/{}", SYSTEM_DEV_PATH, &event.devname),
+            addr: group.to_string(),
+            r#type: DeviceType::Vfio,
+        })
+    } else {
+        None
+    }
+}
+
+// /devices/pci0000:00/0000:00:04.0/virtio2/virtio-ports/vport1p1
+pub fn convert_to_serial_device(event: &Uevent) -> Option<Device> {
+    if event.subsystem == VIRTIO_PORTS_SUBSYSTEM && !event.devname.is_empty() {
+        read_serial_device(&event.devpath, &event.devname)
+    } else {
+        None
+    }
+}
+
+// the addr of a virtio serial port is the name given by the host, which is not in the uevent
+fn read_serial_device(devpath: &str, dev_name: &str) -> Option<Device> {
+    let name_path = format!("{}{}/name", SYSFS_PATH, devpath.trim_end_matches('/'));
+    let name = std::fs::read_to_string(&name_path).unwrap_or_default();
+    let name = name.trim();
+    if name.is_empty() {
+        debug!("no name found in {} for virtio serial port", name_path);
+        return None;
+    }
+    Some(Device {
+        path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+        addr: name.to_string(),
+        r#type: DeviceType::Serial,
+    })
+}
+
+fn create_device_node(dev_name: &str, major: &str, minor: &str) -> Result<()> {
+    let path = Path::new(SYSTEM_DEV_PATH).join(dev_name);
+    if path.exists() {
+        return Ok(());
+    }
+    let (major, minor) = match (major.parse::<u64>(), minor.parse::<u64>()) {
+        (Ok(major), Ok(minor)) => (major, minor),
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "invalid device number {}:{} of {}",
+                major, minor, dev_name
+            )))
+        }
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(other_error!(
+            e,
+            format!("failed to create {}", parent.display())
+        ))?;
+    }
+    mknod(
+        &path,
+        SFlag::S_IFCHR,
+        Mode::from_bits_truncate(0o666),
+        makedev(major, minor),
+    )
+    .map_err(other_error!(
+        e,
+        format!("failed to mknod {}", path.display())
+    ))?;
+    debug!("device node {} created", path.display());
+    Ok(())
+}
+
 pub async fn scan_scsi_bus(scsi_addr: &str) -> containerd_shim::Result<()> {
     let tokens: Vec<&str> = scsi_addr.split(':').collect();
     if tokens.len() != 2 {
@@ -404,3 +756,213 @@ pub async fn scan_scsi_bus(scsi_addr: &str) -> containerd_shim::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        convert_to_net_device, convert_to_pci_device, convert_to_serial_device,
+        convert_to_vfio_device, DeviceMonitor, DeviceType, Uevent,
+    };
+    use crate::sandbox::TypeAddrDeviceMatcher;
+
+    fn uevent(action: &str, devpath: &str, fields: &[(&str, &str)]) -> Uevent {
+        let mut message = format!(
+            "{}@{}\0ACTION={}\0DEVPATH={}",
+            action, devpath, action, devpath
+        );
+        for (k, v) in fields {
+            message.push_str(&format!("\0{}={}", k, v));
+        }
+        Uevent::new(&message)
+    }
+
+    #[test]
+    fn test_uevent_new() {
+        let event = uevent(
+            "move",
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth1",
+            &[
+                ("SUBSYSTEM", "net"),
+                (
+                    "DEVPATH_OLD",
+                    "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth0",
+                ),
+                ("INTERFACE", "eth1"),
+                ("IFINDEX", "3"),
+                ("SEQNUM", "1024"),
+            ],
+        );
+        assert_eq!(event.action, "move");
+        assert_eq!(
+            event.devpath,
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth1"
+        );
+        assert_eq!(
+            event.devpath_old,
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth0"
+        );
+        assert_eq!(event.subsystem, "net");
+        assert_eq!(event.interface, "eth1");
+        assert_eq!(event.seqnum, "1024");
+
+        let event = uevent(
+            "add",
+            "/devices/virtual/vfio/12",
+            &[
+                ("SUBSYSTEM", "vfio"),
+                ("MAJOR", "243"),
+                ("MINOR", "0"),
+                ("DEVNAME", "vfio/12"),
+            ],
+        );
+        assert_eq!(event.devname, "vfio/12");
+        assert_eq!((event.major.as_str(), event.minor.as_str()), ("243", "0"));
+    }
+
+    #[test]
+    fn test_convert_to_net_device() {
+        let device = convert_to_net_device(&uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth0",
+            &[("SUBSYSTEM", "net"), ("INTERFACE", "eth0")],
+        ))
+        .unwrap();
+        assert_eq!(device.addr, "eth0");
+        assert_eq!(device.path, "/sys/class/net/eth0");
+        assert_eq!(device.r#type, DeviceType::Net);
+
+        assert!(convert_to_net_device(&uevent(
+            "add",
+            "/devices/virtual/net/veth1",
+            &[("SUBSYSTEM", "net"), ("INTERFACE", "veth1")],
+        ))
+        .is_none());
+        assert!(convert_to_net_device(&uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:05.0/virtio3",
+            &[("SUBSYSTEM", "virtio")],
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn test_convert_to_pci_device() {
+        let device = convert_to_pci_device(&uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:06.0",
+            &[
+                ("SUBSYSTEM", "pci"),
+                ("PCI_ID", "8086:10ED"),
+                ("PCI_SLOT_NAME", "0000:00:06.0"),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(device.addr, "0000:00:06.0");
+        assert_eq!(device.path, "/sys/devices/pci0000:00/0000:00:06.0");
+        assert_eq!(device.r#type, DeviceType::Pci);
+
+        assert!(convert_to_pci_device(&uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:06.0",
+            &[("SUBSYSTEM", "pci")],
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn test_convert_to_vfio_device() {
+        let device = convert_to_vfio_device(&uevent(
+            "add",
+            "/devices/virtual/vfio/12",
+            &[("SUBSYSTEM", "vfio"), ("DEVNAME", "vfio/12")],
+        ))
+        .unwrap();
+        assert_eq!(device.addr, "12");
+        assert_eq!(device.path, "/dev/vfio/12");
+        assert_eq!(device.r#type, DeviceType::Vfio);
+
+        assert!(convert_to_vfio_device(&uevent(
+            "add",
+            "/devices/virtual/misc/vfio",
+            &[("SUBSYSTEM", "misc"), ("DEVNAME", "vfio/vfio")],
+        ))
+        .is_none());
+        assert!(convert_to_vfio_device(&uevent(
+            "add",
+            "/devices/virtual/vfio/vfiox",
+            &[("SUBSYSTEM", "vfio"), ("DEVNAME", "vfiox/1")],
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn test_convert_to_serial_device() {
+        // the port has no name in sysfs
+        assert!(convert_to_serial_device(&uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:04.0/virtio2/virtio-ports/vport9p9",
+            &[("SUBSYSTEM", "virtio-ports"), ("DEVNAME", "vport9p9")],
+        ))
+        .is_none());
+        assert!(convert_to_serial_device(&uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:02.0/virtio1/block/vda",
+            &[("SUBSYSTEM", "block"), ("DEVNAME", "vda")],
+        ))
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_net_events() {
+        let monitor = DeviceMonitor::new();
+        let add = uevent(
+            "add",
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth0",
+            &[("SUBSYSTEM", "net"), ("INTERFACE", "eth0")],
+        );
+        monitor.internal.lock().await.handle_event(add).await;
+        let mut s = monitor
+            .subscribe(TypeAddrDeviceMatcher::new(
+                DeviceType::Net,
+                "eth0".to_string(),
+            ))
+            .await;
+        assert_eq!(s.rx.recv().await.unwrap().addr, "eth0");
+
+        let mut s = monitor
+            .subscribe(TypeAddrDeviceMatcher::new(
+                DeviceType::Net,
+                "ens5".to_string(),
+            ))
+            .await;
+        let rename = uevent(
+            "move",
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/ens5",
+            &[
+                ("SUBSYSTEM", "net"),
+                ("INTERFACE", "ens5"),
+                (
+                    "DEVPATH_OLD",
+                    "/devices/pci0000:00/0000:00:05.0/virtio3/net/eth0",
+                ),
+            ],
+        );
+        monitor.internal.lock().await.handle_event(rename).await;
+        assert_eq!(s.rx.recv().await.unwrap().path, "/sys/class/net/ens5");
+        {
+            let internal = monitor.internal.lock().await;
+            assert_eq!(internal.devices.len(), 1);
+            assert!(internal
+                .devices
+                .contains_key("/devices/pci0000:00/0000:00:05.0/virtio3/net/ens5"));
+        }
+
+        let remove = uevent(
+            "remove",
+            "/devices/pci0000:00/0000:00:05.0/virtio3/net/ens5",
+            &[("SUBSYSTEM", "net"), ("INTERFACE", "ens5")],
+        );
+        monitor.internal.lock().await.handle_event(remove).await;
+        assert!(monitor.internal.lock().await.devices.is_empty());
+    }
+}
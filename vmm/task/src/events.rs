@@ -0,0 +1,139 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use containerd_shim::{
+    other_error,
+    protos::{
+        protobuf::{Message, MessageDyn, MessageField},
+        shim::events::Envelope,
+    },
+    util::{convert_to_any, timestamp},
+    Error, Result,
+};
+use futures::StreamExt;
+use log::warn;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc::{channel, error::TrySendError, Receiver},
+};
+use tokio_vsock::{VsockListener, VsockStream};
+use vmm_common::TASK_EVENT_PORT;
+
+use crate::vsock::bind_vsock;
+
+// the new events are dropped when the buffer is full, as the host is not reading them
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+/// Serve the task events on the vsock port as length delimited envelopes,
+/// a new connection from the host replaces the old one, as the shim may be restarted,
+/// events are kept in the buffer while there is no connection.
+pub async fn forward_events(
+    mut rx: Receiver<(String, Box<dyn MessageDyn>)>,
+    namespace: String,
+) -> Result<()> {
+    let l = bind_vsock(&format!("vsock://-1:{}", TASK_EVENT_PORT)).await?;
+    let (buf_tx, buf_rx) = channel(EVENT_BUFFER_SIZE);
+    // always receive the events so that the task service is never blocked on sending,
+    // the writing to the host is in another task.
+    tokio::spawn(async move {
+        while let Some((topic, event)) = rx.recv().await {
+            match to_envelope(&namespace, &topic, event) {
+                Ok(buf) => {
+                    if let Err(TrySendError::Full(_)) = buf_tx.try_send(buf) {
+                        warn!("event buffer is full, drop event {}", topic);
+                    }
+                }
+                Err(e) => warn!("failed to encode event {}: {}", topic, e),
+            }
+        }
+    });
+    tokio::spawn(write_events(l, buf_rx));
+    Ok(())
+}
+
+async fn write_events(l: VsockListener, mut rx: Receiver<Vec<u8>>) {
+    let mut incoming = l.incoming();
+    let mut stream: Option<VsockStream> = None;
+    let mut pending: Option<Vec<u8>> = None;
+    loop {
+        if let (Some(s), Some(buf)) = (stream.as_mut(), pending.as_ref()) {
+            match s.write_all(buf).await {
+                Ok(_) => pending = None,
+                Err(e) => {
+                    warn!("failed to forward event to host: {}", e);
+                    stream = None;
+                }
+            }
+            continue;
+        }
+        tokio::select! {
+            s = incoming.next() => match s {
+                Some(Ok(s)) => stream = Some(s),
+                Some(Err(_)) => continue,
+                None => return,
+            },
+            // the event is kept until it is written to a connection
+            buf = rx.recv(), if pending.is_none() => match buf {
+                Some(buf) => pending = Some(buf),
+                None => return,
+            },
+        }
+    }
+}
+
+fn to_envelope(namespace: &str, topic: &str, event: Box<dyn MessageDyn>) -> Result<Vec<u8>> {
+    let mut envelope = Envelope::new();
+    envelope.topic = topic.to_string();
+    envelope.namespace = namespace.to_string();
+    envelope.timestamp = MessageField::some(timestamp()?);
+    envelope.event = MessageField::some(convert_to_any(event)?);
+    envelope
+        .write_length_delimited_to_bytes()
+        .map_err(other_error!(e, "failed to encode envelope"))
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim::protos::{
+        events::task::TaskOOM,
+        protobuf::{CodedInputStream, Message},
+        shim::events::Envelope,
+    };
+
+    use super::to_envelope;
+
+    #[test]
+    fn test_to_envelope() {
+        let mut oom = TaskOOM::new();
+        oom.container_id = "c1".to_string();
+        let buf = to_envelope("k8s.io", "/tasks/oom", Box::new(oom.clone())).unwrap();
+
+        let mut input = CodedInputStream::from_bytes(&buf);
+        let envelope: Envelope = input.read_message().unwrap();
+        assert!(input.eof().unwrap());
+        assert_eq!(envelope.topic, "/tasks/oom");
+        assert_eq!(envelope.namespace, "k8s.io");
+        assert!(envelope.timestamp.is_some());
+        assert!(envelope
+            .event
+            .type_url
+            .ends_with("containerd.events.TaskOOM"));
+        assert_eq!(
+            TaskOOM::parse_from_bytes(&envelope.event.value).unwrap(),
+            oom
+        );
+    }
+}
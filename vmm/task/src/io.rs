@@ -18,7 +18,6 @@ use std::{
     io::{ErrorKind, IoSliceMut},
     ops::Deref,
     os::unix::prelude::{AsRawFd, FromRawFd, RawFd},
-    path::PathBuf,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -43,10 +42,14 @@ use runc::io::{IOOption, Io, NullIo, PipedIo, FIFO};
 use tokio::{
     fs::{File, OpenOptions},
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::Mutex,
 };
 use tokio_vsock::{VsockListener, VsockStream};
 
-use crate::{device::SYSTEM_DEV_PATH, vsock};
+use crate::{
+    sandbox::{get_serial_device, SandboxResources},
+    vsock,
+};
 
 pub struct ProcessIO {
     pub uri: Option<String>,
@@ -402,47 +405,31 @@ pub fn receive_socket(stream_fd: RawFd) -> containerd_shim::Result<RawFd> {
 //    /proc/self/fd/2, it may has no permission to open it because of the devices cgroup constraint
 // 2. even we give container the permission to open this file, the serial device can not
 //    be opened multiple times, it will return the error of "Device or resource busy".
-pub async fn convert_stdio(stdio: &Stdio) -> containerd_shim::Result<Stdio> {
+pub async fn convert_stdio(
+    stdio: &Stdio,
+    sandbox: &Mutex<SandboxResources>,
+) -> containerd_shim::Result<Stdio> {
     Ok(Stdio {
-        stdin: get_io_file_name(&stdio.stdin).await?,
-        stdout: get_io_file_name(&stdio.stdout).await?,
-        stderr: get_io_file_name(&stdio.stderr).await?,
+        stdin: get_io_file_name(&stdio.stdin, sandbox).await?,
+        stdout: get_io_file_name(&stdio.stdout, sandbox).await?,
+        stderr: get_io_file_name(&stdio.stderr, sandbox).await?,
         terminal: stdio.terminal,
     })
 }
 
-async fn get_io_file_name(name: &str) -> containerd_shim::Result<String> {
+// the serial ports are tracked by the device monitor, as they may not be ready yet
+async fn get_io_file_name(
+    name: &str,
+    sandbox: &Mutex<SandboxResources>,
+) -> containerd_shim::Result<String> {
     if !name.is_empty() && !name.contains(VSOCK) {
-        find_serial_dev(name).await
+        let device_monitor = sandbox.lock().await.device_monitor();
+        get_serial_device(&device_monitor, name).await
     } else {
         Ok(name.to_string())
     }
 }
 
-async fn find_serial_dev(serial_name: &str) -> Result<String> {
-    let mut virtio_ports = tokio::fs::read_dir("/sys/class/virtio-ports")
-        .await
-        .unwrap();
-    while let Some(virtio_port) = virtio_ports.next_entry().await.unwrap() {
-        let path = virtio_port.path();
-        debug!("get path: {}", path.to_string_lossy());
-        let abs = PathBuf::new().join(path.as_path()).join("name");
-        if abs.exists() {
-            let name = tokio::fs::read_to_string(abs.as_path()).await.unwrap();
-            debug!("read name:{} from abspath {}", name, abs.to_string_lossy());
-            if name.trim() == serial_name {
-                let dev_path = format!(
-                    "{}/{}",
-                    SYSTEM_DEV_PATH,
-                    path.file_name().unwrap().to_string_lossy()
-                );
-                return Ok(dev_path);
-            }
-        }
-    }
-    Err(other!("failed to get serial dev of name {}", serial_name))
-}
-
 pin_project_lite::pin_project! {
     pub struct VsockIo {
         #[pin]
@@ -0,0 +1,204 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{collections::HashMap, os::unix::io::AsRawFd, path::PathBuf, thread};
+
+use containerd_shim::{io_error, other, other_error, Error, Result};
+use log::debug;
+use nix::sched::{setns, CloneFlags};
+use tokio::{process::Command, sync::oneshot};
+use vmm_common::{
+    api::sandbox::KernelModule, is_valid_module_parameter, IPC_NAMESPACE, SANDBOX_NS_PATH,
+    UTS_NAMESPACE,
+};
+
+const PROC_SYS: &str = "/proc/sys";
+
+pub async fn load_modules(modules: &[KernelModule]) -> Result<()> {
+    for m in modules {
+        if let Some(p) = m.parameters.iter().find(|p| !is_valid_module_parameter(p)) {
+            return Err(Error::InvalidArgument(format!(
+                "invalid parameter \"{}\" of kernel module {}",
+                p, m.name
+            )));
+        }
+        let output = Command::new("modprobe")
+            .arg("--")
+            .arg(&m.name)
+            .args(&m.parameters)
+            .output()
+            .await
+            .map_err(io_error!(e, "failed to run modprobe {}", m.name))?;
+        if !output.status.success() {
+            return Err(other!(
+                "failed to load kernel module {}: {}",
+                m.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        debug!("kernel module {} loaded", m.name);
+    }
+    Ok(())
+}
+
+/// Set the sysctls, the ipc and uts ones are set in the namespaces shared by the containers,
+/// the others are set in the guest, as the containers share the network namespace of it.
+pub async fn set_sysctls(sysctls: &HashMap<String, String>) -> Result<()> {
+    let mut groups: HashMap<Option<&str>, Vec<(PathBuf, String)>> = HashMap::new();
+    for (k, v) in sysctls {
+        groups
+            .entry(sysctl_namespace(k))
+            .or_default()
+            .push((sysctl_path(k)?, v.to_string()));
+    }
+    for (ns, values) in groups {
+        match ns {
+            Some(ns) => set_sysctls_in_namespace(ns, values).await?,
+            None => {
+                for (path, value) in values {
+                    tokio::fs::write(&path, &value).await.map_err(io_error!(
+                        e,
+                        "failed to write {}",
+                        path.display()
+                    ))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// setns only changes the namespace of the calling thread, so a new thread is
+// spawned to join the namespace, which should not be reused by anything else.
+async fn set_sysctls_in_namespace(ns: &str, values: Vec<(PathBuf, String)>) -> Result<()> {
+    let ns_path = format!("{}/{}", SANDBOX_NS_PATH, ns);
+    let flag = if ns == IPC_NAMESPACE {
+        CloneFlags::CLONE_NEWIPC
+    } else {
+        CloneFlags::CLONE_NEWUTS
+    };
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        tx.send(write_in_namespace(&ns_path, flag, values))
+            .unwrap_or_default();
+    });
+    rx.await
+        .map_err(|_| other!("failed to set sysctls in {} namespace", ns))?
+}
+
+fn write_in_namespace(
+    ns_path: &str,
+    flag: CloneFlags,
+    values: Vec<(PathBuf, String)>,
+) -> Result<()> {
+    let f = std::fs::File::open(ns_path).map_err(io_error!(e, "failed to open {}", ns_path))?;
+    setns(f.as_raw_fd(), flag).map_err(other_error!(e, "failed to join {}", ns_path))?;
+    for (path, value) in values {
+        std::fs::write(&path, &value).map_err(io_error!(
+            e,
+            "failed to write {}",
+            path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+// see https://kubernetes.io/docs/tasks/administer-cluster/sysctl-cluster/#listing-all-sysctl-parameters
+fn sysctl_namespace(key: &str) -> Option<&'static str> {
+    let key = key.replace('/', ".");
+    if key.starts_with("kernel.shm")
+        || key.starts_with("kernel.msg")
+        || key == "kernel.sem"
+        || key.starts_with("fs.mqueue.")
+    {
+        Some(IPC_NAMESPACE)
+    } else if key == "kernel.hostname" || key == "kernel.domainname" {
+        Some(UTS_NAMESPACE)
+    } else {
+        None
+    }
+}
+
+// the key is separated by either "." or "/", like "net.ipv4.ip_forward" or "net/ipv4/ip_forward"
+fn sysctl_path(key: &str) -> Result<PathBuf> {
+    let key = if key.contains('/') {
+        key.to_string()
+    } else {
+        key.replace('.', "/")
+    };
+    if key
+        .split('/')
+        .any(|p| p.is_empty() || p == "." || p == "..")
+    {
+        return Err(Error::InvalidArgument(format!("invalid sysctl {}", key)));
+    }
+    Ok(PathBuf::from(PROC_SYS).join(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use vmm_common::{is_valid_module_parameter, IPC_NAMESPACE, UTS_NAMESPACE};
+
+    use super::{sysctl_namespace, sysctl_path};
+
+    #[test]
+    fn test_sysctl_namespace() {
+        assert_eq!(sysctl_namespace("kernel.shmmax"), Some(IPC_NAMESPACE));
+        assert_eq!(sysctl_namespace("kernel.msgmnb"), Some(IPC_NAMESPACE));
+        assert_eq!(sysctl_namespace("kernel.sem"), Some(IPC_NAMESPACE));
+        assert_eq!(sysctl_namespace("fs/mqueue/msg_max"), Some(IPC_NAMESPACE));
+        assert_eq!(sysctl_namespace("kernel.domainname"), Some(UTS_NAMESPACE));
+        assert_eq!(sysctl_namespace("net.core.somaxconn"), None);
+        assert_eq!(sysctl_namespace("kernel.pid_max"), None);
+    }
+
+    #[test]
+    fn test_module_parameter() {
+        for valid in ["hashsize=4096", "expect_hashsize=512", "path=/x", "a=b-c"] {
+            assert!(is_valid_module_parameter(valid), "{}", valid);
+        }
+        for invalid in [
+            "",
+            "-C",
+            "--first-time",
+            "hashsize",
+            "=1",
+            "a=",
+            "a=-1",
+            "a-b=1",
+            "a=b c",
+        ] {
+            assert!(!is_valid_module_parameter(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_sysctl_path() {
+        assert_eq!(
+            sysctl_path("net.ipv4.ip_unprivileged_port_start").unwrap(),
+            PathBuf::from("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        );
+        assert_eq!(
+            sysctl_path("net/ipv4/conf/eth0.100/forwarding").unwrap(),
+            PathBuf::from("/proc/sys/net/ipv4/conf/eth0.100/forwarding")
+        );
+        assert!(sysctl_path("net..core").is_err());
+        assert!(sysctl_path("../../etc/passwd").is_err());
+        assert!(sysctl_path("/net/core/somaxconn").is_err());
+    }
+}
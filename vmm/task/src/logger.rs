@@ -0,0 +1,165 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use containerd_shim::{other, Error, Result};
+use futures::StreamExt;
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+use tokio_vsock::VsockStream;
+use vmm_common::TASK_LOG_PORT;
+
+use crate::vsock::bind_vsock;
+
+// records are dropped when the buffer is full, as the host is not reading them
+const LOG_BUFFER_SIZE: usize = 1024;
+
+#[derive(Serialize)]
+struct LogRecord<'a> {
+    // microseconds since the unix epoch
+    time: u64,
+    level: &'a str,
+    target: &'a str,
+    msg: String,
+}
+
+/// Logger that writes the records to the console as env_logger does,
+/// and forwards them as json lines to the host.
+pub struct TaskLogger {
+    console: env_logger::Logger,
+    tx: Sender<String>,
+}
+
+impl Log for TaskLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.console.matches(record) {
+            return;
+        }
+        self.console.log(record);
+        if let Some(line) = to_json(record) {
+            self.tx.try_send(line).unwrap_or_default();
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush()
+    }
+}
+
+fn to_json(record: &Record) -> Option<String> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default();
+    let r = LogRecord {
+        time,
+        level: record.level().as_str(),
+        target: record.target(),
+        msg: record.args().to_string(),
+    };
+    serde_json::to_string(&r).ok()
+}
+
+/// Install the task logger, the modules of vmm-task are not filtered by env_logger
+/// so that the level can be changed at runtime by set_level.
+pub fn init(level: LevelFilter) -> Result<Receiver<String>> {
+    let console = env_logger::Builder::from_default_env()
+        .format_timestamp_micros()
+        .filter_module("containerd_shim", LevelFilter::Trace)
+        .filter_module("vmm_task", LevelFilter::Trace)
+        .build();
+    let (tx, rx) = channel(LOG_BUFFER_SIZE);
+    log::set_boxed_logger(Box::new(TaskLogger { console, tx }))
+        .map_err(|e| other!("failed to set logger: {}", e))?;
+    log::set_max_level(level);
+    Ok(rx)
+}
+
+pub fn set_level(level: &str) -> Result<()> {
+    let level = LevelFilter::from_str(level)
+        .map_err(|_| Error::InvalidArgument(format!("invalid log level {}", level)))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Serve the log records on the vsock port, a new connection from the host replaces
+/// the old one, as the sandboxer may be restarted, records are kept in the buffer
+/// while there is no connection.
+pub async fn forward_logs(mut rx: Receiver<String>) -> Result<()> {
+    let l = bind_vsock(&format!("vsock://-1:{}", TASK_LOG_PORT)).await?;
+    tokio::spawn(async move {
+        let mut incoming = l.incoming();
+        let mut stream: Option<VsockStream> = None;
+        loop {
+            tokio::select! {
+                s = incoming.next() => match s {
+                    Some(Ok(s)) => stream = Some(s),
+                    Some(Err(_)) => continue,
+                    None => return,
+                },
+                line = rx.recv(), if stream.is_some() => {
+                    let line = match line {
+                        Some(l) => l,
+                        None => return,
+                    };
+                    if let Some(s) = stream.as_mut() {
+                        // do not log the failure, or it will be forwarded again
+                        if s.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                            stream = None;
+                        }
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use super::to_json;
+
+    #[test]
+    fn test_to_json() {
+        let line = to_json(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("vmm_task::sandbox")
+                .args(format_args!("failed to mount {}", "abc"))
+                .build(),
+        )
+        .unwrap();
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["level"], "WARN");
+        assert_eq!(v["target"], "vmm_task::sandbox");
+        assert_eq!(v["msg"], "failed to mount abc");
+        assert!(v["time"].as_u64().unwrap() > 0);
+    }
+}
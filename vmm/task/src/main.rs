@@ -37,7 +37,7 @@ use nix::{
     unistd::{getpid, gettid, Pid},
 };
 use signal_hook_tokio::Signals;
-use tokio::fs::File;
+use tokio::{fs::File, sync::Mutex};
 use vmm_common::{
     api::sandbox_ttrpc::create_sandbox_service, mount::mount, ETC_RESOLV, HOSTNAME_FILENAME,
     IPC_NAMESPACE, KUASAR_STATE_DIR, NET_NAMESPACE, RESOLV_FILENAME, SANDBOX_NS_PATH,
@@ -45,22 +45,33 @@ use vmm_common::{
 };
 
 use crate::{
+    cgroup::CGROUP_V2,
     config::TaskConfig,
     debug::listen_debug_console,
     mount::{get_cgroup_mounts, PROC_CGROUPS},
+    policy::Policy,
+    sandbox::SandboxResources,
     sandbox_service::SandboxService,
     task::create_task_service,
 };
 
+mod cgroup;
 mod config;
 mod container;
 mod debug;
 mod device;
+mod events;
 mod io;
+mod kernel;
+mod logger;
 mod mount;
 mod netlink;
+mod oom;
+mod pid_ns;
+mod policy;
 mod sandbox;
 mod sandbox_service;
+mod stats;
 mod stream;
 mod task;
 mod util;
@@ -142,12 +153,14 @@ async fn main() {
     early_init_call().await.expect("early init call");
     let config = TaskConfig::new().await.unwrap();
     let log_level = LevelFilter::from_str(&config.log_level).unwrap();
-    env_logger::Builder::from_default_env()
-        .format_timestamp_micros()
-        .filter_module("containerd_shim", log_level)
-        .filter_module("vmm_task", log_level)
-        .init();
+    let log_rx = logger::init(log_level).expect("init logger");
+    if let Err(e) = logger::forward_logs(log_rx).await {
+        error!("failed to listen log port, {:?}", e);
+    }
     info!("Task server start with config: {:?}", config);
+    init_cgroups(config.cgroup_version)
+        .await
+        .expect("init cgroups");
     match &*config.sharefs_type {
         "9p" => {
             mount_static_mounts(SHAREFS_9P_MOUNTS.clone())
@@ -163,7 +176,14 @@ async fn main() {
             warn!("sharefs_type should be either 9p or virtiofs");
         }
     }
-    if config.debug {
+    let policy = Arc::new(
+        Policy::load(&config.policy_path)
+            .await
+            .expect("load policy"),
+    );
+    if config.debug && !policy.allow_debug_console {
+        warn!("debug console is not allowed by policy");
+    } else if config.debug {
         debug!("listen vsock port 1025 for debug console");
         if let Err(e) = listen_debug_console("vsock://-1:1025").await {
             error!("failed to listen debug console port, {:?}", e);
@@ -173,7 +193,7 @@ async fn main() {
     late_init_call().await.expect("late init call");
 
     // Start ttrpc server
-    let mut server = start_ttrpc_server()
+    let mut server = start_ttrpc_server(policy)
         .await
         .expect("failed to create ttrpc server");
     server.start().await.expect("failed to start ttrpc server");
@@ -185,7 +205,7 @@ async fn main() {
 }
 
 // Do some initialization before everything starts.
-// Such as setting envs, preparing cgroup mounts, setting kernel paras.
+// Such as setting envs, preparing rootfs mounts, setting kernel paras.
 async fn early_init_call() -> Result<()> {
     // Set environment variables from ENVS vector(ordered).
     for (k, v) in ENVS.iter() {
@@ -279,10 +299,19 @@ async fn handle_signals(signals: Signals) {
 
 async fn init_vm_rootfs() -> Result<()> {
     let mounts = VM_ROOTFS_MOUNTS.clone();
-    mount_static_mounts(mounts).await?;
-    // has to mount /proc before find cgroup mounts
-    let cgroup_mounts = get_cgroup_mounts(PROC_CGROUPS, false).await?;
+    mount_static_mounts(mounts).await
+}
+
+// The cgroup version is read from the kernel cmdline,
+// so it has to be called after /proc is mounted by init_vm_rootfs.
+async fn init_cgroups(cgroup_version: u32) -> Result<()> {
+    let unified = cgroup_version == CGROUP_V2;
+    let cgroup_mounts = get_cgroup_mounts(PROC_CGROUPS, unified).await?;
     mount_static_mounts(cgroup_mounts).await?;
+    if unified {
+        // runc only creates the container cgroups with the controllers enabled in the parent
+        return cgroup::enable_controllers().await;
+    }
     // Enable memory hierarchical account.
     // For more information see https://www.kernel.org/doc/Documentation/cgroup-v1/memory.txt
     tokio::fs::write("/sys/fs/cgroup/memory/memory.use_hierarchy", "1")
@@ -341,11 +370,13 @@ async fn mount_static_mounts(mounts: Vec<StaticMount>) -> Result<()> {
 
 // start_ttrpc_server will create all the ttrpc service and register them to a server that
 // bind to vsock 1024 port.
-async fn start_ttrpc_server() -> Result<Server> {
-    let task = create_task_service().await;
+async fn start_ttrpc_server(policy: Arc<Policy>) -> Result<Server> {
+    let resources = Arc::new(Mutex::new(SandboxResources::new().await));
+    let task = create_task_service(resources.clone(), policy.clone()).await;
+    let containers = task.containers();
     let task_service = create_task(Arc::new(Box::new(task)));
 
-    let sandbox = SandboxService::new()?;
+    let sandbox = SandboxService::new(resources, policy, containers)?;
     sandbox.handle_localhost().await?;
     let sandbox_service = create_sandbox_service(Arc::new(Box::new(sandbox)));
 
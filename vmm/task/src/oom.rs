@@ -0,0 +1,198 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    fs::File,
+    io::ErrorKind,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use containerd_shim::{
+    io_error, other, other_error,
+    protos::{events::task::TaskOOM, protobuf::MessageDyn, topics::TASK_OOM_EVENT_TOPIC},
+    Error, Result,
+};
+use log::{debug, info};
+use nix::sys::{
+    eventfd::{eventfd, EfdFlags},
+    inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use tokio::{io::unix::AsyncFd, sync::mpsc::Sender};
+
+use crate::{
+    cgroup::{flat_keyed_value, is_unified, unified_cgroup_path},
+    mount::SYSFS_CGROUPPATH,
+};
+
+const MEMORY_EVENTS: &str = "memory.events";
+const MEMORY_OOM_CONTROL: &str = "memory.oom_control";
+const CGROUP_EVENT_CONTROL: &str = "cgroup.event_control";
+
+type EventSender = Sender<(String, Box<dyn MessageDyn>)>;
+
+/// Watch the memory cgroup of the container process, a TaskOOM is sent
+/// every time a process in the cgroup is killed by the oom killer.
+/// The watch stops by itself when the cgroup is removed.
+pub async fn watch_oom(id: &str, pid: u32, tx: EventSender) -> Result<()> {
+    let cgroup_file = format!("/proc/{}/cgroup", pid);
+    let content = tokio::fs::read_to_string(&cgroup_file)
+        .await
+        .map_err(io_error!(e, "failed to read {}", cgroup_file))?;
+    if is_unified() {
+        let path = unified_cgroup_path(&content).ok_or_else(|| {
+            Error::NotFoundError(format!("no cgroup v2 path of process {} found", pid))
+        })?;
+        let dir = Path::new(SYSFS_CGROUPPATH).join(path.trim_start_matches('/'));
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)
+            .map_err(other_error!(e, "failed to init inotify"))?;
+        if let Err(e) = inotify.add_watch(&dir.join(MEMORY_EVENTS), AddWatchFlags::IN_MODIFY) {
+            nix::unistd::close(inotify.as_raw_fd()).unwrap_or_default();
+            return Err(other!("failed to watch {}: {}", MEMORY_EVENTS, e));
+        }
+        let fd = inotify.as_raw_fd();
+        let inotify = AsyncFd::new(inotify).map_err(|e| {
+            nix::unistd::close(fd).unwrap_or_default();
+            other!("failed to register inotify: {}", e)
+        })?;
+        tokio::spawn(watch_memory_events(id.to_string(), dir, inotify, tx));
+    } else {
+        let path = memory_cgroup_path(&content).ok_or_else(|| {
+            Error::NotFoundError(format!("no memory cgroup path of process {} found", pid))
+        })?;
+        let dir = Path::new(SYSFS_CGROUPPATH)
+            .join("memory")
+            .join(path.trim_start_matches('/'));
+        let efd = register_oom_event(&dir)?;
+        let efd = AsyncFd::new(efd).map_err(|e| {
+            nix::unistd::close(efd).unwrap_or_default();
+            other!("failed to register eventfd: {}", e)
+        })?;
+        tokio::spawn(watch_oom_control(id.to_string(), dir, efd, tx));
+    }
+    debug!("watching oom events of container {}", id);
+    Ok(())
+}
+
+// register an eventfd on memory.oom_control, see Documentation/cgroup-v1/memory.txt
+fn register_oom_event(dir: &Path) -> Result<RawFd> {
+    let oom_control = File::open(dir.join(MEMORY_OOM_CONTROL)).map_err(io_error!(
+        e,
+        "failed to open {}",
+        MEMORY_OOM_CONTROL
+    ))?;
+    let efd = eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)
+        .map_err(other_error!(e, "failed to create eventfd"))?;
+    std::fs::write(
+        dir.join(CGROUP_EVENT_CONTROL),
+        format!("{} {}", efd, oom_control.as_raw_fd()),
+    )
+    .map_err(|e| {
+        nix::unistd::close(efd).unwrap_or_default();
+        other!("failed to write {}: {}", CGROUP_EVENT_CONTROL, e)
+    })?;
+    Ok(efd)
+}
+
+async fn watch_oom_control(id: String, dir: PathBuf, efd: AsyncFd<RawFd>, tx: EventSender) {
+    let mut buf = [0u8; 8];
+    loop {
+        match read_async_fd(&efd, |fd| nix::unistd::read(*fd, &mut buf)).await {
+            Ok(8) => {}
+            _ => break,
+        }
+        // the eventfd is also notified when the cgroup is removed
+        if !dir.join(CGROUP_EVENT_CONTROL).exists() || !send_oom(&id, &tx).await {
+            break;
+        }
+    }
+    nix::unistd::close(efd.into_inner()).unwrap_or_default();
+}
+
+async fn watch_memory_events(id: String, dir: PathBuf, inotify: AsyncFd<Inotify>, tx: EventSender) {
+    let file = dir.join(MEMORY_EVENTS);
+    let mut oom_kill = 0;
+    loop {
+        // the file can not be read any more when the cgroup is removed
+        let content = match tokio::fs::read_to_string(&file).await {
+            Ok(c) => c,
+            Err(_) => break,
+        };
+        let count = flat_keyed_value(&content, "oom_kill");
+        if count > oom_kill {
+            oom_kill = count;
+            if !send_oom(&id, &tx).await {
+                break;
+            }
+        }
+        if read_async_fd(&inotify, |i| i.read_events()).await.is_err() {
+            break;
+        }
+    }
+    nix::unistd::close(inotify.into_inner().as_raw_fd()).unwrap_or_default();
+}
+
+// wait for the fd to be readable and read it, retry if it is not ready yet.
+async fn read_async_fd<T: AsRawFd, R>(
+    fd: &AsyncFd<T>,
+    mut f: impl FnMut(&T) -> nix::Result<R>,
+) -> std::io::Result<R> {
+    loop {
+        let mut guard = fd.readable().await?;
+        match guard.try_io(|inner| f(inner.get_ref()).map_err(std::io::Error::from)) {
+            Ok(Err(e)) if e.kind() == ErrorKind::Interrupted => continue,
+            Ok(res) => return res,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+async fn send_oom(id: &str, tx: &EventSender) -> bool {
+    info!("container {} is killed by oom killer", id);
+    let mut event = TaskOOM::new();
+    event.container_id = id.to_string();
+    tx.send((TASK_OOM_EVENT_TOPIC.to_string(), Box::new(event)))
+        .await
+        .is_ok()
+}
+
+// lines of the v1 hierarchies in /proc/<pid>/cgroup are like "4:memory:/kubepods/pod1/abc"
+fn memory_cgroup_path(content: &str) -> Option<&str> {
+    content.lines().find_map(|l| {
+        let mut fields = l.splitn(3, ':');
+        let controllers = fields.nth(1)?;
+        let path = fields.next()?;
+        controllers
+            .split(',')
+            .any(|c| c == "memory")
+            .then_some(path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::memory_cgroup_path;
+
+    #[test]
+    fn test_memory_cgroup_path() {
+        let content = "12:cpu,cpuacct:/kubepods/pod1/abc\n\
+                       4:memory:/kubepods/pod1/abc\n\
+                       0::/\n";
+        assert_eq!(memory_cgroup_path(content), Some("/kubepods/pod1/abc"));
+        assert_eq!(memory_cgroup_path("0::/kubepods/pod1/abc\n"), None);
+        assert_eq!(memory_cgroup_path("3:memory,hugetlb:/a\n"), Some("/a"));
+    }
+}
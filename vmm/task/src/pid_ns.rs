@@ -0,0 +1,150 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{path::Path, time::Duration};
+
+use containerd_shim::{io_error, other_error, util::mkdir, Error, Result};
+use lazy_static::lazy_static;
+use log::info;
+use nix::{
+    errno::Errno,
+    mount::MsFlags,
+    sched::{clone, CloneFlags},
+    sys::{
+        signal::{signal, SigHandler, Signal},
+        wait::{waitpid, WaitPidFlag},
+    },
+    unistd::Pid,
+};
+use oci_spec::runtime::{LinuxNamespaceType, Spec};
+use tokio::{fs::File, sync::Mutex};
+use vmm_common::{PID_NAMESPACE, SANDBOX_NS_PATH};
+
+const REAPER_STACK_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    // whether the sandbox pid namespace is created
+    static ref SANDBOX_PID_NS: Mutex<bool> = Mutex::new(false);
+}
+
+fn sandbox_pid_ns_path() -> String {
+    format!("{}/{}", SANDBOX_NS_PATH, PID_NAMESPACE)
+}
+
+/// Whether the container joins the pid namespace shared by the containers of the sandbox.
+pub fn joins_sandbox_pid_ns(spec: &Spec) -> bool {
+    let ns_path = sandbox_pid_ns_path();
+    spec.linux()
+        .as_ref()
+        .and_then(|l| l.namespaces().as_ref())
+        .map(|namespaces| {
+            namespaces.iter().any(|ns| {
+                ns.typ() == LinuxNamespaceType::Pid
+                    && ns.path().as_deref() == Some(Path::new(&ns_path))
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// Create the sandbox pid namespace when the first container joins it, the init process
+/// of the namespace reaps the orphaned processes in it, as the pause container does.
+pub async fn ensure_sandbox_pid_ns() -> Result<()> {
+    let mut created = SANDBOX_PID_NS.lock().await;
+    if *created {
+        return Ok(());
+    }
+    mkdir(SANDBOX_NS_PATH, 0o711).await?;
+    let ns_path = sandbox_pid_ns_path();
+    File::create(&ns_path)
+        .await
+        .map_err(io_error!(e, "failed to create: {}", ns_path))?;
+
+    let pid = spawn_reaper()?;
+    nix::mount::mount(
+        Some(format!("/proc/{}/ns/pid", pid).as_str()),
+        ns_path.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .map_err(other_error!(e, "failed to mount sandbox pid ns"))?;
+    info!("sandbox pid namespace created with reaper {}", pid);
+    *created = true;
+    Ok(())
+}
+
+fn spawn_reaper() -> Result<Pid> {
+    let mut stack = vec![0u8; REAPER_STACK_SIZE];
+    clone(
+        Box::new(reap),
+        &mut stack,
+        CloneFlags::CLONE_NEWPID,
+        Some(Signal::SIGCHLD as i32),
+    )
+    .map_err(other_error!(e, "failed to clone reaper"))
+}
+
+// runs as the pid 1 of the sandbox pid namespace, which is never expected to exit,
+// as all the processes in the namespace are killed when it exits.
+fn reap() -> isize {
+    // the signal handlers of vmm-task are inherited, which should not be run in the reaper
+    for sig in [
+        Signal::SIGCHLD,
+        Signal::SIGTERM,
+        Signal::SIGINT,
+        Signal::SIGPIPE,
+    ] {
+        let _ = unsafe { signal(sig, SigHandler::SigDfl) };
+    }
+    loop {
+        match waitpid(None, Some(WaitPidFlag::__WALL)) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            // no child in the namespace yet
+            Err(_) => std::thread::sleep(Duration::from_secs(1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        LinuxBuilder, LinuxNamespaceBuilder, LinuxNamespaceType, Spec, SpecBuilder,
+    };
+
+    use super::joins_sandbox_pid_ns;
+
+    fn spec(pid_ns_path: Option<&str>) -> Spec {
+        let mut ns = LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Pid);
+        if let Some(p) = pid_ns_path {
+            ns = ns.path(p);
+        }
+        let linux = LinuxBuilder::default()
+            .namespaces(vec![ns.build().unwrap()])
+            .build()
+            .unwrap();
+        SpecBuilder::default().linux(linux).build().unwrap()
+    }
+
+    #[test]
+    fn test_joins_sandbox_pid_ns() {
+        assert!(joins_sandbox_pid_ns(&spec(Some("/run/sandbox-ns/pid"))));
+        assert!(!joins_sandbox_pid_ns(&spec(None)));
+        assert!(!joins_sandbox_pid_ns(&spec(Some("/proc/1/ns/pid"))));
+        assert!(!joins_sandbox_pid_ns(
+            &SpecBuilder::default().build().unwrap()
+        ));
+    }
+}
@@ -0,0 +1,590 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::{Component, Path};
+
+use containerd_shim::{other, other_error, Error, Result};
+use log::{info, warn};
+use oci_spec::runtime::{Hooks, LinuxCapabilities, Process, Spec};
+use serde::Deserialize;
+use vmm_common::storage::Storage;
+
+// the policy file in the initrd, which is used if no policy is given in the kernel cmdline
+pub const DEFAULT_POLICY_PATH: &str = "/etc/kuasar/policy.json";
+
+/// A declarative allow list of what the host is allowed to ask the agent to do,
+/// a list that is not set allows everything. Images are not in the list, as the image of
+/// a container is told by the host, the agent can not tell it apart from a forged one.
+///
+/// A pattern matches a value if they are equal, or if the pattern ends with "*"
+/// and the value starts with the part before it.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// the first arg of the processes of containers and execs, and the paths of oci hooks
+    #[serde(default)]
+    pub commands: Option<Vec<String>>,
+    /// sources of bind mounts of containers
+    #[serde(default)]
+    pub mount_sources: Option<Vec<String>>,
+    /// capabilities of the processes of containers and execs, like "CAP_NET_BIND_SERVICE"
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+    /// sysctls of the pod, like "net.core.somaxconn"
+    #[serde(default)]
+    pub sysctls: Option<Vec<String>>,
+    /// kernel modules loaded for the pod
+    #[serde(default)]
+    pub kernel_modules: Option<Vec<String>>,
+    #[serde(default = "default_allow")]
+    pub allow_exec: bool,
+    /// allow restoring containers from checkpoint images, the processes in which can not be checked
+    #[serde(default = "default_allow")]
+    pub allow_restore: bool,
+    /// allow UpdateInterfaces and UpdateRoutes requests
+    #[serde(default = "default_allow")]
+    pub allow_network_update: bool,
+    #[serde(default = "default_allow")]
+    pub allow_debug_console: bool,
+}
+
+fn default_allow() -> bool {
+    true
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            commands: None,
+            mount_sources: None,
+            capabilities: None,
+            sysctls: None,
+            kernel_modules: None,
+            allow_exec: true,
+            allow_restore: true,
+            allow_network_update: true,
+            allow_debug_console: true,
+        }
+    }
+}
+
+impl Policy {
+    /// Load the policy from path, or from the default path in initrd if path is empty,
+    /// everything is allowed if there is no policy file in initrd.
+    pub async fn load(path: &str) -> Result<Self> {
+        let path = if path.is_empty() {
+            if !Path::new(DEFAULT_POLICY_PATH).exists() {
+                info!("no policy found, all requests are allowed");
+                return Ok(Self::default());
+            }
+            DEFAULT_POLICY_PATH
+        } else {
+            path
+        };
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(other_error!(e, format!("failed to read policy {}", path)))?;
+        let policy = serde_json::from_str::<Self>(&content)
+            .map_err(|e| other!("failed to parse policy {}: {}", path, e))?;
+        info!("policy loaded from {}: {:?}", path, policy);
+        Ok(policy)
+    }
+
+    pub fn check_create(&self, id: &str, spec: &Spec) -> Result<()> {
+        let res = self.do_check_create(spec);
+        log_decision(&format!("create container {}", id), &res);
+        res
+    }
+
+    // the storages are mounted by the agent from what the host tells, so the mount points, and the
+    // sources of the bind mounts, are checked like the mounts of the spec
+    pub fn check_storages(&self, id: &str, storages: &[Storage]) -> Result<()> {
+        let res = self.do_check_storages(storages);
+        log_decision(&format!("storages of container {}", id), &res);
+        res
+    }
+
+    pub fn check_exec(&self, id: &str, exec_id: &str, process: &Process) -> Result<()> {
+        let res = if self.allow_exec {
+            self.check_process(process)
+        } else {
+            Err(denied("exec is not allowed".to_string()))
+        };
+        log_decision(&format!("exec {} in container {}", exec_id, id), &res);
+        res
+    }
+
+    pub fn check_restore(&self, id: &str) -> Result<()> {
+        let res = if self.allow_restore {
+            Ok(())
+        } else {
+            Err(denied("restore is not allowed".to_string()))
+        };
+        log_decision(&format!("restore container {}", id), &res);
+        res
+    }
+
+    pub fn check_setup_kernel(&self, sysctls: &[&str], modules: &[&str]) -> Result<()> {
+        let res = check_all(&self.sysctls, sysctls, "sysctl")
+            .and_then(|_| check_all(&self.kernel_modules, modules, "kernel module"));
+        log_decision("setup kernel", &res);
+        res
+    }
+
+    pub fn check_network_update(&self, action: &str) -> Result<()> {
+        let res = if self.allow_network_update {
+            Ok(())
+        } else {
+            Err(denied(format!("{} is not allowed", action)))
+        };
+        log_decision(action, &res);
+        res
+    }
+
+    fn do_check_create(&self, spec: &Spec) -> Result<()> {
+        // hooks are run by runc in the guest, so they are commands too
+        let hooks = spec.hooks().as_ref().map(hook_paths).unwrap_or_default();
+        let hooks: Vec<&str> = hooks.iter().map(|x| x.as_str()).collect();
+        check_all(&self.commands, &hooks, "hook")?;
+        if let Some(sources) = &self.mount_sources {
+            for m in spec.mounts().as_ref().unwrap_or(&vec![]) {
+                let is_bind = m.typ().as_deref() == Some("bind")
+                    || m.options()
+                        .as_ref()
+                        .map(|o| o.iter().any(|x| x == "bind" || x == "rbind"))
+                        .unwrap_or_default();
+                let source = m
+                    .source()
+                    .as_ref()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default();
+                if is_bind && !matches_any_path(sources, source) {
+                    return Err(denied(format!("mount source {} is not allowed", source)));
+                }
+            }
+        }
+        match spec.process() {
+            Some(p) => self.check_process(p),
+            None => Ok(()),
+        }
+    }
+
+    fn do_check_storages(&self, storages: &[Storage]) -> Result<()> {
+        if let Some(sources) = &self.mount_sources {
+            for s in storages {
+                if !matches_any_path(sources, &s.mount_point) {
+                    return Err(denied(format!(
+                        "storage mount point {} is not allowed",
+                        s.mount_point
+                    )));
+                }
+                let is_bind =
+                    s.fstype == "bind" || s.options.iter().any(|x| x == "bind" || x == "rbind");
+                if is_bind && !matches_any_path(sources, &s.source) {
+                    return Err(denied(format!("mount source {} is not allowed", s.source)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_process(&self, process: &Process) -> Result<()> {
+        if let Some(commands) = &self.commands {
+            let command = process
+                .args()
+                .as_ref()
+                .and_then(|a| a.first())
+                .map(|x| x.as_str())
+                .unwrap_or_default();
+            if !matches_any(commands, command) {
+                return Err(denied(format!("command \"{}\" is not allowed", command)));
+            }
+        }
+        if let (Some(allowed), Some(caps)) = (&self.capabilities, process.capabilities()) {
+            for cap in capability_names(caps) {
+                if !matches_any(allowed, &cap) {
+                    return Err(denied(format!("capability {} is not allowed", cap)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_all(patterns: &Option<Vec<String>>, values: &[&str], kind: &str) -> Result<()> {
+    if let Some(patterns) = patterns {
+        if let Some(v) = values.iter().find(|v| !matches_any(patterns, v)) {
+            return Err(denied(format!("{} \"{}\" is not allowed", kind, v)));
+        }
+    }
+    Ok(())
+}
+
+fn hook_paths(hooks: &Hooks) -> Vec<String> {
+    [
+        hooks.prestart(),
+        hooks.create_runtime(),
+        hooks.create_container(),
+        hooks.start_container(),
+        hooks.poststart(),
+        hooks.poststop(),
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+    .map(|h| h.path().display().to_string())
+    .collect()
+}
+
+fn matches_any(patterns: &[String], value: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => p == value,
+    })
+}
+
+// like matches_any, but paths are compared by components, so "/a/*" does not match "/a/../b",
+// and paths with ".." never match, as they may point out of the allowed dirs.
+fn matches_any_path(patterns: &[String], value: &str) -> bool {
+    let path = Path::new(value);
+    if path.components().any(|c| c == Component::ParentDir) {
+        return false;
+    }
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => path_has_prefix(path, prefix),
+        None => path == Path::new(p),
+    })
+}
+
+// "/a/*" matches the paths under "/a", "/a/b*" matches the paths under "/a" whose next
+// component starts with "b".
+fn path_has_prefix(path: &Path, prefix: &str) -> bool {
+    if prefix.is_empty() {
+        return true;
+    }
+    let prefix_path = Path::new(prefix);
+    if prefix.ends_with('/') {
+        return path.starts_with(prefix_path)
+            && path.components().count() > prefix_path.components().count();
+    }
+    let (parent, name) = match (prefix_path.parent(), prefix_path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name.to_string_lossy()),
+        _ => return false,
+    };
+    if !path.starts_with(parent) {
+        return false;
+    }
+    match path.components().nth(parent.components().count()) {
+        Some(Component::Normal(c)) => c.to_string_lossy().starts_with(name.as_ref()),
+        _ => false,
+    }
+}
+
+// names of the capabilities in all the sets, like "CAP_CHOWN"
+fn capability_names(caps: &LinuxCapabilities) -> Vec<String> {
+    let mut names = vec![];
+    for set in [
+        caps.bounding(),
+        caps.effective(),
+        caps.inheritable(),
+        caps.permitted(),
+        caps.ambient(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        for cap in set {
+            if let Ok(serde_json::Value::String(name)) = serde_json::to_value(cap) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    names
+}
+
+fn denied(reason: String) -> Error {
+    Error::InvalidArgument(format!("request denied by policy: {}", reason))
+}
+
+fn log_decision(action: &str, res: &Result<()>) {
+    match res {
+        Ok(_) => info!("policy allows {}", action),
+        Err(e) => warn!("policy denies {}: {}", action, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        Capability, HookBuilder, HooksBuilder, LinuxCapabilitiesBuilder, MountBuilder,
+        ProcessBuilder, Spec, SpecBuilder,
+    };
+    use vmm_common::storage::Storage;
+
+    use super::{matches_any, matches_any_path, Policy};
+
+    fn spec(args: &[&str], mount_source: &str) -> Spec {
+        let process = ProcessBuilder::default()
+            .args(args.iter().map(|x| x.to_string()).collect::<Vec<String>>())
+            .build()
+            .unwrap();
+        let mount = MountBuilder::default()
+            .destination("/data")
+            .typ("bind")
+            .source(mount_source)
+            .options(vec!["rbind".to_string()])
+            .build()
+            .unwrap();
+        SpecBuilder::default()
+            .process(process)
+            .mounts(vec![mount])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_matches_any() {
+        let patterns = vec!["docker.io/library/*".to_string(), "/bin/sh".to_string()];
+        assert!(matches_any(&patterns, "docker.io/library/nginx:latest"));
+        assert!(matches_any(&patterns, "/bin/sh"));
+        assert!(!matches_any(&patterns, "/bin/shell"));
+        assert!(!matches_any(&patterns, "quay.io/nginx"));
+        assert!(!matches_any(&[], "/bin/sh"));
+    }
+
+    #[test]
+    fn test_matches_any_path() {
+        let patterns = vec![
+            "/run/kuasar/storage/containers/*".to_string(),
+            "/run/kuasar/local/emptydir*".to_string(),
+            "/dev/shm".to_string(),
+        ];
+        assert!(matches_any_path(
+            &patterns,
+            "/run/kuasar/storage/containers/storage1"
+        ));
+        assert!(matches_any_path(
+            &patterns,
+            "/run/kuasar/storage/containers//storage1/data"
+        ));
+        assert!(matches_any_path(&patterns, "/run/kuasar/local/emptydir1"));
+        assert!(matches_any_path(&patterns, "/dev/shm/"));
+        assert!(!matches_any_path(
+            &patterns,
+            "/run/kuasar/storage/containers/../../../etc"
+        ));
+        assert!(!matches_any_path(
+            &patterns,
+            "/run/kuasar/storage/containers/storage1/../../../../etc"
+        ));
+        assert!(!matches_any_path(
+            &patterns,
+            "/run/kuasar/storage/containers"
+        ));
+        assert!(!matches_any_path(
+            &patterns,
+            "/run/kuasar/storage/containers-evil/storage1"
+        ));
+        assert!(!matches_any_path(&patterns, "/run/kuasar/local/other"));
+        assert!(!matches_any_path(&patterns, "/dev/shm/x"));
+        assert!(!matches_any_path(&["/*".to_string()], "/"));
+        assert!(matches_any_path(&["/*".to_string()], "/etc"));
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = serde_json::from_str::<Policy>("{}").unwrap();
+        assert_eq!(policy, Policy::default());
+        let s = spec(&["/any"], "/any");
+        assert!(policy.check_create("c1", &s).is_ok());
+        assert!(policy
+            .check_exec("c1", "e1", s.process().as_ref().unwrap())
+            .is_ok());
+        assert!(policy.check_network_update("update interfaces").is_ok());
+        assert!(policy.check_restore("c1").is_ok());
+        assert!(policy.check_setup_kernel(&["any"], &["any"]).is_ok());
+    }
+
+    #[test]
+    fn test_check_create() {
+        let policy = serde_json::from_str::<Policy>(
+            r#"{
+                "commands": ["nginx", "/usr/bin/hook"],
+                "mount_sources": ["/run/kuasar/storage/containers/*"]
+            }"#,
+        )
+        .unwrap();
+        let with_hook = |path: &str| {
+            let mut s = spec(&["nginx"], "/run/kuasar/storage/containers/storage1");
+            let hook = HookBuilder::default().path(path).build().unwrap();
+            s.set_hooks(Some(
+                HooksBuilder::default()
+                    .create_runtime(vec![hook])
+                    .build()
+                    .unwrap(),
+            ));
+            s
+        };
+        let cases = vec![
+            (
+                spec(&["nginx", "-g"], "/run/kuasar/storage/containers/storage1"),
+                true,
+            ),
+            (
+                spec(
+                    &["/bin/sh", "-c"],
+                    "/run/kuasar/storage/containers/storage1",
+                ),
+                false,
+            ),
+            (spec(&["nginx"], "/etc"), false),
+            (
+                spec(&["nginx"], "/run/kuasar/storage/containers/../../../etc"),
+                false,
+            ),
+            (with_hook("/usr/bin/hook"), true),
+            (with_hook("/bin/sh"), false),
+        ];
+        for (s, allowed) in cases {
+            assert_eq!(policy.check_create("c1", &s).is_ok(), allowed, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_check_storages() {
+        let policy = serde_json::from_str::<Policy>(
+            r#"{"mount_sources": ["/run/kuasar/storage/containers/*"]}"#,
+        )
+        .unwrap();
+        let storage = |source: &str, fstype: &str, mount_point: &str| Storage {
+            host_source: "".to_string(),
+            r#type: "".to_string(),
+            id: "storage1".to_string(),
+            device_id: None,
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: source.to_string(),
+            driver: "".to_string(),
+            driver_options: vec![],
+            fstype: fstype.to_string(),
+            options: vec![],
+            mount_point: mount_point.to_string(),
+            host_options: vec![],
+        };
+        let cases = vec![
+            (
+                storage(
+                    "kuasar",
+                    "virtiofs",
+                    "/run/kuasar/storage/containers/storage1",
+                ),
+                true,
+            ),
+            (storage("kuasar", "virtiofs", "/etc"), false),
+            (
+                storage(
+                    "kuasar",
+                    "virtiofs",
+                    "/run/kuasar/storage/containers/../../../etc",
+                ),
+                false,
+            ),
+            (
+                storage(
+                    "/run/kuasar/storage/containers/storage0",
+                    "bind",
+                    "/run/kuasar/storage/containers/storage1",
+                ),
+                true,
+            ),
+            (
+                storage("/etc", "bind", "/run/kuasar/storage/containers/storage1"),
+                false,
+            ),
+        ];
+        for (s, allowed) in cases {
+            assert_eq!(
+                policy.check_storages("c1", &[s.clone()]).is_ok(),
+                allowed,
+                "{:?}",
+                s
+            );
+        }
+        assert!(Policy::default()
+            .check_storages("c1", &[storage("/etc", "bind", "/etc")])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_check_exec() {
+        let policy = serde_json::from_str::<Policy>(
+            r#"{"capabilities": ["CAP_NET_BIND_SERVICE"], "allow_network_update": false}"#,
+        )
+        .unwrap();
+        let caps = |c: Capability| {
+            LinuxCapabilitiesBuilder::default()
+                .bounding([c].into_iter().collect::<std::collections::HashSet<_>>())
+                .build()
+                .unwrap()
+        };
+        let process = ProcessBuilder::default()
+            .args(vec!["sh".to_string()])
+            .capabilities(caps(Capability::NetBindService))
+            .build()
+            .unwrap();
+        assert!(policy.check_exec("c1", "e1", &process).is_ok());
+        let process = ProcessBuilder::default()
+            .args(vec!["sh".to_string()])
+            .capabilities(caps(Capability::SysAdmin))
+            .build()
+            .unwrap();
+        assert!(policy.check_exec("c1", "e1", &process).is_err());
+        assert!(policy.check_network_update("update routes").is_err());
+
+        let policy = serde_json::from_str::<Policy>(r#"{"allow_exec": false}"#).unwrap();
+        let process = ProcessBuilder::default().build().unwrap();
+        assert!(policy.check_exec("c1", "e1", &process).is_err());
+    }
+
+    #[test]
+    fn test_check_restore_and_setup_kernel() {
+        let policy = serde_json::from_str::<Policy>(
+            r#"{
+                "sysctls": ["net.core.somaxconn", "kernel.shm*"],
+                "kernel_modules": ["nf_conntrack"],
+                "allow_restore": false
+            }"#,
+        )
+        .unwrap();
+        assert!(policy.check_restore("c1").is_err());
+        assert!(policy
+            .check_setup_kernel(&["net.core.somaxconn", "kernel.shmmax"], &["nf_conntrack"])
+            .is_ok());
+        assert!(policy
+            .check_setup_kernel(&["net.ipv4.ip_forward"], &[])
+            .is_err());
+        assert!(policy.check_setup_kernel(&[], &["br_netfilter"]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        assert!(serde_json::from_str::<Policy>(r#"{"image": []}"#).is_err());
+        // the image of a container can not be checked in the guest
+        assert!(serde_json::from_str::<Policy>(r#"{"images": []}"#).is_err());
+    }
+}
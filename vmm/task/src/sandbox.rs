@@ -14,16 +14,37 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{path::Path, time::Duration};
+use std::{
+    fs::Permissions,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path},
+    time::Duration,
+};
 
 use containerd_shim::{error::Error, other, other_error, util::IntoOption, Result};
 use log::{debug, warn};
+use nix::sys::statvfs::statvfs;
 use vmm_common::{
+    api::sandbox::VolumeStats,
     mount::{mount, unmount},
-    storage::{Storage, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERSCSITYPE},
+    storage::{
+        Storage, DRIVER9PTYPE, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERLOCALTYPE,
+        DRIVERMMIOBLKTYPE, DRIVERNVDIMMTYPE, DRIVEROVERLAYFSTYPE, DRIVERSCSITYPE,
+        DRIVERVIRTIOFSTYPE, LAYER_OPTION_PREFIX, LOCAL_STORAGE_DIR,
+    },
+};
+
+use crate::device::{
+    scan_scsi_bus, Device, DeviceMatcher, DeviceMonitor, DeviceType, SYSFS_BLK_DEVICE_PATH,
+    SYSFS_NET_PATH,
 };
 
-use crate::device::{scan_scsi_bus, Device, DeviceMatcher, DeviceMonitor, DeviceType};
+const LOCAL_STORAGE_DEFAULT_MODE: u32 = 0o777;
+
+const DEFAULT_9P_OPTIONS: &[&str] = &["trans=virtio", "version=9p2000.L"];
+
+const SECTOR_SIZE: u64 = 512;
+const DEVICE_RESIZE_TIMEOUT_IN_SEC: u64 = 10;
 
 pub struct SandboxResources {
     storages: Vec<Storage>,
@@ -73,8 +94,33 @@ impl SandboxResources {
             DRIVERBLKTYPE => {
                 self.handle_blk_storage(&mut storage).await?;
             }
+            DRIVERMMIOBLKTYPE => {
+                self.handle_mmio_blk_storage(&mut storage).await?;
+            }
+            DRIVERNVDIMMTYPE => {
+                self.handle_nvdimm_storage(&mut storage).await?;
+            }
+            DRIVER9PTYPE => {
+                storage.fstype = "9p".to_string();
+                append_default_options(&mut storage.options, DEFAULT_9P_OPTIONS);
+                mount_storage(&storage).await?;
+            }
+            DRIVERVIRTIOFSTYPE => {
+                storage.fstype = "virtiofs".to_string();
+                mount_storage(&storage).await?;
+            }
+            DRIVERLOCALTYPE => {
+                handle_local_storage(&mut storage).await?;
+            }
+            DRIVEROVERLAYFSTYPE => {
+                handle_overlay_storage(&storage).await?;
+            }
             _ => {
-                unimplemented!("storage driver not implemented {}", storage.driver)
+                return Err(other!(
+                    "storage driver not implemented {} of storage {}",
+                    storage.driver,
+                    storage.id
+                ));
             }
         }
         self.storages.push(storage);
@@ -91,7 +137,8 @@ impl SandboxResources {
                 true
             }
         });
-        for s in removed {
+        // unmount in the reverse order, as the overlay is on top of the layer storages
+        for s in removed.into_iter().rev() {
             debug!("unmount storage {:?}", s);
             if let Err(_e) = unmount_storage(&s).await {
                 warn!("failed to unmount storage {:?}", s);
@@ -124,18 +171,207 @@ impl SandboxResources {
         Ok(())
     }
 
-    async fn get_device(&self, addr: &str, ty: DeviceType) -> Result<Device> {
-        let mut s = self
-            .device_monitor
-            .subscribe(TypeAddrDeviceMatcher::new(ty, addr.to_string()))
-            .await;
-        let res = tokio::time::timeout(Duration::from_secs(10), s.rx.recv())
+    async fn handle_mmio_blk_storage(&mut self, storage: &mut Storage) -> Result<()> {
+        // Retrieve the device path from the serial of virtio-mmio block device.
+        let device = self
+            .get_device(&storage.source, DeviceType::MmioBlk)
+            .await?;
+        let path = device.path.to_string();
+        storage.source = path;
+
+        mount_storage(storage).await?;
+        Ok(())
+    }
+
+    async fn handle_nvdimm_storage(&mut self, storage: &mut Storage) -> Result<()> {
+        // the source is the pmem device, which may be given as its name or its path in /dev
+        let name = Path::new(&storage.source)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| other!("invalid nvdimm device {}", storage.source))?
+            .to_string();
+        let device = self.get_device(&name, DeviceType::Nvdimm).await?;
+        storage.source = device.path;
+
+        mount_storage(storage).await?;
+        Ok(())
+    }
+
+    /// Grow the filesystem of the block storage mounted at mount_point,
+    /// after its device is grown to size in bytes by the host.
+    pub async fn resize_storage(&self, mount_point: &str, size: u64) -> Result<()> {
+        let storage = self
+            .storages
+            .iter()
+            .find(|s| s.mount_point == mount_point)
+            .ok_or_else(|| other!("no storage mounted at {}", mount_point))?;
+        if ![DRIVERBLKTYPE, DRIVERMMIOBLKTYPE, DRIVERSCSITYPE].contains(&&*storage.driver) {
+            return Err(other!(
+                "storage {} of driver {} can not be resized",
+                storage.id,
+                storage.driver
+            ));
+        }
+        let dev_name = Path::new(&storage.source)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| other!("invalid device {} of storage", storage.source))?;
+        // scsi disks do not notice the capacity change until they are rescanned
+        if storage.driver == DRIVERSCSITYPE {
+            let rescan = format!("{}/{}/device/rescan", SYSFS_BLK_DEVICE_PATH, dev_name);
+            tokio::fs::write(&rescan, "1")
+                .await
+                .map_err(other_error!(e, format!("failed to write {}", rescan)))?;
+        }
+        wait_device_size(dev_name, size).await?;
+
+        let (cmd, arg) = resize_command(storage).ok_or_else(|| {
+            other!(
+                "online resize of fstype {} is not supported",
+                storage.fstype
+            )
+        })?;
+        debug!(
+            "grow filesystem of storage {} by {} {}",
+            storage.id, cmd, arg
+        );
+        let output = tokio::process::Command::new(cmd)
+            .arg(arg)
+            .output()
             .await
-            .map_err(other_error!(
-                e,
-                format!("timeout waiting for device with addr {} ready", addr)
-            ))?;
-        res.ok_or_else(|| other!("can not get device with addr {}", addr))
+            .map_err(other_error!(e, format!("failed to execute {}", cmd)))?;
+        if !output.status.success() {
+            return Err(other!(
+                "failed to grow filesystem by {}: {}",
+                cmd,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Mount points of the volumes in mount_points, or all the volumes if it is empty,
+    /// an overlay and the storages it is on, like the image layers, are not volumes.
+    pub fn volume_mount_points(&self, mount_points: &[String]) -> Vec<String> {
+        let layers: Vec<&str> = self
+            .storages
+            .iter()
+            .flat_map(|s| s.driver_options.iter())
+            .filter_map(|o| o.strip_prefix(LAYER_OPTION_PREFIX))
+            .collect();
+        self.storages
+            .iter()
+            .filter(|s| s.driver != DRIVEROVERLAYFSTYPE && !layers.contains(&s.id.as_str()))
+            .filter(|s| mount_points.is_empty() || mount_points.contains(&s.mount_point))
+            .map(|s| s.mount_point.to_string())
+            .collect()
+    }
+
+    async fn get_device(&self, addr: &str, ty: DeviceType) -> Result<Device> {
+        get_device(&self.device_monitor, addr, ty).await
+    }
+
+    /// The device monitor, for waiting the devices without holding the lock of the sandbox.
+    pub fn device_monitor(&self) -> DeviceMonitor {
+        self.device_monitor.clone()
+    }
+}
+
+async fn get_device(monitor: &DeviceMonitor, addr: &str, ty: DeviceType) -> Result<Device> {
+    let mut s = monitor
+        .subscribe(TypeAddrDeviceMatcher::new(ty, addr.to_string()))
+        .await;
+    let res = tokio::time::timeout(Duration::from_secs(10), s.rx.recv()).await;
+    monitor.unsubscribe(s.id).await;
+    let res = res.map_err(other_error!(
+        e,
+        format!("timeout waiting for device with addr {} ready", addr)
+    ))?;
+    res.ok_or_else(|| other!("can not get device with addr {}", addr))
+}
+
+/// Wait for the nic with the hardware address to be hot plugged, returns the name of it.
+pub async fn wait_net_device(monitor: &DeviceMonitor, hw_addr: &str) -> Result<String> {
+    let mut s = monitor
+        .subscribe(HwAddrDeviceMatcher::new(hw_addr.to_string()))
+        .await;
+    let res = tokio::time::timeout(Duration::from_secs(10), s.rx.recv()).await;
+    // the nic may be renamed later, which should not be sent to this subscription
+    monitor.unsubscribe(s.id).await;
+    let res = res.map_err(other_error!(
+        e,
+        format!("timeout waiting for nic with address {} ready", hw_addr)
+    ))?;
+    res.map(|d| d.addr)
+        .ok_or_else(|| other!("can not get nic with address {}", hw_addr))
+}
+
+/// Get the path of the virtio serial port with the name given by the host.
+pub async fn get_serial_device(monitor: &DeviceMonitor, name: &str) -> Result<String> {
+    let device = get_device(monitor, name, DeviceType::Serial).await?;
+    Ok(device.path)
+}
+
+// the size of virtio block devices is changed by the config change interrupt asynchronously
+async fn wait_device_size(dev_name: &str, size: u64) -> Result<()> {
+    let size_path = format!("{}/{}/size", SYSFS_BLK_DEVICE_PATH, dev_name);
+    let wait = async {
+        loop {
+            let sectors = tokio::fs::read_to_string(&size_path)
+                .await
+                .unwrap_or_default()
+                .trim()
+                .parse::<u64>()
+                .unwrap_or_default();
+            if sectors * SECTOR_SIZE >= size {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(DEVICE_RESIZE_TIMEOUT_IN_SEC), wait)
+        .await
+        .map_err(other_error!(
+            e,
+            format!("timeout waiting for {} grown to {}", dev_name, size)
+        ))
+}
+
+/// Stats of the volumes at mount_points, those failed to statfs are skipped.
+pub fn volume_stats(mount_points: &[String]) -> Vec<VolumeStats> {
+    mount_points
+        .iter()
+        .filter_map(|mp| {
+            volume_stats_of(mp)
+                .map_err(|e| warn!("skip the stats of volume {}, {}", mp, e))
+                .ok()
+        })
+        .collect()
+}
+
+fn volume_stats_of(mount_point: &str) -> Result<VolumeStats> {
+    let st = statvfs(mount_point)
+        .map_err(other_error!(e, format!("failed to statfs {}", mount_point)))?;
+    let fragment_size = st.fragment_size() as u64;
+    let blocks = st.blocks() as u64;
+    let blocks_free = st.blocks_free() as u64;
+    let mut stats = VolumeStats::new();
+    stats.mount_point = mount_point.to_string();
+    stats.capacity_bytes = blocks * fragment_size;
+    stats.used_bytes = (blocks - blocks_free) * fragment_size;
+    stats.available_bytes = st.blocks_available() as u64 * fragment_size;
+    stats.inodes = st.files() as u64;
+    stats.inodes_free = st.files_free() as u64;
+    stats.inodes_used = stats.inodes - stats.inodes_free;
+    Ok(stats)
+}
+
+// ext filesystems are grown by the device, and xfs is grown by the mount point
+fn resize_command(storage: &Storage) -> Option<(&'static str, &str)> {
+    match &*storage.fstype {
+        "ext2" | "ext3" | "ext4" => Some(("resize2fs", &storage.source)),
+        "xfs" => Some(("xfs_growfs", &storage.mount_point)),
+        _ => None,
     }
 }
 
@@ -159,6 +395,94 @@ async fn mount_storage(storage: &Storage) -> Result<()> {
     Ok(())
 }
 
+// local storage is an empty dir in the guest bind mounted to the mount point, the dir is created
+// in the source if it is an absolute path, the "mode=" option sets the permission of the dir.
+async fn handle_local_storage(storage: &mut Storage) -> Result<()> {
+    let dir = local_storage_dir(storage)?;
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(other_error!(e, format!("failed to create dir {}", dir)))?;
+
+    let mut mode = LOCAL_STORAGE_DEFAULT_MODE;
+    let mut options = vec!["bind".to_string()];
+    for o in &storage.options {
+        match o.strip_prefix("mode=") {
+            Some(m) => {
+                mode = u32::from_str_radix(m, 8).map_err(other_error!(
+                    e,
+                    format!("invalid mode of local storage {}", m)
+                ))?;
+            }
+            None => options.push(o.to_string()),
+        }
+    }
+    tokio::fs::set_permissions(&dir, Permissions::from_mode(mode))
+        .await
+        .map_err(other_error!(e, format!("failed to set mode of {}", dir)))?;
+
+    storage.source = dir;
+    storage.fstype = "bind".to_string();
+    storage.options = options;
+    mount_storage(storage).await
+}
+
+// both the id and the source are given by the host, the dir must not escape the local storage dir
+fn local_storage_dir(storage: &Storage) -> Result<String> {
+    if storage.id.is_empty() || storage.id.contains('/') || storage.id == "." || storage.id == ".."
+    {
+        return Err(other!("invalid id {} of local storage", storage.id));
+    }
+    let base_dir = if storage.source.is_empty() {
+        LOCAL_STORAGE_DIR
+    } else if is_in_local_storage_dir(Path::new(&storage.source)) {
+        storage.source.as_str()
+    } else {
+        return Err(other!(
+            "source {} of local storage is not in {}",
+            storage.source,
+            LOCAL_STORAGE_DIR
+        ));
+    };
+    Ok(format!("{}/{}", base_dir, storage.id))
+}
+
+fn is_in_local_storage_dir(path: &Path) -> bool {
+    path.starts_with(LOCAL_STORAGE_DIR)
+        && path
+            .components()
+            .all(|c| !matches!(c, Component::ParentDir | Component::CurDir))
+}
+
+fn append_default_options(options: &mut Vec<String>, defaults: &[&str]) {
+    for d in defaults {
+        let key = d.split('=').next().unwrap_or_default();
+        if !options.iter().any(|o| o.split('=').next() == Some(key)) {
+            options.push(d.to_string());
+        }
+    }
+}
+
+// the upper and work dirs of the overlay are on the upper layer storage mounted before it
+async fn handle_overlay_storage(storage: &Storage) -> Result<()> {
+    for dir in overlay_rw_dirs(storage) {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(other_error!(e, format!("failed to create dir {}", dir)))?;
+    }
+    mount_storage(storage).await
+}
+
+fn overlay_rw_dirs(storage: &Storage) -> Vec<&str> {
+    storage
+        .options
+        .iter()
+        .filter_map(|o| {
+            o.strip_prefix("upperdir=")
+                .or_else(|| o.strip_prefix("workdir="))
+        })
+        .collect()
+}
+
 async fn unmount_storage(storage: &Storage) -> Result<()> {
     let src_path = Path::new(&storage.source);
     unmount(&storage.mount_point, 0).map_err(other_error!(e, ""))?;
@@ -171,6 +495,21 @@ async fn unmount_storage(storage: &Storage) -> Result<()> {
             .await
             .map_err(other_error!(e, ""))?
     }
+    if storage.driver == DRIVERLOCALTYPE {
+        if !is_in_local_storage_dir(Path::new(&storage.source)) {
+            return Err(other!(
+                "dir {} of local storage is not in {}",
+                storage.source,
+                LOCAL_STORAGE_DIR
+            ));
+        }
+        tokio::fs::remove_dir_all(&storage.source)
+            .await
+            .map_err(other_error!(
+                e,
+                format!("failed to remove dir {}", storage.source)
+            ))?;
+    }
     Ok(())
 }
 
@@ -217,3 +556,125 @@ impl DeviceMatcher for TypeAddrDeviceMatcher {
         false
     }
 }
+
+// the address of the nic is read from sysfs, as it is not in the uevent
+pub struct HwAddrDeviceMatcher {
+    hw_addr: String,
+}
+
+impl HwAddrDeviceMatcher {
+    pub fn new(hw_addr: String) -> Self {
+        Self { hw_addr }
+    }
+}
+
+impl DeviceMatcher for HwAddrDeviceMatcher {
+    fn is_match(&self, device: &Device) -> bool {
+        if device.r#type != DeviceType::Net {
+            return false;
+        }
+        let path = format!("{}/{}/address", SYSFS_NET_PATH, device.addr);
+        std::fs::read_to_string(path)
+            .map(|a| a.trim().eq_ignore_ascii_case(&self.hw_addr))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm_common::storage::Storage;
+
+    use super::{
+        append_default_options, local_storage_dir, resize_command, volume_stats, volume_stats_of,
+        DEFAULT_9P_OPTIONS,
+    };
+
+    #[test]
+    fn test_local_storage_dir() {
+        let storage = |id: &str, source: &str| Storage {
+            host_source: "".to_string(),
+            r#type: "".to_string(),
+            id: id.to_string(),
+            device_id: None,
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: source.to_string(),
+            driver: "local".to_string(),
+            driver_options: vec![],
+            fstype: "".to_string(),
+            options: vec![],
+            mount_point: "/run/kuasar/storage/containers/storage1".to_string(),
+            host_options: vec![],
+        };
+        assert_eq!(
+            local_storage_dir(&storage("s1", "")).unwrap(),
+            "/run/kuasar/local/s1"
+        );
+        assert_eq!(
+            local_storage_dir(&storage("s1", "/run/kuasar/local/disk1")).unwrap(),
+            "/run/kuasar/local/disk1/s1"
+        );
+        assert!(local_storage_dir(&storage("../..", "")).is_err());
+        assert!(local_storage_dir(&storage("a/b", "")).is_err());
+        assert!(local_storage_dir(&storage("s1", "/etc")).is_err());
+        assert!(local_storage_dir(&storage("s1", "/run/kuasar/local/../../../etc")).is_err());
+    }
+
+    #[test]
+    fn test_append_default_options() {
+        let mut options = vec!["version=9p2000.u".to_string(), "ro".to_string()];
+        append_default_options(&mut options, DEFAULT_9P_OPTIONS);
+        assert_eq!(
+            options,
+            vec![
+                "version=9p2000.u".to_string(),
+                "ro".to_string(),
+                "trans=virtio".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resize_command() {
+        let storage = |fstype: &str| Storage {
+            host_source: "/dev/sdb".to_string(),
+            r#type: "bind".to_string(),
+            id: "storage1".to_string(),
+            device_id: Some("blk1".to_string()),
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: "/dev/vdb".to_string(),
+            driver: "blk".to_string(),
+            driver_options: vec![],
+            fstype: fstype.to_string(),
+            options: vec![],
+            mount_point: "/run/kuasar/storage/containers/storage1".to_string(),
+            host_options: vec![],
+        };
+        assert_eq!(
+            resize_command(&storage("ext4")),
+            Some(("resize2fs", "/dev/vdb"))
+        );
+        assert_eq!(
+            resize_command(&storage("xfs")),
+            Some(("xfs_growfs", "/run/kuasar/storage/containers/storage1"))
+        );
+        assert_eq!(resize_command(&storage("erofs")), None);
+    }
+
+    #[test]
+    fn test_volume_stats_of() {
+        let stats = volume_stats_of("/").unwrap();
+        assert_eq!(stats.mount_point, "/");
+        assert!(stats.capacity_bytes > 0);
+        assert!(stats.used_bytes <= stats.capacity_bytes);
+        assert!(stats.available_bytes <= stats.capacity_bytes);
+        assert_eq!(stats.inodes, stats.inodes_used + stats.inodes_free);
+
+        assert!(volume_stats_of("/path/not/exist").is_err());
+
+        let stats = volume_stats(&["/".to_string(), "/path/not/exist".to_string()]);
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].mount_point, "/");
+    }
+}
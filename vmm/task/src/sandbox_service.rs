@@ -28,17 +28,38 @@ use vmm_common::{
     api::{empty::Empty, sandbox::*},
 };
 
-use crate::netlink::Handle;
+use crate::{
+    kernel::{load_modules, set_sysctls},
+    logger,
+    netlink::Handle,
+    policy::Policy,
+    sandbox::{volume_stats, wait_net_device, SandboxResources},
+    stats::sandbox_stats,
+    task::{pause_containers, resume_containers, Containers},
+};
 
 pub struct SandboxService {
     pub handle: Arc<Mutex<Handle>>,
+    pub sandbox: Arc<Mutex<SandboxResources>>,
+    pub policy: Arc<Policy>,
+    containers: Containers,
+    // ids of the containers frozen by pause_containers
+    paused: Mutex<Vec<String>>,
 }
 
 impl SandboxService {
-    pub fn new() -> Result<Self> {
+    pub fn new(
+        sandbox: Arc<Mutex<SandboxResources>>,
+        policy: Arc<Policy>,
+        containers: Containers,
+    ) -> Result<Self> {
         let handle = Handle::new()?;
         Ok(Self {
             handle: Arc::new(Mutex::new(handle)),
+            sandbox,
+            policy,
+            containers,
+            paused: Mutex::new(vec![]),
         })
     }
 
@@ -54,6 +75,12 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         _ctx: &TtrpcContext,
         req: UpdateInterfacesRequest,
     ) -> TtrpcResult<Empty> {
+        self.policy.check_network_update("update interfaces")?;
+        // the nics may be hot plugged by the host just before the update
+        let device_monitor = self.sandbox.lock().await.device_monitor();
+        for intf in req.interfaces.iter().filter(|i| !i.hwAddr.is_empty()) {
+            wait_net_device(&device_monitor, &intf.hwAddr).await?;
+        }
         self.handle
             .lock()
             .await
@@ -67,6 +94,7 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         _ctx: &TtrpcContext,
         req: UpdateRoutesRequest,
     ) -> TtrpcResult<Empty> {
+        self.policy.check_network_update("update routes")?;
         self.handle.lock().await.update_routes(req.routes).await?;
         Ok(Empty::new())
     }
@@ -112,4 +140,83 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         }
         Ok(resp)
     }
+
+    async fn resize_volume(
+        &self,
+        _ctx: &TtrpcContext,
+        req: ResizeVolumeRequest,
+    ) -> TtrpcResult<Empty> {
+        self.sandbox
+            .lock()
+            .await
+            .resize_storage(&req.mount_point, req.size)
+            .await?;
+        Ok(Empty::new())
+    }
+
+    async fn get_volume_stats(
+        &self,
+        _ctx: &TtrpcContext,
+        req: GetVolumeStatsRequest,
+    ) -> TtrpcResult<GetVolumeStatsResponse> {
+        // statfs may block on a broken filesystem, so it is not called with the lock held
+        let mount_points = self
+            .sandbox
+            .lock()
+            .await
+            .volume_mount_points(&req.mount_points);
+        let mut resp = GetVolumeStatsResponse::new();
+        resp.stats = volume_stats(&mount_points);
+        Ok(resp)
+    }
+
+    async fn get_sandbox_stats(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: GetSandboxStatsRequest,
+    ) -> TtrpcResult<SandboxStats> {
+        Ok(sandbox_stats().await?)
+    }
+
+    async fn set_log_level(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SetLogLevelRequest,
+    ) -> TtrpcResult<Empty> {
+        logger::set_level(&req.level)?;
+        Ok(Empty::new())
+    }
+
+    async fn pause_containers(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: PauseContainersRequest,
+    ) -> TtrpcResult<Empty> {
+        let ids = pause_containers(&self.containers).await?;
+        self.paused.lock().await.extend(ids);
+        Ok(Empty::new())
+    }
+
+    async fn resume_containers(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: ResumeContainersRequest,
+    ) -> TtrpcResult<Empty> {
+        let ids = std::mem::take(&mut *self.paused.lock().await);
+        resume_containers(&self.containers, &ids).await;
+        Ok(Empty::new())
+    }
+
+    async fn setup_kernel(
+        &self,
+        _ctx: &TtrpcContext,
+        req: SetupKernelRequest,
+    ) -> TtrpcResult<Empty> {
+        let sysctls: Vec<&str> = req.sysctls.keys().map(|k| k.as_str()).collect();
+        let modules: Vec<&str> = req.kernel_modules.iter().map(|m| m.name.as_str()).collect();
+        self.policy.check_setup_kernel(&sysctls, &modules)?;
+        load_modules(&req.kernel_modules).await?;
+        set_sysctls(&req.sysctls).await?;
+        Ok(Empty::new())
+    }
 }
@@ -0,0 +1,218 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use containerd_shim::{other_error, protos::protobuf::MessageField, Error, Result};
+use log::debug;
+use vmm_common::api::sandbox::{CpuStats, MemoryStats, PressureStats, SandboxStats};
+
+const PROC_MEMINFO: &str = "/proc/meminfo";
+const PROC_STAT: &str = "/proc/stat";
+const PROC_LOADAVG: &str = "/proc/loadavg";
+const PROC_PRESSURE_DIR: &str = "/proc/pressure";
+const PRESSURE_RESOURCES: [&str; 3] = ["cpu", "memory", "io"];
+
+/// Stats of the whole guest from /proc, pressures are skipped if the kernel has no PSI.
+pub async fn sandbox_stats() -> Result<SandboxStats> {
+    let mut stats = SandboxStats::new();
+    stats.memory = MessageField::some(parse_meminfo(&read_proc(PROC_MEMINFO).await?));
+    stats.cpus = parse_stat(&read_proc(PROC_STAT).await?);
+    let (load1, load5, load15) = parse_loadavg(&read_proc(PROC_LOADAVG).await?);
+    stats.load1 = load1;
+    stats.load5 = load5;
+    stats.load15 = load15;
+    for resource in PRESSURE_RESOURCES {
+        let path = format!("{}/{}", PROC_PRESSURE_DIR, resource);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => stats.pressures.push(parse_pressure(resource, &content)),
+            Err(e) => debug!("failed to read {}: {}", path, e),
+        }
+    }
+    Ok(stats)
+}
+
+async fn read_proc(path: &str) -> Result<String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(other_error!(e, format!("failed to read {}", path)))
+}
+
+// lines like "MemTotal:        2014240 kB"
+fn parse_meminfo(content: &str) -> MemoryStats {
+    let mut mem = MemoryStats::new();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (key, value) = match (fields.next(), fields.next()) {
+            (Some(k), Some(v)) => (
+                k.trim_end_matches(':'),
+                v.parse::<u64>().unwrap_or_default(),
+            ),
+            _ => continue,
+        };
+        let bytes = match fields.next() {
+            Some("kB") => value * 1024,
+            _ => value,
+        };
+        match key {
+            "MemTotal" => mem.total = bytes,
+            "MemFree" => mem.free = bytes,
+            "MemAvailable" => mem.available = bytes,
+            "Buffers" => mem.buffers = bytes,
+            "Cached" => mem.cached = bytes,
+            "SwapTotal" => mem.swap_total = bytes,
+            "SwapFree" => mem.swap_free = bytes,
+            _ => {}
+        }
+    }
+    mem
+}
+
+// lines like "cpu0 4705 356 584 3699 23 23 0 0 0 0", the first one is the sum of all cpus
+fn parse_stat(content: &str) -> Vec<CpuStats> {
+    content
+        .lines()
+        .filter(|l| l.starts_with("cpu"))
+        .map(|l| {
+            let mut fields = l.split_whitespace();
+            let mut cpu = CpuStats::new();
+            cpu.cpu = fields.next().unwrap_or_default().to_string();
+            let values: Vec<u64> = fields.map(|x| x.parse().unwrap_or_default()).collect();
+            let value = |i: usize| values.get(i).copied().unwrap_or_default();
+            cpu.user = value(0);
+            cpu.nice = value(1);
+            cpu.system = value(2);
+            cpu.idle = value(3);
+            cpu.iowait = value(4);
+            cpu.irq = value(5);
+            cpu.softirq = value(6);
+            cpu.steal = value(7);
+            cpu
+        })
+        .collect()
+}
+
+// "0.20 0.18 0.12 1/80 11206"
+fn parse_loadavg(content: &str) -> (f64, f64, f64) {
+    let loads: Vec<f64> = content
+        .split_whitespace()
+        .take(3)
+        .map(|x| x.parse().unwrap_or_default())
+        .collect();
+    let load = |i: usize| loads.get(i).copied().unwrap_or_default();
+    (load(0), load(1), load(2))
+}
+
+// some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+// full avg10=0.00 avg60=0.00 avg300=0.00 total=0
+fn parse_pressure(resource: &str, content: &str) -> PressureStats {
+    let mut pressure = PressureStats::new();
+    pressure.resource = resource.to_string();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next().unwrap_or_default();
+        for field in fields {
+            let (key, value) = match field.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match (kind, key) {
+                ("some", "avg10") => pressure.some_avg10 = value.parse().unwrap_or_default(),
+                ("some", "avg60") => pressure.some_avg60 = value.parse().unwrap_or_default(),
+                ("some", "avg300") => pressure.some_avg300 = value.parse().unwrap_or_default(),
+                ("some", "total") => pressure.some_total = value.parse().unwrap_or_default(),
+                ("full", "avg10") => pressure.full_avg10 = value.parse().unwrap_or_default(),
+                ("full", "avg60") => pressure.full_avg60 = value.parse().unwrap_or_default(),
+                ("full", "avg300") => pressure.full_avg300 = value.parse().unwrap_or_default(),
+                ("full", "total") => pressure.full_total = value.parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+    }
+    pressure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_loadavg, parse_meminfo, parse_pressure, parse_stat};
+
+    #[test]
+    fn test_parse_meminfo() {
+        let content = "\
+MemTotal:        2014240 kB
+MemFree:          102400 kB
+MemAvailable:    1048576 kB
+Buffers:            1024 kB
+Cached:           524288 kB
+SwapCached:            0 kB
+SwapTotal:             0 kB
+SwapFree:              0 kB
+HugePages_Total:       0
+";
+        let mem = parse_meminfo(content);
+        assert_eq!(mem.total, 2014240 * 1024);
+        assert_eq!(mem.free, 102400 * 1024);
+        assert_eq!(mem.available, 1048576 * 1024);
+        assert_eq!(mem.buffers, 1024 * 1024);
+        assert_eq!(mem.cached, 524288 * 1024);
+        assert_eq!(mem.swap_total, 0);
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let content = "\
+cpu  9410 712 1168 7398 46 0 46 20 0 0
+cpu0 4705 356 584 3699 23 0 23 10 0 0
+cpu1 4705 356 584 3699 23 0 23 10 0 0
+intr 1234 0 0
+ctxt 5678
+";
+        let cpus = parse_stat(content);
+        assert_eq!(cpus.len(), 3);
+        assert_eq!(cpus[0].cpu, "cpu");
+        assert_eq!(cpus[0].user, 9410);
+        assert_eq!(cpus[0].steal, 20);
+        assert_eq!(cpus[2].cpu, "cpu1");
+        assert_eq!(cpus[2].idle, 3699);
+        assert_eq!(cpus[2].softirq, 23);
+    }
+
+    #[test]
+    fn test_parse_loadavg() {
+        assert_eq!(
+            parse_loadavg("0.20 0.18 0.12 1/80 11206\n"),
+            (0.20, 0.18, 0.12)
+        );
+        assert_eq!(parse_loadavg(""), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_parse_pressure() {
+        let content = "\
+some avg10=1.50 avg60=0.75 avg300=0.10 total=123456
+full avg10=0.50 avg60=0.25 avg300=0.00 total=6789
+";
+        let p = parse_pressure("memory", content);
+        assert_eq!(p.resource, "memory");
+        assert_eq!(p.some_avg10, 1.5);
+        assert_eq!(p.some_avg60, 0.75);
+        assert_eq!(p.some_total, 123456);
+        assert_eq!(p.full_avg10, 0.5);
+        assert_eq!(p.full_total, 6789);
+
+        let p = parse_pressure("cpu", "some avg10=2.00 avg60=1.00 avg300=0.50 total=42\n");
+        assert_eq!(p.some_avg300, 0.5);
+        assert_eq!(p.full_total, 0);
+    }
+}
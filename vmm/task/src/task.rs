@@ -14,9 +14,11 @@
    limitations under the License.
 */
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
+use async_trait::async_trait;
 use containerd_shim::{
+    api::*,
     asynchronous::{
         container::Container,
         monitor::{monitor_subscribe, monitor_unsubscribe, Subscription},
@@ -25,21 +27,85 @@ use containerd_shim::{
         util::read_spec,
     },
     monitor::{Subject, Topic},
+    protos::{
+        protobuf::{CodedInputStream, Message},
+        shim::{oci::CheckpointOptions, shim_ttrpc_async::Task},
+    },
+    Error, Result, TtrpcContext, TtrpcResult,
 };
-use log::{debug, error};
+use log::{debug, error, warn};
 use oci_spec::runtime::{LinuxNamespaceType, Spec};
 use tokio::sync::{mpsc::channel, Mutex};
 
 use crate::{
     container::{KuasarContainer, KuasarFactory},
+    events::forward_events,
+    oom::watch_oom,
+    policy::Policy,
     sandbox::SandboxResources,
 };
 
-pub(crate) async fn create_task_service() -> TaskService<KuasarFactory, KuasarContainer> {
-    let (tx, mut rx) = channel(128);
-    let sandbox = Arc::new(Mutex::new(SandboxResources::new().await));
+pub(crate) type Containers = Arc<Mutex<HashMap<String, KuasarContainer>>>;
+
+/// Task service of the guest, which adds the checkpoint support to the task service of the shim.
+pub(crate) struct KuasarTask {
+    inner: TaskService<KuasarFactory, KuasarContainer>,
+}
+
+impl KuasarTask {
+    pub(crate) fn containers(&self) -> Containers {
+        self.inner.containers.clone()
+    }
+}
+
+/// Freeze all the running containers before the vm is paused, and return the ids of them,
+/// the containers frozen are thawed if any of them fails.
+pub(crate) async fn pause_containers(containers: &Containers) -> Result<Vec<String>> {
+    let mut containers = containers.lock().await;
+    let mut paused = vec![];
+    for c in containers
+        .values_mut()
+        .filter(|c| c.init.state == Status::RUNNING)
+    {
+        let lifecycle = c.init.lifecycle.clone();
+        if let Err(e) = lifecycle.pause(&mut c.init).await {
+            for p in paused {
+                resume_container(p).await;
+            }
+            return Err(e);
+        }
+        paused.push(c);
+    }
+    Ok(paused.into_iter().map(|c| c.id.to_string()).collect())
+}
+
+/// Thaw the containers frozen by `pause_containers`, the containers paused by
+/// the task api are left paused.
+pub(crate) async fn resume_containers(containers: &Containers, ids: &[String]) {
+    let mut containers = containers.lock().await;
+    for c in containers
+        .values_mut()
+        .filter(|c| ids.contains(&c.id) && c.init.state == Status::PAUSED)
+    {
+        resume_container(c).await;
+    }
+}
+
+async fn resume_container(c: &mut KuasarContainer) {
+    let lifecycle = c.init.lifecycle.clone();
+    lifecycle
+        .resume(&mut c.init)
+        .await
+        .unwrap_or_else(|e| warn!("failed to resume container {}: {}", c.id, e));
+}
+
+pub(crate) async fn create_task_service(
+    sandbox: Arc<Mutex<SandboxResources>>,
+    policy: Arc<Policy>,
+) -> KuasarTask {
+    let (tx, rx) = channel(128);
     let task = TaskService {
-        factory: KuasarFactory::new(sandbox),
+        factory: KuasarFactory::new(sandbox, policy),
         containers: Arc::new(Default::default()),
         namespace: "k8s.io".to_string(),
         exit: Arc::new(Default::default()),
@@ -49,12 +115,114 @@ pub(crate) async fn create_task_service() -> TaskService<KuasarFactory, KuasarCo
         .await
         .expect("monitor subscribe failed");
     process_exits(s, &task).await;
-    tokio::spawn(async move {
-        while let Some((_topic, e)) = rx.recv().await {
-            debug!("received event {:?}", e);
+    if let Err(e) = forward_events(rx, task.namespace.clone()).await {
+        error!("failed to forward task events: {}", e);
+    }
+    KuasarTask { inner: task }
+}
+
+#[async_trait]
+impl Task for KuasarTask {
+    async fn state(&self, ctx: &TtrpcContext, req: StateRequest) -> TtrpcResult<StateResponse> {
+        self.inner.state(ctx, req).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &TtrpcContext,
+        req: CreateTaskRequest,
+    ) -> TtrpcResult<CreateTaskResponse> {
+        self.inner.create(ctx, req).await
+    }
+
+    async fn start(&self, ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
+        let resp = self.inner.start(ctx, req.clone()).await?;
+        if req.exec_id.is_empty() {
+            if let Err(e) = watch_oom(req.id(), resp.pid, self.inner.tx.clone()).await {
+                warn!("failed to watch oom events of container {}: {}", req.id, e);
+            }
         }
-    });
-    task
+        Ok(resp)
+    }
+
+    async fn delete(&self, ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
+        self.inner.delete(ctx, req).await
+    }
+
+    async fn pids(&self, ctx: &TtrpcContext, req: PidsRequest) -> TtrpcResult<PidsResponse> {
+        self.inner.pids(ctx, req).await
+    }
+
+    async fn pause(&self, ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        self.inner.pause(ctx, req).await
+    }
+
+    async fn resume(&self, ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        self.inner.resume(ctx, req).await
+    }
+
+    async fn checkpoint(
+        &self,
+        _ctx: &TtrpcContext,
+        req: CheckpointTaskRequest,
+    ) -> TtrpcResult<Empty> {
+        let mut opts = CheckpointOptions::new();
+        if let Some(any) = req.options.as_ref() {
+            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
+            opts.merge_from(&mut input)?;
+        }
+        // runc checkpoint may take a long time, do not hold the lock of containers during it.
+        let lifecycle = self
+            .inner
+            .containers
+            .lock()
+            .await
+            .get(req.id())
+            .map(|c| c.init.lifecycle.clone())
+            .ok_or_else(|| Error::NotFoundError(format!("can not find container {}", req.id)))?;
+        lifecycle.checkpoint(req.id(), req.path(), &opts).await?;
+        Ok(Empty::new())
+    }
+
+    async fn kill(&self, ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
+        self.inner.kill(ctx, req).await
+    }
+
+    async fn exec(&self, ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
+        self.inner.exec(ctx, req).await
+    }
+
+    async fn resize_pty(&self, ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
+        self.inner.resize_pty(ctx, req).await
+    }
+
+    async fn close_io(&self, ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        self.inner.close_io(ctx, req).await
+    }
+
+    async fn update(&self, ctx: &TtrpcContext, req: UpdateTaskRequest) -> TtrpcResult<Empty> {
+        self.inner.update(ctx, req).await
+    }
+
+    async fn wait(&self, ctx: &TtrpcContext, req: WaitRequest) -> TtrpcResult<WaitResponse> {
+        self.inner.wait(ctx, req).await
+    }
+
+    async fn stats(&self, ctx: &TtrpcContext, req: StatsRequest) -> TtrpcResult<StatsResponse> {
+        self.inner.stats(ctx, req).await
+    }
+
+    async fn connect(
+        &self,
+        ctx: &TtrpcContext,
+        req: ConnectRequest,
+    ) -> TtrpcResult<ConnectResponse> {
+        self.inner.connect(ctx, req).await
+    }
+
+    async fn shutdown(&self, ctx: &TtrpcContext, req: ShutdownRequest) -> TtrpcResult<Empty> {
+        self.inner.shutdown(ctx, req).await
+    }
 }
 
 async fn process_exits(s: Subscription, task: &TaskService<KuasarFactory, KuasarContainer>) {
@@ -69,7 +237,9 @@ async fn process_exits(s: Subscription, task: &TaskService<KuasarFactory, Kuasar
                     let bundle = cont.bundle.to_string();
                     // pid belongs to container init process
                     if cont.init.pid == pid {
-                        // kill all children process if the container has a private PID namespace
+                        // kill all the processes of the container if it has no private PID namespace,
+                        // like the ones sharing the sandbox PID namespace, as they are not killed
+                        // by the kernel when the init process exits.
                         if should_kill_all_on_exit(&bundle).await {
                             cont.kill(None, 9, true).await.unwrap_or_else(|e| {
                                 error!("failed to kill init's children: {}", e)
@@ -129,7 +299,9 @@ pub fn has_shared_pid_namespace(spec: &Spec) -> bool {
 mod tests {
     use std::path::Path;
 
-    use crate::task::should_kill_all_on_exit;
+    use oci_spec::runtime::{LinuxBuilder, LinuxNamespaceBuilder, LinuxNamespaceType, SpecBuilder};
+
+    use crate::task::{has_shared_pid_namespace, should_kill_all_on_exit};
 
     #[tokio::test]
     async fn test_should_kill_all_on_exit_when_no_path() {
@@ -140,4 +312,28 @@ mod tests {
         // return true if path not exist
         assert!(should_kill_all_on_exit(path).await);
     }
+
+    #[test]
+    fn test_has_shared_pid_namespace() {
+        let spec = |path: Option<&str>| {
+            let mut ns = LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Pid);
+            if let Some(p) = path {
+                ns = ns.path(p);
+            }
+            let linux = LinuxBuilder::default()
+                .namespaces(vec![ns.build().unwrap()])
+                .build()
+                .unwrap();
+            SpecBuilder::default().linux(linux).build().unwrap()
+        };
+        // a private pid namespace is created for the container
+        assert!(!has_shared_pid_namespace(&spec(None)));
+        // the pid namespace shared by the containers of the sandbox
+        assert!(has_shared_pid_namespace(&spec(Some("/run/sandbox-ns/pid"))));
+        // the pid namespace of the guest
+        let linux = LinuxBuilder::default().namespaces(vec![]).build().unwrap();
+        assert!(has_shared_pid_namespace(
+            &SpecBuilder::default().linux(linux).build().unwrap()
+        ));
+    }
 }